@@ -23,6 +23,7 @@
             rust-analyzer
             pkg-config
             openssl
+            sqlite
             jq
             lld
             wasm-pack
@@ -0,0 +1,307 @@
+//! Timing analysis of a pipeline that has already run.
+//!
+//! The critical path and slack assume unlimited parallelism, so they show what the pipeline could
+//! take at best, while the idle time and achieved parallelism come from when the jobs actually ran.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+
+use crate::pipeline::Pipeline;
+use crate::pipeline_tree::PipelineTree;
+use crate::Result;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobAnalysis {
+    pub job_id: Uuid,
+    pub name: String,
+    /// When the job started, relative to the first job of the run
+    pub started: Option<Duration>,
+    /// Zero for jobs that haven't finished
+    pub duration: Duration,
+    /// How much the job could take longer without making the pipeline take longer
+    pub slack: Duration,
+    pub critical: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunAnalysis {
+    /// Jobs in topological order
+    pub jobs: Vec<JobAnalysis>,
+    /// Longest chain of dependent jobs, from the root to the leaf
+    pub critical_path: Vec<Uuid>,
+    pub critical_path_duration: Duration,
+    /// Time from the start of the first job to the end of the last one
+    pub wall_time: Duration,
+    /// Time spent in jobs
+    pub busy_time: Duration,
+    /// Time during the run when no job was running
+    pub idle_time: Duration,
+    /// Average number of jobs that were running at the same time
+    pub parallelism: f64,
+    /// Average number of jobs that would be running with unlimited parallelism
+    pub max_parallelism: f64,
+}
+
+impl RunAnalysis {
+    /// Fails if the pipeline's dependencies contain a cycle
+    pub fn new(pipeline: &Pipeline) -> Result<Self> {
+        let tree = PipelineTree::new(pipeline);
+        let order = tree.topological_order()?;
+
+        let durations = pipeline
+            .jobs
+            .iter()
+            .map(|job| (job.get_id(), job.get_duration().unwrap_or_default()))
+            .collect::<BTreeMap<_, _>>();
+
+        // Earliest finish of every job, when every job starts as soon as its dependencies finish
+        let mut earliest_start = BTreeMap::<Uuid, Duration>::new();
+        let mut earliest_finish = BTreeMap::<Uuid, Duration>::new();
+        for id in &order {
+            let start = tree
+                .node(*id)
+                .unwrap()
+                .dependencies
+                .iter()
+                .map(|dependency| earliest_finish[dependency])
+                .max()
+                .unwrap_or_default();
+            earliest_start.insert(*id, start);
+            earliest_finish.insert(*id, start + durations[id]);
+        }
+        let critical_path_duration = earliest_finish.values().copied().max().unwrap_or_default();
+
+        // Latest start of every job, that doesn't delay the end of the pipeline
+        let mut latest_start = BTreeMap::<Uuid, Duration>::new();
+        for id in order.iter().rev() {
+            let finish = tree
+                .node(*id)
+                .unwrap()
+                .dependants
+                .iter()
+                .map(|dependant| latest_start[dependant])
+                .min()
+                .unwrap_or(critical_path_duration);
+            latest_start.insert(*id, finish.saturating_sub(durations[id]));
+        }
+        let slack = |id: &Uuid| latest_start[id].saturating_sub(earliest_start[id]);
+
+        let mut critical_path = vec![];
+        let mut current = order
+            .iter()
+            .find(|id| earliest_finish[*id] == critical_path_duration)
+            .copied();
+        while let Some(id) = current {
+            critical_path.push(id);
+            current = tree
+                .node(id)
+                .unwrap()
+                .dependencies
+                .iter()
+                .find(|dependency| {
+                    earliest_finish[*dependency] == earliest_start[&id]
+                        && slack(dependency).is_zero()
+                })
+                .copied();
+        }
+        critical_path.reverse();
+
+        let first_start = pipeline.jobs.iter().filter_map(|job| job.started_at).min();
+        let started = |started_at: Option<Instant>| Some(started_at?.duration_since(first_start?));
+
+        let jobs = order
+            .iter()
+            .map(|id| {
+                let node = tree.node(*id).unwrap();
+                let job = pipeline
+                    .jobs
+                    .iter()
+                    .find(|job| job.get_id() == *id)
+                    .unwrap();
+                JobAnalysis {
+                    job_id: *id,
+                    name: node.name.clone(),
+                    started: started(job.started_at),
+                    duration: durations[id],
+                    slack: slack(id),
+                    critical: critical_path.contains(id),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut intervals = jobs
+            .iter()
+            .filter_map(|job| Some((job.started?, job.started? + job.duration)))
+            .collect::<Vec<_>>();
+        intervals.sort();
+
+        let wall_time = intervals
+            .iter()
+            .map(|(_, end)| *end)
+            .max()
+            .unwrap_or_default();
+        let busy_time = intervals
+            .iter()
+            .map(|(start, end)| *end - *start)
+            .sum::<Duration>();
+
+        // Merge the overlapping intervals, and count the gaps between them
+        let mut idle_time = Duration::ZERO;
+        let mut covered_until = Duration::ZERO;
+        for (start, end) in intervals {
+            idle_time += start.saturating_sub(covered_until);
+            covered_until = covered_until.max(end);
+        }
+
+        let ratio = |a: Duration, b: Duration| {
+            if b.is_zero() {
+                0.0
+            } else {
+                a.as_secs_f64() / b.as_secs_f64()
+            }
+        };
+
+        Ok(RunAnalysis {
+            jobs,
+            critical_path,
+            critical_path_duration,
+            wall_time,
+            busy_time,
+            idle_time,
+            parallelism: ratio(busy_time, wall_time),
+            max_parallelism: ratio(busy_time, critical_path_duration),
+        })
+    }
+
+    fn job(&self, job_id: Uuid) -> Option<&JobAnalysis> {
+        self.jobs.iter().find(|job| job.job_id == job_id)
+    }
+}
+
+fn seconds(duration: Duration) -> String {
+    format!("{:.3}s", duration.as_secs_f64())
+}
+
+impl Display for RunAnalysis {
+    /// Prints the summary and the jobs, starting with the ones that are most worth optimising
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let path = self
+            .critical_path
+            .iter()
+            .filter_map(|id| self.job(*id))
+            .map(|job| job.name.as_str())
+            .collect::<Vec<_>>();
+
+        writeln!(
+            f,
+            "Critical path ({}): {}",
+            seconds(self.critical_path_duration),
+            path.join(" -> ")
+        )?;
+        writeln!(
+            f,
+            "Wall time: {}, busy: {}, idle: {}",
+            seconds(self.wall_time),
+            seconds(self.busy_time),
+            seconds(self.idle_time)
+        )?;
+        writeln!(
+            f,
+            "Parallelism: {:.2}x achieved, {:.2}x possible",
+            self.parallelism, self.max_parallelism
+        )?;
+        writeln!(f)?;
+
+        let mut jobs = self.jobs.iter().collect::<Vec<_>>();
+        jobs.sort_by(|a, b| {
+            b.critical
+                .cmp(&a.critical)
+                .then(b.duration.cmp(&a.duration))
+                .then(a.slack.cmp(&b.slack))
+        });
+
+        let width = jobs
+            .iter()
+            .map(|job| job.name.len())
+            .max()
+            .unwrap_or(0)
+            .max(3);
+        writeln!(
+            f,
+            "  {:width$}  {:>10}  {:>10}  {:>10}",
+            "Job", "Start", "Duration", "Slack"
+        )?;
+        for job in jobs {
+            let marker = if job.critical { '*' } else { ' ' };
+            let started = job.started.map(seconds).unwrap_or_else(|| "-".to_string());
+            writeln!(
+                f,
+                "{marker} {:width$}  {:>10}  {:>10}  {:>10}",
+                job.name,
+                started,
+                seconds(job.duration),
+                seconds(job.slack)
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+pub fn test_run_analysis() {
+    use crate::job::{Job, JobStatus};
+    use crate::job_type::JobType;
+
+    let start = Instant::now();
+    let ran = |name: &str, started: u64, duration: u64| {
+        let mut job = Job::new(name, JobType::Noop);
+        job.started_at = Some(start + Duration::from_secs(started));
+        job.status = JobStatus::Succeeded {
+            msg: String::new(),
+            duration: Duration::from_secs(duration),
+        };
+        job
+    };
+
+    // The jobs ran one after another, with a gap before the last one
+    let top = ran("Top", 0, 1);
+    let mut left = ran("Left", 1, 3);
+    let mut right = ran("Right", 4, 1);
+    let mut bottom = ran("Bottom", 6, 1);
+    left.add_dependency(top.get_id());
+    right.add_dependency(top.get_id());
+    bottom.add_dependency(left.get_id());
+    bottom.add_dependency(right.get_id());
+
+    let ids = [&top, &left, &bottom].map(|job| job.get_id());
+    let right_id = right.get_id();
+
+    let mut pipeline = Pipeline::new();
+    pipeline.add_jobs(vec![top, left, right, bottom]);
+    let analysis = RunAnalysis::new(&pipeline).unwrap();
+
+    assert_eq!(analysis.critical_path, ids);
+    assert_eq!(analysis.critical_path_duration, Duration::from_secs(5));
+    assert_eq!(analysis.wall_time, Duration::from_secs(7));
+    assert_eq!(analysis.busy_time, Duration::from_secs(6));
+    assert_eq!(analysis.idle_time, Duration::from_secs(1));
+    assert!((analysis.parallelism - 6.0 / 7.0).abs() < 1e-9);
+    assert!((analysis.max_parallelism - 1.2).abs() < 1e-9);
+
+    let right = analysis.job(right_id).unwrap();
+    assert_eq!(right.slack, Duration::from_secs(2));
+    assert!(!right.critical);
+    assert!(analysis
+        .jobs
+        .iter()
+        .filter(|job| job.critical)
+        .all(|job| job.slack.is_zero()));
+
+    let printed = analysis.to_string();
+    assert!(printed.starts_with("Critical path (5.000s): Top -> Left -> Bottom\n"));
+    assert!(printed.contains("* Left"));
+}
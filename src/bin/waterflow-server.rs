@@ -0,0 +1,55 @@
+use std::process::ExitCode;
+
+use waterflow::remote::Coordinator;
+use waterflow::server::Server;
+
+const USAGE: &str = "Usage: waterflow-server [options]
+
+Runs the pipelines that are submitted to its REST API. Submitting and changing runs needs the
+token from the WATERFLOW_TOKEN environment variable.
+
+Options:
+  --address <host:port>       Address to listen on (default: 127.0.0.1:8080)
+  --workers <n>               How many pipelines can run at the same time (default: 1)
+  --coordinator <host:port>   Runs the jobs that select a worker on the waterflow-worker agents that
+                              connect to the address, with the same token";
+
+fn main() -> ExitCode {
+    match run(std::env::args().skip(1).collect()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), String> {
+    let mut address = "127.0.0.1:8080".to_string();
+    let token = std::env::var("WATERFLOW_TOKEN").map_err(|_| "WATERFLOW_TOKEN isn't set")?;
+    let mut server = Server::new().with_token(&token);
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .ok_or(format!("{arg} needs a value\n\n{USAGE}"))?;
+        match arg.as_str() {
+            "--address" => address = value.clone(),
+            "--coordinator" => {
+                let coordinator = Coordinator::bind(value, &token).map_err(|e| e.to_string())?;
+                println!("Waiting for workers on {}", coordinator.address());
+                server = server.with_coordinator(coordinator)
+            }
+            "--workers" => {
+                server = server.with_workers(value.parse().map_err(|_| "--workers needs a number")?)
+            }
+            _ => return Err(format!("Unknown option {arg}\n\n{USAGE}")),
+        }
+    }
+
+    let server = server.serve(&address).map_err(|e| e.to_string())?;
+    println!("Listening on http://{}", server.address());
+    server.join();
+    Ok(())
+}
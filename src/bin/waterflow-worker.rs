@@ -0,0 +1,53 @@
+use std::process::ExitCode;
+
+use waterflow::remote::Worker;
+
+const USAGE: &str = "Usage: waterflow-worker --coordinator <host:port> [options]
+
+Runs the jobs that it pulls from a waterflow coordinator, until the coordinator goes away.
+The worker registers with the coordinator's token from the WATERFLOW_TOKEN environment variable.
+
+Options:
+  --coordinator <host:port>   Address of the coordinator
+  --name <name>               Name of the worker (default: the host's name)
+  --label <label>             Label that jobs can ask for, like linux or has-docker, can be repeated";
+
+fn main() -> ExitCode {
+    match run(std::env::args().skip(1).collect()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), String> {
+    let mut coordinator = None;
+    let mut name = std::env::var("HOSTNAME").unwrap_or_else(|_| "worker".to_string());
+    let mut labels = vec![];
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .ok_or(format!("{arg} needs a value\n\n{USAGE}"))?;
+        match arg.as_str() {
+            "--coordinator" => coordinator = Some(value.clone()),
+            "--name" => name = value.clone(),
+            "--label" => labels.push(value.clone()),
+            _ => return Err(format!("Unknown option {arg}\n\n{USAGE}")),
+        }
+    }
+    let coordinator = coordinator.ok_or(USAGE)?;
+    let token = std::env::var("WATERFLOW_TOKEN").map_err(|_| "WATERFLOW_TOKEN isn't set")?;
+
+    let worker = labels
+        .iter()
+        .fold(Worker::new(&name, &token), |worker, label| {
+            worker.with_label(label)
+        });
+    let worker = worker.connect(&coordinator).map_err(|e| e.to_string())?;
+    println!("Worker {name} connected to {coordinator}");
+    worker.join().map_err(|e| e.to_string())
+}
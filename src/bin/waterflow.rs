@@ -0,0 +1,236 @@
+use std::io::IsTerminal;
+use std::process::ExitCode;
+
+use uuid::Uuid;
+use waterflow::analysis::RunAnalysis;
+use waterflow::definition::PipelineDefinition;
+use waterflow::history::{format_time, RunHistory};
+use waterflow::metrics::Metrics;
+use waterflow::pipeline::{JobRef, Pipeline};
+use waterflow::remote::Coordinator;
+use waterflow::render::{self, RenderFormat};
+use waterflow::report::{self, ReportFormat};
+use waterflow::state::JsonStateStore;
+
+const USAGE: &str = "Usage: waterflow <command> [options]
+
+Commands:
+  run <pipeline.json>                     Runs the pipeline, then prints the graph and the timing analysis
+  render <pipeline.json>                  Prints the dependency graph
+  history <state dir> [list]              Lists the last runs, newest first
+  history <state dir> show <run id>       Prints the jobs of a run
+  history <state dir> diff <run> <run>    Compares two runs
+  history <state dir> trend <job>         Prints how long the job took over the last runs
+
+Options:
+  --format <ascii|dot|mermaid>    Format of the graph (default: ascii)
+  --target <job>                  Only runs the job and what it depends on, can be repeated
+  --tags <tag,...>                Only runs the jobs with any of the tags and what they depend on
+  --state <dir>                   Checkpoints the jobs' states to the directory
+  --resume <run id>               Continues a run from the state directory
+  --report <file.xml|file.json>   Writes a JUnit or JSON report of the run, can be repeated
+  --metrics <file.prom>           Writes the run's metrics for node exporter's textfile collector
+  --last <n>                      Number of runs that history looks at (default: 20)
+  --coordinator <host:port>       Runs the jobs that select a worker on the waterflow-worker agents that
+                                  connect to the address, with the token from WATERFLOW_TOKEN
+  --otlp <url>                    Exports the run's spans to an OTLP/HTTP collector (needs the otel feature)";
+
+#[derive(Debug, Default)]
+struct Options {
+    format: RenderFormat,
+    targets: Vec<String>,
+    tags: Vec<String>,
+    state: Option<String>,
+    resume: Option<Uuid>,
+    reports: Vec<(String, ReportFormat)>,
+    metrics: Option<String>,
+    last: Option<usize>,
+    coordinator: Option<String>,
+    #[cfg(feature = "otel")]
+    otlp: Option<String>,
+}
+
+fn main() -> ExitCode {
+    match run(std::env::args().skip(1).collect()) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Vec<String>) -> Result<ExitCode, String> {
+    let mut arguments = vec![];
+    let mut options = Options::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            arguments.push(arg.as_str());
+            continue;
+        }
+
+        let value = args.next().ok_or(format!("{arg} needs a value"))?;
+        match arg.as_str() {
+            "--format" => options.format = value.parse()?,
+            "--target" => options.targets.push(value.clone()),
+            "--tags" => options
+                .tags
+                .extend(value.split(',').map(|tag| tag.trim().to_string())),
+            "--state" => options.state = Some(value.clone()),
+            "--resume" => options.resume = Some(parse_run_id(value)?),
+            "--report" => {
+                let format = ReportFormat::from_path(value)
+                    .ok_or(format!("Can't tell the format of report {value}"))?;
+                options.reports.push((value.clone(), format));
+            }
+            "--metrics" => options.metrics = Some(value.clone()),
+            "--coordinator" => options.coordinator = Some(value.clone()),
+            "--last" => options.last = Some(value.parse().map_err(|_| "--last needs a number")?),
+            #[cfg(feature = "otel")]
+            "--otlp" => options.otlp = Some(value.clone()),
+            _ => return Err(format!("Unknown option {arg}\n\n{USAGE}")),
+        }
+    }
+
+    match arguments.as_slice() {
+        ["run", path] => run_pipeline(path, options),
+        ["render", path] => {
+            let pipeline = load_pipeline(path, &options)?;
+            print!(
+                "{}",
+                render::render(&pipeline, options.format, std::io::stdout().is_terminal())
+            );
+            Ok(ExitCode::SUCCESS)
+        }
+        ["history", state, query @ ..] => {
+            history(state, query, options.last.unwrap_or(20))?;
+            Ok(ExitCode::SUCCESS)
+        }
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+fn load_pipeline(path: &str, options: &Options) -> Result<Pipeline, String> {
+    let definition = PipelineDefinition::from_file(path).map_err(|e| e.to_string())?;
+    let mut pipeline = Pipeline::new();
+    pipeline
+        .load_definition(&definition)
+        .map_err(|e| e.to_string())?;
+    if let Some(state) = &options.state {
+        pipeline.set_state_store(JsonStateStore::new(state).map_err(|e| e.to_string())?);
+    }
+    Ok(pipeline)
+}
+
+fn run_pipeline(path: &str, options: Options) -> Result<ExitCode, String> {
+    #[cfg(feature = "otel")]
+    let _telemetry = match &options.otlp {
+        Some(endpoint) => {
+            Some(waterflow::otel::init(endpoint, "waterflow").map_err(|e| e.to_string())?)
+        }
+        None => None,
+    };
+    let mut pipeline = load_pipeline(path, &options)?;
+    let metrics = Metrics::new();
+    pipeline.set_metrics(metrics.clone());
+    if let Some(address) = &options.coordinator {
+        let token = std::env::var("WATERFLOW_TOKEN").map_err(|_| "WATERFLOW_TOKEN isn't set")?;
+        let coordinator = Coordinator::bind(address, &token).map_err(|e| e.to_string())?;
+        println!("Waiting for workers on {}", coordinator.address());
+        pipeline.set_coordinator(coordinator);
+    }
+
+    let mut targets = options
+        .targets
+        .into_iter()
+        .map(JobRef::from)
+        .collect::<Vec<_>>();
+    if !options.tags.is_empty() {
+        let tagged = pipeline.tagged(&options.tags);
+        if tagged.is_empty() {
+            return Err(format!("No job is tagged {}", options.tags.join(", ")));
+        }
+        targets.extend(tagged.into_iter().map(JobRef::from));
+    }
+
+    let result = match options.resume {
+        Some(run_id) => futures_lite::future::block_on(pipeline.resume(run_id)),
+        None if targets.is_empty() => futures_lite::future::block_on(pipeline.execute()),
+        None => futures_lite::future::block_on(pipeline.execute_targets(targets)),
+    };
+    println!("Run {}", pipeline.run_id());
+    result.map_err(|e| e.to_string())?;
+
+    for (path, format) in &options.reports {
+        let report = report::report(&pipeline, *format).map_err(|e| e.to_string())?;
+        std::fs::write(path, report).map_err(|e| format!("Failed to write {path}! {e}"))?;
+    }
+    if let Some(path) = &options.metrics {
+        metrics
+            .write_textfile(path)
+            .map_err(|e| format!("Failed to write {path}! {e}"))?;
+    }
+
+    print!(
+        "{}",
+        render::render(&pipeline, options.format, std::io::stdout().is_terminal())
+    );
+    println!();
+    let analysis = RunAnalysis::new(&pipeline).map_err(|e| e.to_string())?;
+    print!("{analysis}");
+
+    let failed = pipeline
+        .get_job_statuses()
+        .iter()
+        .any(|(_, status)| status.is_failed());
+    Ok(if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+fn history(state: &str, query: &[&str], last: usize) -> Result<(), String> {
+    let history = RunHistory::new(JsonStateStore::new(state).map_err(|e| e.to_string())?);
+
+    match query {
+        [] | ["list"] => {
+            for run in history.list(last).map_err(|e| e.to_string())? {
+                let duration = run
+                    .duration()
+                    .map_or("-".to_string(), |d| format!("{:.3}s", d.as_secs_f64()));
+                println!(
+                    "{}  {}  {:9}  {duration}",
+                    run.run_id,
+                    format_time(run.started_at),
+                    run.state.as_str()
+                );
+            }
+        }
+        ["show", run_id] => {
+            let details = history
+                .show(parse_run_id(run_id)?)
+                .map_err(|e| e.to_string())?;
+            print!("{details}");
+        }
+        ["diff", before, after] => {
+            let diff = history
+                .diff(parse_run_id(before)?, parse_run_id(after)?)
+                .map_err(|e| e.to_string())?;
+            print!("{diff}");
+        }
+        ["trend", job] => {
+            let trend = history.trend(job, last).map_err(|e| e.to_string())?;
+            print!("{trend}");
+        }
+        _ => return Err(USAGE.to_string()),
+    }
+    Ok(())
+}
+
+fn parse_run_id(run_id: &str) -> Result<Uuid, String> {
+    run_id
+        .parse()
+        .map_err(|e| format!("Invalid run id {run_id}! {e}"))
+}
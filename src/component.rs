@@ -0,0 +1,145 @@
+//! Plugins built as WebAssembly components, which implement the `waterflow:plugin` WIT world.
+//!
+//! Unlike the modules built with `#[waterflow_binding]`, these don't need the bypar protocol,
+//! so they can be written in any language that can target components.
+
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use tracing::{info, warn, Level};
+use wasmtime::component::{Component, Linker};
+use wasmtime::{Config, Engine, Store};
+
+use crate::error::Error;
+use crate::job::JobContext;
+use crate::module_source::ModuleSource;
+use crate::wasm::{
+    http_fetch, interrupt_on_cancel, log_plugin_message, tick_epochs, trapped, Capabilities,
+    HostState,
+};
+use crate::Result;
+
+wasmtime::component::bindgen!({
+    path: "wit",
+    world: "plugin",
+});
+
+use self::exports::waterflow::plugin::run::PluginError;
+use self::waterflow::plugin::host::{self, LogLevel};
+
+static ENGINE: OnceLock<Engine> = OnceLock::new();
+
+fn engine() -> &'static Engine {
+    let mut created = false;
+    let engine = ENGINE.get_or_init(|| {
+        created = true;
+        let mut config = Config::new();
+        config.wasm_component_model(true).epoch_interruption(true);
+        Engine::new(&config).expect("Failed to create the component engine")
+    });
+    if created {
+        tick_epochs(engine);
+    }
+    engine
+}
+
+impl host::Host for HostState {
+    fn log(&mut self, level: LogLevel, message: String) {
+        let level = match level {
+            LogLevel::Error => Level::ERROR,
+            LogLevel::Warn => Level::WARN,
+            LogLevel::Info => Level::INFO,
+            LogLevel::Debug => Level::DEBUG,
+            LogLevel::Trace => Level::TRACE,
+        };
+        log_plugin_message(self, level, &message);
+    }
+
+    fn get_env(&mut self, key: String) -> Option<String> {
+        self.env.get(&key).cloned()
+    }
+
+    fn get_secret(&mut self, key: String) -> Option<String> {
+        self.secrets.get(&key).map(str::to_string)
+    }
+
+    fn emit_progress(&mut self, progress: f32) {
+        let job_id = self.job_id;
+        info!(%job_id, progress, "Plugin reported progress");
+    }
+
+    fn http_fetch(&mut self, url: String) -> Option<String> {
+        let job_id = self.job_id;
+        if !self.capabilities.http {
+            warn!(%job_id, "Plugin tried to fetch {url} without the http capability");
+            return None;
+        }
+
+        http_fetch(&url)
+            .inspect_err(|e| warn!(%job_id, "Plugin failed to fetch {url}: {e}"))
+            .ok()
+    }
+}
+
+/// Checks that the file is a component that implements the `waterflow:plugin` world
+pub(crate) fn validate_component(source: &ModuleSource) -> Result<()> {
+    let component = Component::new(engine(), &source.load()?)?;
+    let (mut store, linker) = new_store(&JobContext::default(), Capabilities::default())?;
+    Plugin::instantiate(&mut store, &component, &linker)?;
+    Ok(())
+}
+
+pub(crate) fn run_component(
+    function_name: &str,
+    source: &ModuleSource,
+    capabilities: Capabilities,
+    context: &JobContext,
+) -> Result<String> {
+    let started_at = Instant::now();
+    let component = Component::new(engine(), &source.load()?)?;
+    let (mut store, linker) = new_store(context, capabilities)?;
+    let plugin = Plugin::instantiate(&mut store, &component, &linker)?;
+    if let Some(metrics) = &context.metrics {
+        metrics.wasm_instantiated("component", started_at.elapsed());
+    }
+
+    match plugin
+        .waterflow_plugin_run()
+        .call_run(&mut store, function_name, &context.input)
+        .map_err(|e| trapped(e, context))?
+    {
+        Ok(output) => Ok(output),
+        Err(PluginError { message, code }) => Err(Error::WasmPlugin { message, code }),
+    }
+}
+
+fn new_store(
+    context: &JobContext,
+    capabilities: Capabilities,
+) -> Result<(Store<HostState>, Linker<HostState>)> {
+    let mut linker = Linker::new(engine());
+    Plugin::add_to_linker(&mut linker, |state: &mut HostState| state)?;
+
+    let mut store = Store::new(engine(), HostState::new(context, capabilities));
+    interrupt_on_cancel(&mut store);
+
+    Ok((store, linker))
+}
+
+#[test]
+#[ignore = "This needs to have the component_example built"]
+pub fn test_run_component() {
+    let source = ModuleSource::from("tests/component_example/pkg/component_example.wasm");
+
+    let context = JobContext {
+        input: vec!["Hello".to_string(), "World!".to_string()],
+        ..Default::default()
+    };
+
+    let output = run_component("reverse_join", &source, Capabilities::default(), &context)
+        .expect("Failed to run the component");
+    assert_eq!(output, "World!, Hello");
+
+    let error = run_component("missing", &source, Capabilities::default(), &context);
+    assert!(matches!(error, Err(Error::WasmPlugin { code: 2, .. })));
+}
@@ -0,0 +1,259 @@
+"use strict";
+
+// Same colours as the rendered graphs and the HTML report
+const COLOURS = {
+  waiting: "#d0d0d0",
+  running: "#8ec5ff",
+  failed: "#ff8e8e",
+  succeeded: "#9be39b",
+  cancelled: "#f0c27b",
+};
+const NODE_WIDTH = 170, NODE_HEIGHT = 36, COLUMN = 220, ROW = 56, MARGIN = 10;
+const SVG = "http://www.w3.org/2000/svg";
+
+let runId = null;
+let run = null;
+let graph = [];
+let jobId = null;
+let source = null;
+
+const $ = (id) => document.getElementById(id);
+
+// Changing runs needs the server's token, which is asked for once and kept in the browser
+function token() {
+  let token = localStorage.getItem("waterflow-token");
+  if (!token) {
+    token = prompt("Token of the server (WATERFLOW_TOKEN)") || "";
+    localStorage.setItem("waterflow-token", token);
+  }
+  return token;
+}
+
+async function api(method, path) {
+  const headers = method === "POST"
+    ? { "Authorization": `Bearer ${token()}`, "Content-Type": "application/json" }
+    : {};
+  const response = await fetch(path, { method, headers });
+  const body = await response.json();
+  if (!response.ok) {
+    if (response.status === 401) {
+      localStorage.removeItem("waterflow-token");
+    }
+    throw new Error(body.error);
+  }
+  return body;
+}
+
+function showError(e) {
+  $("error").textContent = e ? e.message : "";
+}
+
+function time(systemTime) {
+  return systemTime ? new Date(systemTime.secs_since_epoch * 1000).toLocaleString() : "";
+}
+
+function seconds(duration) {
+  return duration ? (duration.secs + duration.nanos / 1e9).toFixed(3) + "s" : "-";
+}
+
+function setState(element, state) {
+  element.textContent = state;
+  element.style.background = COLOURS[state];
+}
+
+async function loadRuns() {
+  try {
+    const runs = await api("GET", "/runs");
+    $("runs").replaceChildren(...runs.map((r) => {
+      const item = document.createElement("li");
+      const dot = document.createElement("span");
+      dot.className = "dot";
+      dot.style.background = COLOURS[r.state];
+      dot.title = r.state;
+      const id = document.createElement("code");
+      id.textContent = r.run_id.slice(0, 8);
+      const submitted = document.createElement("span");
+      submitted.className = "time";
+      submitted.textContent = time(r.submitted_at);
+      item.append(dot, id, submitted);
+      item.classList.toggle("selected", r.run_id === runId);
+      item.onclick = () => selectRun(r.run_id);
+      return item;
+    }));
+    showError(null);
+  } catch (e) {
+    showError(e);
+  }
+}
+
+async function selectRun(id) {
+  runId = id;
+  jobId = null;
+  try {
+    graph = await api("GET", `/runs/${id}/graph`);
+  } catch (e) {
+    return showError(e);
+  }
+  $("run").hidden = false;
+  follow();
+  loadRuns();
+}
+
+// Follows the run's events until it finishes, as the browser would reconnect to an ended stream
+function follow() {
+  if (source) {
+    source.close();
+  }
+  source = new EventSource(`/runs/${runId}/events`);
+  source.addEventListener("status", (e) => {
+    run = JSON.parse(e.data);
+    if (run.finished_at) {
+      source.close();
+    }
+    draw();
+  });
+  source.addEventListener("run", (e) => {
+    const record = JSON.parse(e.data).run;
+    Object.assign(run, {
+      state: record.state,
+      started_at: record.started_at,
+      finished_at: record.finished_at,
+    });
+    if (run.finished_at) {
+      source.close();
+      loadRuns();
+    }
+    draw();
+  });
+  source.addEventListener("job", (e) => {
+    const record = JSON.parse(e.data).job;
+    run.jobs = run.jobs.map((job) => (job.job_id === record.job_id ? record : job));
+    draw();
+  });
+  source.addEventListener("log", (e) => {
+    const event = JSON.parse(e.data);
+    const job = run.jobs.find((job) => job.job_id === event.job_id);
+    job.logs.push(event.line);
+    if (job.job_id === jobId) {
+      appendLog(event.line);
+    }
+  });
+}
+
+function draw() {
+  $("run-title").textContent = `Run ${run.run_id}`;
+  setState($("run-state"), run.state);
+  $("cancel").hidden = Boolean(run.finished_at);
+  drawGraph();
+  drawJob();
+}
+
+function drawGraph() {
+  const rows = {};
+  const positions = {};
+  for (const node of graph) {
+    const row = rows[node.depth] || 0;
+    positions[node.job_id] = [MARGIN + node.depth * COLUMN, MARGIN + row * ROW];
+    rows[node.depth] = row + 1;
+  }
+  const columns = Object.keys(rows).length;
+  const maxRows = Math.max(0, ...Object.values(rows));
+
+  const svg = document.createElementNS(SVG, "svg");
+  svg.setAttribute("width", 2 * MARGIN + Math.max(0, columns * COLUMN - (COLUMN - NODE_WIDTH)));
+  svg.setAttribute("height", 2 * MARGIN + Math.max(0, maxRows * ROW - (ROW - NODE_HEIGHT)));
+  svg.innerHTML = '<defs><marker id="arrow" viewBox="0 0 10 10" refX="10" refY="5" markerWidth="8" markerHeight="8" orient="auto"><path d="M 0 0 L 10 5 L 0 10 z" fill="#555"/></marker></defs>';
+
+  for (const node of graph) {
+    const [x2, top] = positions[node.job_id];
+    const y2 = top + NODE_HEIGHT / 2;
+    for (const dependency of node.dependencies) {
+      const [left, y] = positions[dependency];
+      const [x1, y1] = [left + NODE_WIDTH, y + NODE_HEIGHT / 2];
+      const middle = (x1 + x2) / 2;
+      const path = document.createElementNS(SVG, "path");
+      path.setAttribute("d", `M ${x1} ${y1} C ${middle} ${y1}, ${middle} ${y2}, ${x2} ${y2}`);
+      path.setAttribute("fill", "none");
+      path.setAttribute("stroke", "#555");
+      path.setAttribute("marker-end", "url(#arrow)");
+      svg.append(path);
+    }
+  }
+
+  for (const node of graph) {
+    const [x, y] = positions[node.job_id];
+    const job = run.jobs.find((job) => job.job_id === node.job_id);
+    const group = document.createElementNS(SVG, "g");
+    group.classList.toggle("selected", node.job_id === jobId);
+    group.onclick = () => selectJob(node.job_id);
+
+    const title = document.createElementNS(SVG, "title");
+    title.textContent = `${node.name} (${node.job_type}, ${job.state})`;
+    const rect = document.createElementNS(SVG, "rect");
+    for (const [name, value] of Object.entries({ x, y, width: NODE_WIDTH, height: NODE_HEIGHT, rx: 6, fill: COLOURS[job.state], stroke: "#555" })) {
+      rect.setAttribute(name, value);
+    }
+    const text = document.createElementNS(SVG, "text");
+    text.setAttribute("x", x + 10);
+    text.setAttribute("y", y + 23);
+    text.textContent = node.name.length > 22 ? node.name.slice(0, 21) + "…" : node.name;
+    group.append(title, rect, text);
+    svg.append(group);
+  }
+
+  $("graph").replaceChildren(svg);
+}
+
+function selectJob(id) {
+  jobId = id;
+  $("logs").textContent = "";
+  for (const line of run.jobs.find((job) => job.job_id === id).logs) {
+    appendLog(line);
+  }
+  draw();
+}
+
+function drawJob() {
+  const job = run.jobs.find((job) => job.job_id === jobId);
+  $("job").hidden = !job;
+  if (!job) {
+    return;
+  }
+  $("job-title").textContent = job.name;
+  setState($("job-state"), job.state);
+  $("job-details").textContent = `Duration ${seconds(job.duration)}, attempts ${job.attempts}`;
+  $("job-message").textContent = job.state === "waiting" ? "" : job.message;
+  const finished = Boolean(run.finished_at);
+  $("retry").hidden = !finished || job.state !== "failed";
+  $("rerun").hidden = !finished;
+  if (job.logs.length === 0) {
+    $("logs").textContent = "";
+  }
+}
+
+function appendLog(line) {
+  const logs = $("logs");
+  const following = logs.scrollTop + logs.clientHeight >= logs.scrollHeight - 4;
+  logs.append(line + "\n");
+  if (following) {
+    logs.scrollTop = logs.scrollHeight;
+  }
+}
+
+async function post(path) {
+  try {
+    run = await api("POST", path);
+    showError(null);
+    follow();
+    loadRuns();
+  } catch (e) {
+    showError(e);
+  }
+}
+
+$("cancel").onclick = () => post(`/runs/${runId}/cancel`);
+$("retry").onclick = () => post(`/runs/${runId}/jobs/${jobId}/retry`);
+$("rerun").onclick = () => post(`/runs/${runId}/jobs/${jobId}/rerun`);
+
+loadRuns();
+setInterval(loadRuns, 2000);
@@ -0,0 +1,315 @@
+//! Declarative pipeline format, which is read from and written to JSON.
+//!
+//! ```json
+//! {
+//!     "jobs": [
+//!         { "name": "Hello", "type": "bash", "config": { "command": "echo -n Hello" } },
+//!         { "name": "Deploy", "type": "kubectl", "depends_on": ["Hello"], "config": { "file": "app.yaml" } },
+//!         { "name": "Build", "type": "bash", "config": { "command": "make" }, "tags": ["build"], "selector": { "worker": ["linux"] } }
+//!     ]
+//! }
+//! ```
+//!
+//! Types other than the built-in ones are looked up in the pipeline's
+//! [`ExecutorRegistry`](crate::executor::ExecutorRegistry).
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::executor::{ExecutorRegistry, JobConfig};
+use crate::job::{Job, Selector};
+use crate::job_type::JobType;
+use crate::Result;
+#[cfg(feature = "wasm")]
+use crate::{
+    module_source::{ModuleSource, PluginStore},
+    wasm::Capabilities,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct PipelineDefinition {
+    pub jobs: Vec<JobDefinition>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct JobDefinition {
+    /// Name of the job, which other jobs use to depend on it
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<String>,
+    /// Inputs that the job gets before the outputs of its dependencies
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub input: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Options of the job type
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub config: JobConfig,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// `"local"`, `{ "executor": <name> }` or `{ "worker": [<label>, ...] }`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selector: Option<Selector>,
+}
+
+impl PipelineDefinition {
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+impl JobDefinition {
+    /// Creates the job, without its dependencies
+    pub(crate) fn to_job(&self, executors: &ExecutorRegistry) -> Result<Job> {
+        let mut job = Job::new(&self.name, self.job_type(executors)?);
+        job.fixed_input = self.input.clone();
+        job.env = self.env.clone();
+        job.tags = self.tags.clone();
+        job.selector = self.selector.clone();
+        Ok(job)
+    }
+
+    /// Describes the job, with its dependencies already resolved to names
+    pub(crate) fn from_job(job: &Job, depends_on: Vec<String>) -> Result<Self> {
+        let (kind, config) = job_type_config(job)?;
+        Ok(JobDefinition {
+            name: job.name.clone(),
+            kind,
+            depends_on,
+            input: job.fixed_input.clone(),
+            env: job.env.clone(),
+            config,
+            tags: job.tags.clone(),
+            selector: job.selector.clone(),
+        })
+    }
+
+    fn job_type(&self, executors: &ExecutorRegistry) -> Result<JobType> {
+        match self.kind.as_str() {
+            "noop" => Ok(JobType::Noop),
+            "bash" => Ok(JobType::new_bash(self.required("command")?)),
+            #[cfg(feature = "wasm")]
+            "wasm" => Ok(JobType::new_wasm_with_capabilities(
+                self.required("function")?,
+                self.module_source()?,
+                Capabilities {
+                    http: self.config.get("http").is_some_and(|http| http == "true"),
+                },
+            )),
+            #[cfg(feature = "wasm")]
+            "component" => Ok(JobType::new_component(
+                self.required("function")?,
+                self.module_source()?,
+            )),
+            #[cfg(feature = "web")]
+            "web_request" => {
+                use crate::job_type::WebRequestType;
+
+                let req_type = match self.config.get("method").map(String::as_str) {
+                    None | Some("GET") => WebRequestType::Get,
+                    Some("POST") => WebRequestType::Post,
+                    Some(method) => return Err(self.error(&format!("unsupported method {method}"))),
+                };
+                Ok(JobType::new_web_request(self.required("url")?, req_type))
+            }
+            kind => match executors.get(kind) {
+                Some(executor) => Ok(JobType::new_custom(executor, self.config.clone())),
+                None => Err(Error::UnknownJobKind {
+                    kind: kind.to_string(),
+                }),
+            },
+        }
+    }
+
+    #[cfg(feature = "wasm")]
+    fn module_source(&self) -> Result<ModuleSource> {
+        let get = |key| self.config.get(key).map(String::as_str);
+        match (get("module"), get("url"), get("store"), get("sha256")) {
+            (Some(path), None, None, None) => Ok(path.into()),
+            #[cfg(feature = "web")]
+            (None, Some(url), None, Some(sha256)) => Ok(ModuleSource::from_url(url, sha256)),
+            (None, None, Some(store), Some(sha256)) => {
+                Ok(ModuleSource::from_store(&PluginStore::new(store), sha256))
+            }
+            _ => Err(self.error("expected `module`, `url` and `sha256` or `store` and `sha256`")),
+        }
+    }
+
+    fn required(&self, key: &str) -> Result<&str> {
+        self.config
+            .get(key)
+            .map(String::as_str)
+            .ok_or_else(|| self.error(&format!("missing `{key}`")))
+    }
+
+    fn error(&self, message: &str) -> Error {
+        Error::Definition {
+            message: format!("Job {} of type {}: {message}", self.name, self.kind),
+        }
+    }
+}
+
+/// Type and config of the job, as they're written in the definition
+fn job_type_config(job: &Job) -> Result<(String, JobConfig)> {
+    let not_serializable = || Error::Definition {
+        message: format!("Job {} can't be written to a definition", job.name),
+    };
+    let config = |entries: &[(&str, &str)]| {
+        entries
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<JobConfig>()
+    };
+
+    match &job.job_type {
+        JobType::Noop => Ok(("noop".to_string(), JobConfig::new())),
+        JobType::Bash { command } => Ok(("bash".to_string(), config(&[("command", command)]))),
+        #[cfg(feature = "wasm")]
+        JobType::Wasm {
+            function_name,
+            source,
+            capabilities,
+        } => {
+            let mut config = config(&[("function", function_name)]);
+            config.extend(module_source_config(source).ok_or_else(not_serializable)?);
+            if capabilities.http {
+                config.insert("http".to_string(), "true".to_string());
+            }
+            Ok(("wasm".to_string(), config))
+        }
+        #[cfg(feature = "wasm")]
+        JobType::Component {
+            function_name,
+            source,
+            ..
+        } => {
+            let mut config = config(&[("function", function_name)]);
+            config.extend(module_source_config(source).ok_or_else(not_serializable)?);
+            Ok(("component".to_string(), config))
+        }
+        #[cfg(feature = "web")]
+        JobType::WebRequest { url, req_type } => {
+            let method = format!("{req_type:?}").to_uppercase();
+            Ok((
+                "web_request".to_string(),
+                config(&[("url", url), ("method", &method)]),
+            ))
+        }
+        JobType::Native(_) => Err(not_serializable()),
+        JobType::Custom(custom) => Ok((
+            custom.executor.kind().to_string(),
+            custom.executor.serialize(&custom.config)?,
+        )),
+    }
+}
+
+#[cfg(feature = "wasm")]
+fn module_source_config(source: &ModuleSource) -> Option<JobConfig> {
+    let entries = match source {
+        ModuleSource::Bytes(_) => return None,
+        ModuleSource::Path(path) => vec![("module", path.to_str()?.to_string())],
+        ModuleSource::Store { store, sha256 } => vec![
+            ("store", store.root().to_str()?.to_string()),
+            ("sha256", sha256.clone()),
+        ],
+        #[cfg(feature = "web")]
+        ModuleSource::Url { url, sha256 } => {
+            vec![("url", url.clone()), ("sha256", sha256.clone())]
+        }
+    };
+
+    Some(
+        entries
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect(),
+    )
+}
+
+#[test]
+pub fn test_pipeline_definition() {
+    use crate::executor::JobExecutor;
+    use crate::job::JobContext;
+    use crate::pipeline::Pipeline;
+
+    #[derive(Debug)]
+    struct Shout;
+
+    impl JobExecutor for Shout {
+        fn kind(&self) -> &str {
+            "shout"
+        }
+
+        fn execute(&self, config: &JobConfig, context: &JobContext) -> Result<String> {
+            let separator = config.get("separator").map_or(" ", String::as_str);
+            Ok(context.input.join(separator).to_uppercase())
+        }
+    }
+
+    let json = r#"{
+        "jobs": [
+            { "name": "Hello", "type": "bash", "input": ["Hello"], "config": { "command": "echo -n {INPUT}" } },
+            { "name": "World", "type": "noop" },
+            {
+                "name": "Shout",
+                "type": "shout",
+                "depends_on": ["Hello", "World"],
+                "input": ["Hey"],
+                "config": { "separator": ", " }
+            }
+        ]
+    }"#;
+    let definition = PipelineDefinition::from_json(json).unwrap();
+
+    let mut pipeline = Pipeline::new();
+    assert!(matches!(
+        pipeline.load_definition(&definition),
+        Err(Error::UnknownJobKind { kind }) if kind == "shout"
+    ));
+
+    let mut pipeline = Pipeline::new().with_executor(Shout);
+    pipeline.load_definition(&definition).unwrap();
+    assert_eq!(pipeline.to_definition().unwrap(), definition);
+
+    smol::block_on(pipeline.execute()).unwrap();
+    let shout = pipeline
+        .jobs
+        .iter()
+        .find(|job| job.name == "Shout")
+        .unwrap();
+    assert_eq!(shout.output, "HEY, HELLO, NOOP HAS BEEN HIT!");
+    // The outputs that the jobs got as inputs aren't part of the definition
+    assert_eq!(pipeline.to_definition().unwrap(), definition);
+
+    let missing_command = r#"{ "jobs": [{ "name": "Broken", "type": "bash" }] }"#;
+    assert!(matches!(
+        Pipeline::new().load_definition(&PipelineDefinition::from_json(missing_command).unwrap()),
+        Err(Error::Definition { .. })
+    ));
+
+    let self_dependency =
+        r#"{ "jobs": [{ "name": "Loop", "type": "noop", "depends_on": ["Loop"] }] }"#;
+    assert!(matches!(
+        Pipeline::new().load_definition(&PipelineDefinition::from_json(self_dependency).unwrap()),
+        Err(Error::Definition { message }) if message.contains("depends on itself")
+    ));
+    let duplicate =
+        r#"{ "jobs": [{ "name": "Twice", "type": "noop" }, { "name": "Twice", "type": "noop" }] }"#;
+    assert!(matches!(
+        Pipeline::new().load_definition(&PipelineDefinition::from_json(duplicate).unwrap()),
+        Err(Error::Definition { message }) if message.contains("more than once")
+    ));
+}
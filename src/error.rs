@@ -28,6 +28,110 @@ pub enum Error {
 
     #[snafu(display("WASM module returned the wrong type"))]
     WasmWrongTypeReturned,
+
+    #[cfg(feature = "wasm")]
+    #[snafu(display("{message}"))]
+    WasmPlugin { message: String, code: u32 },
+
+    #[cfg(feature = "wasm")]
+    #[snafu(display(
+        "WASM module returned {len} bytes of output at {ptr}, outside of its memory"
+    ))]
+    WasmOutputOutOfBounds { ptr: usize, len: usize },
+
+    #[cfg(feature = "wasm")]
+    #[snafu(display("WASM module uses ABI version {version}, which isn't supported"))]
+    WasmAbiUnsupported { version: u32 },
+
+    #[cfg(feature = "wasm")]
+    #[snafu(display("Failed to parse the WASM module! {e}"))]
+    WasmParse {
+        e: Box<wasmparser::BinaryReaderError>,
+    },
+
+    #[cfg(feature = "wasm")]
+    #[snafu(display("WASM module {module} doesn't export a runnable function {function_name}"))]
+    WasmFunctionMissing {
+        function_name: String,
+        module: String,
+    },
+
+    #[cfg(feature = "wasm")]
+    #[snafu(display("WASM module's sha256 is {actual}, but {expected} was expected"))]
+    ModuleHashMismatch { expected: String, actual: String },
+
+    #[cfg(feature = "wasm")]
+    #[snafu(display("{sha256} isn't a sha256, which is 64 hex characters"))]
+    InvalidSha256 { sha256: String },
+
+    #[cfg(feature = "wasm")]
+    #[snafu(display("Failed to build the plugin! {message}"))]
+    PluginBuild { message: String },
+
+    #[snafu(display("Native job panicked! {message}"))]
+    NativePanic { message: String },
+
+    #[snafu(display("Job {job} is part of a dependency cycle"))]
+    DependencyCycle { job: String },
+
+    #[snafu(display("There's no job {job} in the pipeline"))]
+    UnknownJob { job: String },
+
+    #[snafu(display("Job {job} needs the output of {dependency}, which hasn't succeeded"))]
+    MissingOutput { job: String, dependency: String },
+
+    #[snafu(display("There's no stored run {run_id}"))]
+    UnknownRun { run_id: uuid::Uuid },
+
+    #[snafu(display("The pipeline doesn't have a state store"))]
+    NoStateStore,
+
+    #[snafu(display("The run was cancelled"))]
+    Cancelled,
+
+    #[snafu(display("Job {job} is marked as running, but nothing is running it"))]
+    JobStuck { job: String },
+
+    #[snafu(display("Job {job} selects executor {name}, which isn't registered"))]
+    UnknownExecutor { job: String, name: String },
+
+    #[snafu(display("Job {job} has to run on a worker, but the pipeline has no coordinator"))]
+    NoCoordinator { job: String },
+
+    #[snafu(display("Job failed on worker {worker}! {message}"))]
+    Remote { worker: String, message: String },
+
+    #[snafu(display("No worker picked up job {job} within {}s", timeout.as_secs_f64()))]
+    QueueTimeout {
+        job: String,
+        timeout: std::time::Duration,
+    },
+
+    #[snafu(display("Worker broke the protocol! {message}"))]
+    WorkerProtocol { message: String },
+
+    #[snafu(display("The coordinator and the server need a token, which their clients send"))]
+    MissingToken,
+
+    #[snafu(display("The coordinator rejected the worker! {message}"))]
+    WorkerRejected { message: String },
+
+    #[cfg(feature = "sqlite")]
+    #[snafu(display("SQLite error occured! {e}"))]
+    Sqlite { e: rusqlite::Error },
+
+    #[cfg(feature = "otel")]
+    #[snafu(display("Failed to set up OpenTelemetry! {message}"))]
+    Telemetry { message: String },
+
+    #[snafu(display("Invalid pipeline definition! {message}"))]
+    Definition { message: String },
+
+    #[snafu(display("There's no executor registered for job type {kind}"))]
+    UnknownJobKind { kind: String },
+
+    #[snafu(display("JSON (de)serialization failed! {e}"))]
+    Json { e: serde_json::Error },
 }
 
 impl From<flume::RecvError> for Error {
@@ -63,6 +167,26 @@ impl From<wasmtime::MemoryAccessError> for Error {
     }
 }
 
+#[cfg(feature = "wasm")]
+impl From<wasmparser::BinaryReaderError> for Error {
+    fn from(value: wasmparser::BinaryReaderError) -> Self {
+        Error::WasmParse { e: Box::new(value) }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(value: serde_json::Error) -> Self {
+        Error::Json { e: value }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for Error {
+    fn from(value: rusqlite::Error) -> Self {
+        Error::Sqlite { e: value }
+    }
+}
+
 impl From<bypar::error::Error> for Error {
     fn from(value: bypar::error::Error) -> Self {
         Error::ByparParse { e: value }
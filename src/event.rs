@@ -0,0 +1,38 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::state::{JobRecord, RunRecord};
+
+/// Something that happened while a pipeline was running, see [crate::pipeline::Pipeline::subscribe]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum PipelineEvent {
+    /// The run started or finished
+    Run { run: RunRecord },
+    /// A job was started or finished
+    Job { run_id: Uuid, job: JobRecord },
+    /// A running job logged a line
+    Log {
+        run_id: Uuid,
+        job_id: Uuid,
+        line: String,
+    },
+}
+
+impl PipelineEvent {
+    pub fn run_id(&self) -> Uuid {
+        match self {
+            PipelineEvent::Run { run } => run.run_id,
+            PipelineEvent::Job { run_id, .. } | PipelineEvent::Log { run_id, .. } => *run_id,
+        }
+    }
+
+    /// Name of the event's type, as it's serialized
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PipelineEvent::Run { .. } => "run",
+            PipelineEvent::Job { .. } => "job",
+            PipelineEvent::Log { .. } => "log",
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use crate::definition::JobDefinition;
+use crate::job::JobContext;
+use crate::Result;
+
+/// Options of a job, as they're written in the pipeline definition
+pub type JobConfig = BTreeMap<String, String>;
+
+/// Kind of job that's provided by another crate, like `kubectl apply` or an S3 upload.
+///
+/// Executors are registered on the [`Pipeline`](crate::pipeline::Pipeline), which uses them for
+/// the jobs in a [`PipelineDefinition`](crate::definition::PipelineDefinition) with a matching
+/// `type`.
+pub trait JobExecutor: Debug + Send + Sync {
+    /// Name of the job type in pipeline definitions
+    fn kind(&self) -> &str;
+
+    fn execute(&self, config: &JobConfig, context: &JobContext) -> Result<String>;
+
+    /// Checks the config before the pipeline starts running anything
+    fn validate(&self, _config: &JobConfig) -> Result<()> {
+        Ok(())
+    }
+
+    /// Short summary of what the job does, for logs and rendered pipelines
+    fn describe(&self, config: &JobConfig) -> String {
+        let options = config
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect::<Vec<_>>();
+        format!("{} {}", self.kind(), options.join(" "))
+            .trim_end()
+            .to_string()
+    }
+
+    /// Config that's written back into the pipeline definition
+    fn serialize(&self, config: &JobConfig) -> Result<JobConfig> {
+        Ok(config.clone())
+    }
+
+    /// Runs a job of any type whose selector names this executor, like a bash job in a container.
+    ///
+    /// By default the job's config is executed as if the job was of this executor's type.
+    fn execute_job(&self, job: &JobDefinition, context: &JobContext) -> Result<String> {
+        self.execute(&job.config, context)
+    }
+}
+
+/// Job that's run by a [`JobExecutor`]
+#[derive(Debug, Clone)]
+pub struct CustomJob {
+    pub executor: Arc<dyn JobExecutor>,
+    pub config: JobConfig,
+}
+
+impl PartialEq for CustomJob {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.executor, &other.executor) && self.config == other.config
+    }
+}
+
+impl Eq for CustomJob {}
+
+/// Executors keyed by their kind
+#[derive(Debug, Clone, Default)]
+pub struct ExecutorRegistry {
+    executors: BTreeMap<String, Arc<dyn JobExecutor>>,
+}
+
+impl ExecutorRegistry {
+    pub fn new() -> Self {
+        ExecutorRegistry::default()
+    }
+
+    /// Registers the executor, replacing any executor of the same kind
+    pub fn register(&mut self, executor: impl JobExecutor + 'static) {
+        self.executors
+            .insert(executor.kind().to_string(), Arc::new(executor));
+    }
+
+    pub fn get(&self, kind: &str) -> Option<Arc<dyn JobExecutor>> {
+        self.executors.get(kind).cloned()
+    }
+
+    pub fn kinds(&self) -> impl Iterator<Item = &str> {
+        self.executors.keys().map(String::as_str)
+    }
+}
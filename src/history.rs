@@ -0,0 +1,469 @@
+//! Queries over the runs that were recorded in a [StateStore]: listing them, comparing two of them
+//! and following how long a job took over the last runs.
+
+use std::fmt::Display;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use uuid::Uuid;
+
+use crate::error::Error;
+use crate::state::{JobRecord, JobState, RunRecord, StateStore};
+use crate::Result;
+
+#[derive(Debug, Clone)]
+pub struct RunHistory {
+    store: Arc<dyn StateStore>,
+}
+
+impl RunHistory {
+    pub fn new(store: impl StateStore + 'static) -> Self {
+        RunHistory {
+            store: Arc::new(store),
+        }
+    }
+
+    /// The last runs, from the newest to the oldest
+    pub fn list(&self, last: usize) -> Result<Vec<RunRecord>> {
+        let mut runs = self.store.runs()?;
+        runs.reverse();
+        runs.truncate(last);
+        Ok(runs)
+    }
+
+    pub fn show(&self, run_id: Uuid) -> Result<RunDetails> {
+        let run = self
+            .store
+            .load_run(run_id)?
+            .ok_or(Error::UnknownRun { run_id })?;
+        let jobs = self.store.load_jobs(run_id)?;
+        Ok(RunDetails { run, jobs })
+    }
+
+    /// Compares the jobs of two runs, which are matched by their names
+    pub fn diff(&self, before: Uuid, after: Uuid) -> Result<RunDiff> {
+        let before = self.show(before)?;
+        let after = self.show(after)?;
+
+        let find = |details: &RunDetails, name: &str| {
+            details.jobs.iter().find(|job| job.name == name).cloned()
+        };
+        let mut jobs = after
+            .jobs
+            .iter()
+            .map(|job| JobDiff {
+                name: job.name.clone(),
+                before: find(&before, &job.name),
+                after: Some(job.clone()),
+            })
+            .collect::<Vec<_>>();
+        jobs.extend(
+            before
+                .jobs
+                .iter()
+                .filter(|job| find(&after, &job.name).is_none())
+                .map(|job| JobDiff {
+                    name: job.name.clone(),
+                    before: Some(job.clone()),
+                    after: None,
+                }),
+        );
+
+        Ok(RunDiff {
+            before,
+            after,
+            jobs,
+        })
+    }
+
+    /// How long the job took in the last runs, from the oldest to the newest.
+    ///
+    /// Runs that didn't run the job are skipped.
+    pub fn trend(&self, job: &str, last: usize) -> Result<Trend> {
+        let mut points = vec![];
+        for run in self.list(last)? {
+            let jobs = self.store.load_jobs(run.run_id)?;
+            if let Some(record) = jobs.iter().find(|record| record.name == job) {
+                points.push(TrendPoint {
+                    run_id: run.run_id,
+                    started_at: run.started_at,
+                    state: record.state,
+                    duration: record.duration,
+                });
+            }
+        }
+        points.reverse();
+
+        Ok(Trend {
+            job: job.to_string(),
+            points,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunDetails {
+    pub run: RunRecord,
+    pub jobs: Vec<JobRecord>,
+}
+
+impl Display for RunDetails {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let run = &self.run;
+        writeln!(f, "Run {} ({})", run.run_id, run.state.as_str())?;
+        writeln!(f, "Started:    {}", format_time(run.started_at))?;
+        match run.finished_at {
+            Some(finished_at) => writeln!(
+                f,
+                "Finished:   {} ({})",
+                format_time(finished_at),
+                seconds(run.duration())
+            )?,
+            None => writeln!(f, "Finished:   -")?,
+        }
+        writeln!(
+            f,
+            "Definition: {}",
+            run.definition_hash.as_deref().unwrap_or("-")
+        )?;
+        writeln!(f)?;
+
+        let width = name_width(self.jobs.iter().map(|job| job.name.as_str()));
+        writeln!(
+            f,
+            "{:width$}  {:9}  {:>8}  {:>10}  Output",
+            "Job", "State", "Attempts", "Duration"
+        )?;
+        for job in &self.jobs {
+            writeln!(
+                f,
+                "{:width$}  {:9}  {:>8}  {:>10}  {}",
+                job.name,
+                job.state.as_str(),
+                job.attempts,
+                seconds(job.duration),
+                summary(&job.message)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobDiff {
+    pub name: String,
+    /// Unset when the job was added in the later run
+    pub before: Option<JobRecord>,
+    /// Unset when the job was removed in the later run
+    pub after: Option<JobRecord>,
+}
+
+impl JobDiff {
+    pub fn state_changed(&self) -> bool {
+        self.before.as_ref().map(|job| job.state) != self.after.as_ref().map(|job| job.state)
+    }
+
+    pub fn output_changed(&self) -> bool {
+        match (&self.before, &self.after) {
+            (Some(before), Some(after)) => before.message != after.message,
+            _ => false,
+        }
+    }
+
+    /// How much longer the job took in the later run, in seconds
+    pub fn duration_change(&self) -> Option<f64> {
+        let before = self.before.as_ref()?.duration?;
+        let after = self.after.as_ref()?.duration?;
+        Some(after.as_secs_f64() - before.as_secs_f64())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunDiff {
+    pub before: RunDetails,
+    pub after: RunDetails,
+    /// Jobs of the later run, followed by the ones that it no longer has
+    pub jobs: Vec<JobDiff>,
+}
+
+impl RunDiff {
+    pub fn definition_changed(&self) -> bool {
+        self.before.run.definition_hash != self.after.run.definition_hash
+    }
+}
+
+impl Display for RunDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (before, after) = (&self.before.run, &self.after.run);
+        writeln!(f, "Runs {} -> {}", before.run_id, after.run_id)?;
+        writeln!(
+            f,
+            "State:    {}",
+            change(before.state.as_str(), after.state.as_str())
+        )?;
+        writeln!(
+            f,
+            "Duration: {}",
+            duration_change(before.duration(), after.duration())
+        )?;
+        if self.definition_changed() {
+            writeln!(f, "The pipeline definition changed")?;
+        }
+        writeln!(f)?;
+
+        let width = name_width(self.jobs.iter().map(|job| job.name.as_str()));
+        writeln!(f, "{:width$}  {:22}  Duration", "Job", "State")?;
+        for job in &self.jobs {
+            let state = |record: &Option<JobRecord>| {
+                record
+                    .as_ref()
+                    .map_or("-", |record| record.state.as_str())
+                    .to_string()
+            };
+            let duration =
+                |record: &Option<JobRecord>| record.as_ref().and_then(|record| record.duration);
+
+            write!(
+                f,
+                "{:width$}  {:22}  {}",
+                job.name,
+                change(&state(&job.before), &state(&job.after)),
+                duration_change(duration(&job.before), duration(&job.after))
+            )?;
+            if job.output_changed() {
+                write!(f, "  (output changed)")?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrendPoint {
+    pub run_id: Uuid,
+    pub started_at: SystemTime,
+    pub state: JobState,
+    pub duration: Option<Duration>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trend {
+    pub job: String,
+    /// From the oldest run to the newest
+    pub points: Vec<TrendPoint>,
+}
+
+impl Trend {
+    /// Average duration of the runs in which the job finished
+    pub fn average(&self) -> Option<Duration> {
+        let durations = self
+            .points
+            .iter()
+            .filter_map(|point| point.duration)
+            .collect::<Vec<_>>();
+        let count = u32::try_from(durations.len()).ok().filter(|n| *n > 0)?;
+        Some(durations.iter().sum::<Duration>() / count)
+    }
+}
+
+impl Display for Trend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const BAR_WIDTH: f64 = 40.0;
+
+        writeln!(f, "{} over the last {} runs", self.job, self.points.len())?;
+        let longest = self
+            .points
+            .iter()
+            .filter_map(|point| point.duration)
+            .max()
+            .unwrap_or_default();
+
+        for point in &self.points {
+            let bar = match point.duration {
+                Some(duration) if !longest.is_zero() => {
+                    let length = duration.as_secs_f64() / longest.as_secs_f64() * BAR_WIDTH;
+                    "#".repeat(length.round().max(1.0) as usize)
+                }
+                _ => String::new(),
+            };
+            writeln!(
+                f,
+                "{}  {:9}  {:>10}  {bar}",
+                format_time(point.started_at),
+                point.state.as_str(),
+                seconds(point.duration)
+            )?;
+        }
+
+        if let (Some(average), Some(latest)) = (
+            self.average(),
+            self.points.last().and_then(|point| point.duration),
+        ) {
+            write!(
+                f,
+                "Average: {}, latest: {}",
+                seconds(Some(average)),
+                seconds(Some(latest))
+            )?;
+            if !average.is_zero() {
+                let change = (latest.as_secs_f64() / average.as_secs_f64() - 1.0) * 100.0;
+                write!(f, " ({change:+.1}% from the average)")?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+fn seconds(duration: Option<Duration>) -> String {
+    duration.map_or("-".to_string(), |duration| {
+        format!("{:.3}s", duration.as_secs_f64())
+    })
+}
+
+fn change(before: &str, after: &str) -> String {
+    if before == after {
+        before.to_string()
+    } else {
+        format!("{before} -> {after}")
+    }
+}
+
+fn duration_change(before: Option<Duration>, after: Option<Duration>) -> String {
+    match (before, after) {
+        (Some(before), Some(after)) if before != after => {
+            let difference = after.as_secs_f64() - before.as_secs_f64();
+            format!(
+                "{} -> {} ({difference:+.3}s)",
+                seconds(Some(before)),
+                seconds(Some(after))
+            )
+        }
+        (before, after) => change(&seconds(before), &seconds(after)),
+    }
+}
+
+fn name_width<'a>(names: impl Iterator<Item = &'a str>) -> usize {
+    names.map(str::len).max().unwrap_or(0).max(3)
+}
+
+/// First line of the output, shortened so that it fits in a table
+fn summary(message: &str) -> String {
+    let line = message.lines().next().unwrap_or_default();
+    if line.chars().count() > 40 {
+        format!("{}...", line.chars().take(37).collect::<String>())
+    } else {
+        line.to_string()
+    }
+}
+
+/// Formats the time as UTC, like `2024-05-01 13:45:00 UTC`
+pub fn format_time(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    let (days, time) = (seconds.div_euclid(86_400), seconds.rem_euclid(86_400));
+
+    // Converts the days since the epoch to a date in the proleptic Gregorian calendar
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+#[test]
+pub fn test_run_history() {
+    use crate::job::Job;
+    use crate::job_type::JobType;
+    use crate::pipeline::Pipeline;
+    use crate::state::JsonStateStore;
+
+    let root = std::env::temp_dir().join(format!("waterflow-history-{}", Uuid::new_v4()));
+    // Build's output differs between runs, while the definition stays the same
+    let marker = root.join("fail");
+    let run = || {
+        let build = Job::new("Build", JobType::new_bash("date +%s%N"));
+        let mut test = Job::new(
+            "Test",
+            JobType::new_bash(&format!(
+                "test -e {} && echo 1 test failed >&2 && exit 1; echo -n ok",
+                marker.display()
+            )),
+        );
+        test.add_dependency(build.get_id());
+
+        let mut pipeline = Pipeline::new().with_state_store(JsonStateStore::new(&root).unwrap());
+        pipeline.add_jobs(vec![build, test]);
+        smol::block_on(pipeline.execute()).unwrap();
+        pipeline.run_id()
+    };
+
+    let first = run();
+    std::fs::write(&marker, "").unwrap();
+    let second = run();
+    let history = RunHistory::new(JsonStateStore::new(&root).unwrap());
+
+    let runs = history.list(10).unwrap();
+    assert_eq!(runs.len(), 2);
+    assert_eq!(runs[0].run_id, second);
+    assert_eq!(runs[0].state, JobState::Failed);
+    assert!(runs[0].finished_at.is_some());
+    assert_eq!(history.list(1).unwrap().len(), 1);
+
+    let details = history.show(first).unwrap();
+    assert_eq!(details.run.state, JobState::Succeeded);
+    assert_eq!(details.jobs.len(), 2);
+    assert_eq!(details.jobs[1].attempts, 1);
+    assert!(details.to_string().contains("Test"));
+    assert!(matches!(
+        history.show(Uuid::new_v4()),
+        Err(Error::UnknownRun { .. })
+    ));
+
+    let diff = history.diff(first, second).unwrap();
+    assert!(diff.before.run.definition_hash.is_some());
+    assert!(!diff.definition_changed());
+    assert!(!diff.jobs[0].state_changed());
+    assert!(diff.jobs[1].state_changed());
+    assert!(diff.jobs[1].output_changed());
+    assert!(diff.to_string().contains("succeeded -> failed"));
+
+    let trend = history.trend("Build", 50).unwrap();
+    assert_eq!(trend.points.len(), 2);
+    assert_eq!(trend.points[0].run_id, first);
+    assert!(trend.average().is_some());
+    assert!(history.trend("Deploy", 50).unwrap().points.is_empty());
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+pub fn test_format_time() {
+    assert_eq!(
+        format_time(SystemTime::UNIX_EPOCH),
+        "1970-01-01 00:00:00 UTC"
+    );
+    assert_eq!(
+        format_time(SystemTime::UNIX_EPOCH + Duration::from_secs(1_709_210_096)),
+        "2024-02-29 12:34:56 UTC"
+    );
+}
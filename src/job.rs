@@ -1,7 +1,15 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
-use crate::{job_type::JobType, Result};
-use tracing::trace;
+use crate::definition::JobDefinition;
+use crate::executor::JobExecutor;
+use crate::pipeline::CancelHandle;
+use crate::remote::Coordinator;
+use crate::{job_type::JobType, metrics::Metrics, state::JobState, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{field, info_span, trace, Instrument};
 use uuid::Uuid;
 
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
@@ -38,6 +46,112 @@ impl JobStatus {
     }
 }
 
+/// Secret values handed to a job, which are never printed when debugging
+#[derive(Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Secrets(BTreeMap<String, String>);
+
+impl Secrets {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    pub fn insert(&mut self, key: &str, value: &str) {
+        self.0.insert(key.to_string(), value.to_string());
+    }
+}
+
+impl std::fmt::Debug for Secrets {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map()
+            .entries(self.0.keys().map(|k| (k, "<redacted>")))
+            .finish()
+    }
+}
+
+type LogListener = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Lines that a job logs while it's running, shared between the clones of its context
+#[derive(Clone, Default)]
+pub struct JobLogs {
+    lines: Arc<Mutex<Vec<String>>>,
+    listener: Option<LogListener>,
+}
+
+impl std::fmt::Debug for JobLogs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("JobLogs").field(&self.lines).finish()
+    }
+}
+
+impl JobLogs {
+    /// Calls the listener with every line as soon as it's logged
+    pub fn with_listener(listener: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        JobLogs {
+            lines: Arc::default(),
+            listener: Some(Arc::new(listener)),
+        }
+    }
+
+    pub fn push(&self, line: impl Into<String>) {
+        let line = line.into();
+        if let Some(listener) = &self.listener {
+            listener(&line);
+        }
+        self.lines
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(line);
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        self.lines.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+/// Where a job may run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Selector {
+    /// On a thread of the process that runs the pipeline
+    Local,
+    /// By the pipeline's executor that's registered under the name
+    Executor(String),
+    /// On a remote worker that has all of the labels
+    Worker(Vec<String>),
+}
+
+/// Everything that a job type gets to see while it's executing
+#[derive(Debug, Clone, Default)]
+pub struct JobContext {
+    pub job_id: Uuid,
+    pub name: String,
+    pub fixed_input: Vec<String>,
+    pub input: Vec<String>,
+    pub env: BTreeMap<String, String>,
+    pub secrets: Secrets,
+    pub logs: JobLogs,
+    /// Where the job type can record its own metrics
+    pub metrics: Option<Metrics>,
+    /// Cancelled together with the run, which job types check to stop early
+    pub cancel: CancelHandle,
+}
+
+impl JobContext {
+    /// Adds a line to the job's logs
+    pub fn log(&self, line: impl Into<String>) {
+        self.logs.push(line);
+    }
+}
+
+/// Outcome of a job's run, which is recorded with [Job::finish]
+#[derive(Debug)]
+pub(crate) struct JobRun {
+    status: JobStatus,
+    output: String,
+    logs: Vec<String>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub struct Job {
     /// Job's UUID, which can be used for dependencies
@@ -56,11 +170,26 @@ pub struct Job {
 
     /// Job's status
     pub status: JobStatus,
+    /// When the job last started running
+    pub started_at: Option<Instant>,
+    /// How many times the job was started
+    pub attempts: u32,
 
     /// Job's IO
     pub fixed_input: Vec<String>,
     pub input: Vec<String>,
     pub output: String,
+    /// Lines that the job logged during its last run
+    pub logs: Vec<String>,
+
+    /// Configuration values, exposed to Bash as environment variables and to plugins through `get_env`
+    pub env: BTreeMap<String, String>,
+    /// Secret values, exposed to plugins through `get_secret`
+    pub secrets: Secrets,
+    /// Labels for finding the job, like `lint` or `test`
+    pub tags: Vec<String>,
+    /// Where the job may run, by default locally
+    pub selector: Option<Selector>,
 }
 
 // Builder pattern
@@ -74,11 +203,52 @@ impl Job {
         }
     }
 
+    /// Creates a job that runs the closure in-process
+    pub fn from_fn<F>(name: &str, f: F) -> Self
+    where
+        F: Fn(&JobContext) -> Result<String> + Send + Sync + 'static,
+    {
+        Self::new(name, JobType::new_native(f))
+    }
+
+    /// Creates a job that runs the async closure in-process
+    pub fn from_async_fn<F, Fut>(name: &str, f: F) -> Self
+    where
+        F: Fn(JobContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<String>> + Send + 'static,
+    {
+        Self::new(name, JobType::new_native_async(f))
+    }
+
     // TODO: Fix the input fetching from output of another job
     pub fn with_input(mut self, input: Vec<String>) -> Self {
         self.input = input;
         self
     }
+
+    pub fn with_env(mut self, key: &str, value: &str) -> Self {
+        self.env.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn with_secret(mut self, key: &str, value: &str) -> Self {
+        self.secrets.insert(key, value);
+        self
+    }
+
+    pub fn with_tag(mut self, tag: &str) -> Self {
+        self.tags.push(tag.to_string());
+        self
+    }
+
+    pub fn with_selector(mut self, selector: Selector) -> Self {
+        self.selector = Some(selector);
+        self
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
 }
 
 // Dependency management
@@ -124,56 +294,157 @@ impl Job {
             .all(|dep| completed_uuids.contains(dep))
     }
 
+    pub(crate) fn context(&self) -> JobContext {
+        JobContext {
+            job_id: self.job_id,
+            name: self.name.clone(),
+            fixed_input: self.fixed_input.clone(),
+            input: self.input.clone(),
+            env: self.env.clone(),
+            secrets: self.secrets.clone(),
+            logs: JobLogs::default(),
+            metrics: None,
+            cancel: CancelHandle::default(),
+        }
+    }
+
+    #[cfg(test)]
     pub(crate) async fn execute(&mut self) -> Result<JobStatus> {
-        let (tx, rx) = flume::bounded(1);
+        self.start();
+        let run = self.run(self.context()).await;
+        Ok(self.finish(run))
+    }
 
-        let id = self.get_id();
-        let job_type = self.job_type.clone();
+    /// Marks the job as running
+    pub(crate) fn start(&mut self) {
         let started_at = Instant::now();
+        self.started_at = Some(started_at);
+        self.attempts += 1;
+        self.logs.clear();
         self.set_status(&JobStatus::InProgress { started_at });
+    }
 
-        // Add fixed_input before other elements
-        let fixed_input = self.fixed_input.clone();
-        let input = self.input.clone();
+    /// Runs a job that was already started, with a context that was made by [Job::context]
+    pub(crate) fn run(&self, context: JobContext) -> impl Future<Output = JobRun> + Send + 'static {
+        let job_type = self.job_type.clone();
+        self.run_with(context, move |context| job_type.execute(context))
+    }
+
+    /// Runs a job that was already started with the executor, whatever its job type is
+    pub(crate) fn run_on(
+        &self,
+        context: JobContext,
+        executor: Arc<dyn JobExecutor>,
+    ) -> impl Future<Output = JobRun> + Send + 'static {
+        let definition = JobDefinition::from_job(self, vec![]);
+        self.run_with(context, move |context| {
+            executor.execute_job(&definition?, context)
+        })
+    }
+
+    /// Runs a job that was already started on one of the coordinator's workers
+    pub(crate) fn run_remote(
+        &self,
+        context: JobContext,
+        coordinator: Coordinator,
+    ) -> impl Future<Output = JobRun> + Send + 'static {
+        let definition = JobDefinition::from_job(self, vec![]);
+        self.run_with(context, move |context| {
+            coordinator.execute(definition?, context)
+        })
+    }
+
+    /// Executes the job on its own thread, the future doesn't borrow the job so that others can run
+    /// at the same time
+    fn run_with<F>(
+        &self,
+        context: JobContext,
+        execute: F,
+    ) -> impl Future<Output = JobRun> + Send + 'static
+    where
+        F: FnOnce(&JobContext) -> Result<String> + Send + 'static,
+    {
+        let (tx, rx) = flume::bounded(1);
+
+        let id = self.get_id();
+        let started_at = self.started_at.unwrap_or_else(Instant::now);
+
+        let logs = context.logs.clone();
+
+        let span = info_span!(
+            "job",
+            job_id = %id,
+            name = %self.name,
+            job_type = self.job_type.kind(),
+            attempt = self.attempts,
+            status = field::Empty,
+        );
+        let thread_span = span.clone();
 
         std::thread::spawn(move || {
-            //TODO: execute something
-            let res = job_type.execute(&fixed_input, &input);
+            let res = thread_span.in_scope(|| execute(&context));
+            drop(thread_span);
 
+            // Nobody is waiting for the result anymore when the future was dropped
             match res {
                 Ok(output) => {
-                    tx.send((
+                    let _ = tx.send((
                         JobStatus::Succeeded {
                             msg: output.clone(),
                             duration: Instant::now().duration_since(started_at),
                         },
                         output,
-                    ))
-                    .unwrap();
+                    ));
                     trace!("Job {:?} finished the execution", id);
                 }
                 Err(e) => {
-                    tx.send((
+                    let _ = tx.send((
                         JobStatus::Failed {
                             msg: e.to_string(),
                             duration: Instant::now().duration_since(started_at),
                         },
                         e.to_string(),
-                    ))
-                    .unwrap();
+                    ));
                     trace!("Job {:?} finished the execution", id);
                 }
             }
         });
 
-        let (status, output) = rx.recv_async().await?;
+        async move {
+            // The thread only drops the sender without a result when the executor panicked
+            let (status, output) = rx
+                .recv_async()
+                .instrument(span.clone())
+                .await
+                .unwrap_or_else(|_| {
+                    let msg = "The job's executor panicked".to_string();
+                    let duration = Instant::now().duration_since(started_at);
+                    (
+                        JobStatus::Failed {
+                            msg: msg.clone(),
+                            duration,
+                        },
+                        msg,
+                    )
+                });
 
-        trace!("Received \"job finished\" response from the thread");
+            trace!("Received \"job finished\" response from the thread");
+            span.record("status", JobState::from(&status).as_str());
 
-        self.set_status(&status);
-        self.set_output(&output);
+            JobRun {
+                status,
+                output,
+                logs: logs.lines(),
+            }
+        }
+    }
 
-        Ok(status)
+    /// Records how the job's run went
+    pub(crate) fn finish(&mut self, run: JobRun) -> JobStatus {
+        self.set_status(&run.status);
+        self.set_output(&run.output);
+        self.logs = run.logs;
+        run.status
     }
 }
 
@@ -186,6 +457,16 @@ impl Job {
     pub fn get_status(&self) -> JobStatus {
         self.status.clone()
     }
+
+    /// How long the job ran for, if it has finished
+    pub fn get_duration(&self) -> Option<Duration> {
+        match self.status {
+            JobStatus::Failed { duration, .. } | JobStatus::Succeeded { duration, .. } => {
+                Some(duration)
+            }
+            _ => None,
+        }
+    }
 }
 
 #[test]
@@ -200,3 +481,24 @@ pub fn test_job_execution() {
 
     assert!(job_res.is_succeeded());
 }
+
+#[test]
+pub fn test_job_logs() {
+    let mut job = Job::new(
+        "Noisy job",
+        JobType::new_bash("echo 'warning: deprecated' >&2; echo -n 'done'"),
+    );
+
+    smol::block_on(job.execute()).unwrap();
+    assert_eq!(job.output, "done");
+    assert_eq!(job.logs, ["warning: deprecated"]);
+
+    job.job_type = JobType::new_native(|context| {
+        context.log("first");
+        context.log("second");
+        Ok(String::new())
+    });
+    smol::block_on(job.execute()).unwrap();
+    assert_eq!(job.logs, ["first", "second"]);
+    assert_eq!(job.attempts, 2);
+}
@@ -1,6 +1,19 @@
+use std::future::Future;
+use std::io::Read;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
 use tracing::trace;
 
-use crate::{error::Error, Result};
+use crate::executor::{CustomJob, JobConfig, JobExecutor};
+use crate::{error::Error, job::JobContext, native::NativeJob, Result};
+#[cfg(feature = "wasm")]
+use crate::{module_source::ModuleSource, wasm::Capabilities};
+
+/// How often a running Bash job checks whether it was cancelled
+const BASH_POLL_INTERVAL: Duration = Duration::from_millis(10);
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum WebRequestType {
@@ -17,8 +30,20 @@ pub enum JobType {
     Wasm {
         /// Function name to be called with the input
         function_name: String,
-        /// Name of the binary that we want to run
-        file_name: String,
+        /// Binary that we want to run
+        source: ModuleSource,
+        /// Host functions that the plugin is allowed to use
+        capabilities: Capabilities,
+    },
+    /// Plugin built as a component that implements the `waterflow:plugin` WIT world
+    #[cfg(feature = "wasm")]
+    Component {
+        /// Function name that's passed to the component's `run` export
+        function_name: String,
+        /// Component that we want to run
+        source: ModuleSource,
+        /// Host functions that the plugin is allowed to use
+        capabilities: Capabilities,
     },
     Bash {
         /// Command that will be executed inside of Bash
@@ -29,6 +54,10 @@ pub enum JobType {
         url: String,
         req_type: WebRequestType,
     },
+    /// Rust closure that runs in-process
+    Native(NativeJob),
+    /// Job type that's provided by a [`JobExecutor`]
+    Custom(CustomJob),
 }
 
 impl JobType {
@@ -39,10 +68,29 @@ impl JobType {
     }
 
     #[cfg(feature = "wasm")]
-    pub fn new_wasm(function_name: &str, file_name: &str) -> Self {
+    pub fn new_wasm(function_name: &str, source: impl Into<ModuleSource>) -> Self {
+        Self::new_wasm_with_capabilities(function_name, source, Capabilities::default())
+    }
+
+    #[cfg(feature = "wasm")]
+    pub fn new_wasm_with_capabilities(
+        function_name: &str,
+        source: impl Into<ModuleSource>,
+        capabilities: Capabilities,
+    ) -> Self {
         Self::Wasm {
             function_name: function_name.to_string(),
-            file_name: file_name.to_string(),
+            source: source.into(),
+            capabilities,
+        }
+    }
+
+    #[cfg(feature = "wasm")]
+    pub fn new_component(function_name: &str, source: impl Into<ModuleSource>) -> Self {
+        Self::Component {
+            function_name: function_name.to_string(),
+            source: source.into(),
+            capabilities: Capabilities::default(),
         }
     }
 
@@ -54,7 +102,104 @@ impl JobType {
         }
     }
 
-    pub fn execute(&self, _fixed_inputs: &[String], inputs: &[String]) -> Result<String> {
+    pub fn new_native<F>(f: F) -> Self
+    where
+        F: Fn(&JobContext) -> Result<String> + Send + Sync + 'static,
+    {
+        Self::Native(NativeJob::new(f))
+    }
+
+    pub fn new_native_async<F, Fut>(f: F) -> Self
+    where
+        F: Fn(JobContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<String>> + Send + 'static,
+    {
+        Self::Native(NativeJob::new_async(f))
+    }
+
+    pub fn new_custom(executor: Arc<dyn JobExecutor>, config: JobConfig) -> Self {
+        Self::Custom(CustomJob { executor, config })
+    }
+
+    /// Short summary of what the job does
+    pub fn describe(&self) -> String {
+        match self {
+            JobType::Noop => "noop".to_string(),
+            #[cfg(feature = "wasm")]
+            JobType::Wasm {
+                function_name,
+                source,
+                ..
+            } => format!("wasm {function_name} from {source}"),
+            #[cfg(feature = "wasm")]
+            JobType::Component {
+                function_name,
+                source,
+                ..
+            } => format!("component {function_name} from {source}"),
+            JobType::Bash { command } => format!("bash {command}"),
+            #[cfg(feature = "web")]
+            JobType::WebRequest { url, req_type } => {
+                format!("{} {url}", format!("{req_type:?}").to_uppercase())
+            }
+            JobType::Native(_) => "native closure".to_string(),
+            JobType::Custom(custom) => custom.executor.describe(&custom.config),
+        }
+    }
+
+    /// Name of the job type, as used in pipeline definitions
+    pub fn kind(&self) -> &str {
+        match self {
+            JobType::Noop => "noop",
+            #[cfg(feature = "wasm")]
+            JobType::Wasm { .. } => "wasm",
+            #[cfg(feature = "wasm")]
+            JobType::Component { .. } => "component",
+            JobType::Bash { .. } => "bash",
+            #[cfg(feature = "web")]
+            JobType::WebRequest { .. } => "web_request",
+            JobType::Native(_) => "native",
+            JobType::Custom(custom) => custom.executor.kind(),
+        }
+    }
+
+    /// Checks that the job can be executed, before the pipeline starts running anything
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            #[cfg(feature = "wasm")]
+            JobType::Wasm {
+                function_name,
+                source,
+                ..
+            } => {
+                use crate::manifest::PluginManifest;
+                use crate::wasm::SUPPORTED_ABI_VERSIONS;
+
+                let manifest = PluginManifest::from_source(source)?;
+                if !manifest.can_run(function_name) {
+                    return Err(Error::WasmFunctionMissing {
+                        function_name: function_name.clone(),
+                        module: source.to_string(),
+                    });
+                }
+
+                match manifest.function(function_name) {
+                    Some(function) if !SUPPORTED_ABI_VERSIONS.contains(&function.abi) => {
+                        Err(Error::WasmAbiUnsupported {
+                            version: function.abi,
+                        })
+                    }
+                    _ => Ok(()),
+                }
+            }
+            #[cfg(feature = "wasm")]
+            JobType::Component { source, .. } => crate::component::validate_component(source),
+            JobType::Custom(custom) => custom.executor.validate(&custom.config),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn execute(&self, context: &JobContext) -> Result<String> {
         match self {
             JobType::Noop => {
                 trace!("Noop has been hit!");
@@ -63,38 +208,84 @@ impl JobType {
             #[cfg(feature = "wasm")]
             JobType::Wasm {
                 function_name,
-                file_name,
-            } => JobType::execute_wasm(function_name, file_name, inputs),
-            JobType::Bash { command } => JobType::execute_bash(command, inputs),
+                source,
+                capabilities,
+            } => JobType::execute_wasm(function_name, source, *capabilities, context),
+            #[cfg(feature = "wasm")]
+            JobType::Component {
+                function_name,
+                source,
+                capabilities,
+            } => crate::component::run_component(function_name, source, *capabilities, context),
+            JobType::Bash { command } => JobType::execute_bash(command, context),
             #[cfg(feature = "web")]
             JobType::WebRequest { url, req_type } => {
-                JobType::execute_web_request(url, *req_type, inputs)
+                JobType::execute_web_request(url, *req_type, &context.input)
             }
+            JobType::Native(native) => native.call(context),
+            JobType::Custom(custom) => custom.executor.execute(&custom.config, context),
         }
     }
 
     #[cfg(feature = "wasm")]
-    fn execute_wasm(function_name: &str, file_name: &str, inputs: &[String]) -> Result<String> {
+    fn execute_wasm(
+        function_name: &str,
+        source: &ModuleSource,
+        capabilities: Capabilities,
+        context: &JobContext,
+    ) -> Result<String> {
         use crate::wasm::run_wasm_code;
 
-        run_wasm_code(function_name, file_name, inputs)
+        run_wasm_code(function_name, source, capabilities, context)
     }
 
     // TODO: implement better kind of placeholding "{INPUT}"
-    fn execute_bash(command: &str, inputs: &[String]) -> Result<String> {
+    fn execute_bash(command: &str, context: &JobContext) -> Result<String> {
+        let inputs = &context.input;
         trace!("Inputs: {:?}", inputs);
         let command = command.replace("{INPUT}", &inputs.join(" "));
 
-        let output = std::process::Command::new("bash")
-            .args(["-c", &command])
-            .output()?;
+        let mut bash = std::process::Command::new("bash");
+        bash.args(["-c", &command])
+            .envs(&context.env)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        // Bash gets its own process group, so that cancelling also kills the commands it started
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut bash, 0);
+        let mut child = bash.spawn()?;
 
-        if output.status.success() {
-            let output = String::from_utf8_lossy(&output.stdout).to_string();
+        let stdout = read_pipe(child.stdout.take());
+        let stderr = read_pipe(child.stderr.take());
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if context.cancel.is_cancelled() {
+                #[cfg(unix)]
+                // SAFETY: Only sends a signal to the process group that was created for the child
+                unsafe {
+                    libc::kill(-(child.id() as i32), libc::SIGKILL);
+                }
+                child.kill()?;
+                child.wait()?;
+                trace!("Bash execution was cancelled");
+                return Err(Error::Cancelled);
+            }
+            std::thread::sleep(BASH_POLL_INTERVAL);
+        };
+        let stdout = stdout.join().unwrap_or_default();
+        let stderr = stderr.join().unwrap_or_default();
+
+        if status.success() {
+            String::from_utf8_lossy(&stderr)
+                .lines()
+                .for_each(|line| context.log(line));
+            let output = String::from_utf8_lossy(&stdout).to_string();
             trace!("Bash execution succeeded: {}", output);
             Ok(output)
         } else {
-            let err = String::from_utf8_lossy(&output.stderr).to_string();
+            let err = String::from_utf8_lossy(&stderr).to_string();
             trace!("Bash execution failed: {}", err);
             Err(Error::Bash { e: err })
         }
@@ -116,3 +307,31 @@ impl JobType {
         Ok(body)
     }
 }
+
+/// Reads the pipe until it's closed, so that the child never blocks on a full pipe
+fn read_pipe(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut bytes = vec![];
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut bytes);
+        }
+        bytes
+    })
+}
+
+#[test]
+#[cfg(feature = "wasm")]
+pub fn test_validate_missing_wasm_function() {
+    let module = wat::parse_str(
+        r#"(module (func (export "normal_join") (param i32 i32) (result i32) (i32.const 0)))"#,
+    )
+    .unwrap();
+
+    assert!(JobType::new_wasm("normal_join", module.clone())
+        .validate()
+        .is_ok());
+    assert!(matches!(
+        JobType::new_wasm("reverse_join", module).validate(),
+        Err(Error::WasmFunctionMissing { .. })
+    ));
+}
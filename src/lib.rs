@@ -1,8 +1,31 @@
+pub mod analysis;
+#[cfg(feature = "wasm")]
+pub mod component;
+pub mod definition;
 pub mod error;
+pub mod event;
+pub mod executor;
+pub mod history;
 pub mod job;
 pub mod job_type;
+#[cfg(feature = "wasm")]
+pub mod manifest;
+pub mod metrics;
+#[cfg(feature = "wasm")]
+pub mod module_source;
+pub mod native;
+#[cfg(feature = "otel")]
+pub mod otel;
 pub mod pipeline;
 pub mod pipeline_tree;
+pub mod remote;
+pub mod render;
+pub mod report;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod state;
+#[cfg(feature = "wasm")]
+pub mod testing;
 #[cfg(feature = "wasm")]
 pub mod wasm;
 
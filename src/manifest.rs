@@ -0,0 +1,123 @@
+use serde::{Deserialize, Serialize};
+use wasmparser::{Parser, Payload};
+use wasmtime::{ExternType, Module, ValType};
+use waterflow_plugin_interface::manifest::MANIFEST_SECTION;
+
+use crate::module_source::ModuleSource;
+use crate::wasm::engine;
+use crate::Result;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParamManifest {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: String,
+}
+
+/// Description of a function that was exported through `#[waterflow_binding]`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FunctionManifest {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub params: Vec<ParamManifest>,
+    pub returns: String,
+    /// Version of the plugin crate
+    pub version: String,
+    /// Version of the byte layout that the function expects
+    pub abi: u32,
+}
+
+/// Everything that the host can find out about a plugin without running it
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PluginManifest {
+    /// Functions described in the plugin's manifest section
+    pub functions: Vec<FunctionManifest>,
+    /// Exports that have the signature of a runnable function, including the ones without a manifest
+    pub runnable: Vec<String>,
+}
+
+impl PluginManifest {
+    pub fn from_source(source: &ModuleSource) -> Result<Self> {
+        Self::from_bytes(&source.load()?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let module = Module::new(engine(), bytes)?;
+
+        let runnable = module
+            .exports()
+            .filter(|export| match export.ty() {
+                ExternType::Func(func) => {
+                    let params = func.params().collect::<Vec<_>>();
+                    let results = func.results().collect::<Vec<_>>();
+                    matches!(params.as_slice(), [ValType::I32, ValType::I32])
+                        && matches!(results.as_slice(), [ValType::I32])
+                }
+                _ => false,
+            })
+            .map(|export| export.name().to_string())
+            .collect();
+
+        Ok(PluginManifest {
+            functions: Self::read_manifest_section(bytes)?,
+            runnable,
+        })
+    }
+
+    fn read_manifest_section(bytes: &[u8]) -> Result<Vec<FunctionManifest>> {
+        let mut functions = vec![];
+
+        for payload in Parser::new(0).parse_all(bytes) {
+            let Payload::CustomSection(section) = payload? else {
+                continue;
+            };
+            if section.name() != MANIFEST_SECTION {
+                continue;
+            }
+
+            for line in String::from_utf8_lossy(section.data()).lines() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                functions.push(serde_json::from_str(line)?);
+            }
+        }
+
+        Ok(functions)
+    }
+
+    pub fn function(&self, name: &str) -> Option<&FunctionManifest> {
+        self.functions.iter().find(|f| f.name == name)
+    }
+
+    /// Whether the plugin exports a function with this name, that the host can call
+    pub fn can_run(&self, name: &str) -> bool {
+        self.runnable.iter().any(|f| f == name)
+    }
+}
+
+#[test]
+pub fn test_plugin_manifest() {
+    let module = wat::parse_str(
+        r#"
+        (module
+            (memory (export "memory") 1)
+            (func (export "waterflow_alloc") (param i32) (result i32) (i32.const 0))
+            (func (export "reverse_join") (param i32 i32) (result i32) (i32.const 0))
+            (@custom "waterflow_manifest" "{\"name\":\"reverse_join\",\"params\":[{\"name\":\"input\",\"type\":\"Vec<String>\"}],\"returns\":\"String\",\"version\":\"0.1.0\",\"abi\":1}\n"))
+        "#,
+    )
+    .unwrap();
+
+    let manifest = PluginManifest::from_bytes(&module).unwrap();
+
+    assert_eq!(manifest.runnable, ["reverse_join"]);
+    assert!(manifest.can_run("reverse_join"));
+    assert!(!manifest.can_run("waterflow_alloc"));
+
+    let function = manifest.function("reverse_join").unwrap();
+    assert_eq!(function.params[0].ty, "Vec<String>");
+    assert_eq!(function.returns, "String");
+    assert_eq!(function.abi, 1);
+}
@@ -0,0 +1,332 @@
+//! Job metrics in the Prometheus text exposition format.
+//!
+//! The metrics can be scraped from [`Metrics::serve`], or written for node exporter's textfile
+//! collector with [`Metrics::write_textfile`].
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tracing::warn;
+
+use crate::job::JobStatus;
+use crate::Result;
+
+/// Upper bounds of the histogram buckets, in seconds
+const BUCKETS: [f64; 14] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 60.0, 300.0, 3600.0,
+];
+/// How long a scraper gets to send its request, so that idle connections get dropped
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bucket, le) in self.buckets.iter_mut().zip(BUCKETS) {
+            if seconds <= le {
+                *bucket += 1;
+            }
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    started: BTreeMap<String, u64>,
+    succeeded: BTreeMap<String, u64>,
+    failed: BTreeMap<String, u64>,
+    running: u64,
+    durations: BTreeMap<String, Histogram>,
+    queue_waits: BTreeMap<String, Histogram>,
+    wasm_instantiations: BTreeMap<String, Histogram>,
+}
+
+/// Counters and histograms of the jobs that were run, labelled by job type.
+///
+/// Clones share the same values, so one `Metrics` can be handed to several pipelines.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics(Arc<Mutex<Registry>>);
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    fn registry(&self) -> std::sync::MutexGuard<'_, Registry> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Records a job that was started after waiting `queue_wait` for its turn
+    pub(crate) fn job_started(&self, job_type: &str, queue_wait: Duration) {
+        let mut registry = self.registry();
+        *registry.started.entry(job_type.to_string()).or_default() += 1;
+        registry
+            .queue_waits
+            .entry(job_type.to_string())
+            .or_default()
+            .observe(queue_wait);
+        registry.running += 1;
+    }
+
+    /// Records how a started job finished
+    pub(crate) fn job_finished(&self, job_type: &str, status: &JobStatus) {
+        let mut registry = self.registry();
+        let (counter, duration) = match status {
+            JobStatus::Succeeded { duration, .. } => (&mut registry.succeeded, *duration),
+            JobStatus::Failed { duration, .. } => (&mut registry.failed, *duration),
+            _ => return,
+        };
+        *counter.entry(job_type.to_string()).or_default() += 1;
+        registry
+            .durations
+            .entry(job_type.to_string())
+            .or_default()
+            .observe(duration);
+        registry.running = registry.running.saturating_sub(1);
+    }
+
+    /// Records how long it took to compile and instantiate a WASM module or component
+    #[cfg(feature = "wasm")]
+    pub(crate) fn wasm_instantiated(&self, job_type: &str, duration: Duration) {
+        self.registry()
+            .wasm_instantiations
+            .entry(job_type.to_string())
+            .or_default()
+            .observe(duration);
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let registry = self.registry();
+        let mut out = String::new();
+
+        counter(
+            &mut out,
+            "waterflow_jobs_started_total",
+            "Jobs that were started",
+            &registry.started,
+        );
+        counter(
+            &mut out,
+            "waterflow_jobs_succeeded_total",
+            "Jobs that succeeded",
+            &registry.succeeded,
+        );
+        counter(
+            &mut out,
+            "waterflow_jobs_failed_total",
+            "Jobs that failed",
+            &registry.failed,
+        );
+
+        header(
+            &mut out,
+            "waterflow_jobs_running",
+            "Jobs that are currently running",
+            "gauge",
+        );
+        let _ = writeln!(out, "waterflow_jobs_running {}", registry.running);
+
+        histogram(
+            &mut out,
+            "waterflow_job_duration_seconds",
+            "How long the jobs ran",
+            &registry.durations,
+        );
+        histogram(
+            &mut out,
+            "waterflow_job_queue_wait_seconds",
+            "How long runnable jobs waited before they were started",
+            &registry.queue_waits,
+        );
+        histogram(
+            &mut out,
+            "waterflow_wasm_instantiation_seconds",
+            "How long it took to compile and instantiate WASM plugins",
+            &registry.wasm_instantiations,
+        );
+
+        out
+    }
+
+    /// Writes the metrics to a `.prom` file for node exporter's textfile collector
+    pub fn write_textfile(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+
+        // The collector could read a half written file, so the metrics are moved into place
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        std::fs::write(&temp, self.render())?;
+        std::fs::rename(temp, path)?;
+        Ok(())
+    }
+
+    /// Serves the metrics on `/metrics` from a background thread, until the process exits
+    pub fn serve(&self, address: impl ToSocketAddrs) -> Result<SocketAddr> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        let metrics = self.clone();
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let metrics = metrics.clone();
+                // Every connection gets its own thread, so a slow scraper doesn't block the others
+                std::thread::spawn(move || {
+                    if let Err(e) = stream.and_then(|stream| metrics.respond(stream)) {
+                        warn!("Failed to serve the metrics: {e}");
+                    }
+                });
+            }
+        });
+
+        Ok(address)
+    }
+
+    fn respond(&self, mut stream: std::net::TcpStream) -> std::io::Result<()> {
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        // Skip the headers, the request doesn't have a body
+        let mut line = String::new();
+        while reader.read_line(&mut line)? > 2 {
+            line.clear();
+        }
+
+        let path = request.split_whitespace().nth(1).unwrap_or_default();
+        let (status, body) = match path {
+            "/metrics" => ("200 OK", self.render()),
+            _ => ("404 Not Found", "Not found\n".to_string()),
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn counter(out: &mut String, name: &str, help: &str, values: &BTreeMap<String, u64>) {
+    header(out, name, help, "counter");
+    for (job_type, value) in values {
+        let _ = writeln!(out, "{name}{{job_type=\"{}\"}} {value}", escape(job_type));
+    }
+}
+
+fn histogram(out: &mut String, name: &str, help: &str, values: &BTreeMap<String, Histogram>) {
+    header(out, name, help, "histogram");
+    for (job_type, histogram) in values {
+        let job_type = escape(job_type);
+        for (le, count) in BUCKETS.iter().zip(histogram.buckets) {
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{job_type=\"{job_type}\",le=\"{le}\"}} {count}"
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{name}_bucket{{job_type=\"{job_type}\",le=\"+Inf\"}} {}",
+            histogram.count
+        );
+        let _ = writeln!(
+            out,
+            "{name}_sum{{job_type=\"{job_type}\"}} {}",
+            histogram.sum
+        );
+        let _ = writeln!(
+            out,
+            "{name}_count{{job_type=\"{job_type}\"}} {}",
+            histogram.count
+        );
+    }
+}
+
+/// Escapes a label value
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[test]
+pub fn test_metrics() {
+    use crate::job::Job;
+    use crate::job_type::JobType;
+    use crate::pipeline::Pipeline;
+    use std::io::Read;
+
+    let metrics = Metrics::new();
+    let job1 = Job::new("Hello", JobType::new_bash("echo -n Hello"));
+    let job2 = Job::new("Fail", JobType::new_bash("exit 1"));
+    let job3 = Job::from_fn("Native", |_| Ok("done".to_string()));
+    let mut pipeline = Pipeline::new().with_metrics(metrics.clone());
+    pipeline.add_jobs(vec![job1, job2, job3]);
+    smol::block_on(pipeline.execute()).unwrap();
+
+    let rendered = metrics.render();
+    for line in [
+        "# TYPE waterflow_jobs_started_total counter",
+        "waterflow_jobs_started_total{job_type=\"bash\"} 2",
+        "waterflow_jobs_started_total{job_type=\"native\"} 1",
+        "waterflow_jobs_succeeded_total{job_type=\"bash\"} 1",
+        "waterflow_jobs_failed_total{job_type=\"bash\"} 1",
+        "waterflow_jobs_running 0",
+        "# TYPE waterflow_job_duration_seconds histogram",
+        "waterflow_job_duration_seconds_bucket{job_type=\"bash\",le=\"+Inf\"} 2",
+        "waterflow_job_duration_seconds_count{job_type=\"native\"} 1",
+        "waterflow_job_queue_wait_seconds_count{job_type=\"bash\"} 2",
+        "# TYPE waterflow_wasm_instantiation_seconds histogram",
+    ] {
+        assert!(
+            rendered.lines().any(|l| l == line),
+            "{line} is missing from\n{rendered}"
+        );
+    }
+
+    let mut histogram = Histogram::default();
+    histogram.observe(Duration::from_millis(30));
+    assert_eq!(histogram.buckets[..4], [0, 0, 0, 1]);
+    assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+
+    let path = std::env::temp_dir().join(format!("waterflow-{}.prom", uuid::Uuid::new_v4()));
+    metrics.write_textfile(&path).unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), rendered);
+    std::fs::remove_file(path).unwrap();
+
+    let address = metrics.serve("127.0.0.1:0").unwrap();
+    // An idle connection doesn't hold up the scrapes after it
+    let _idle = std::net::TcpStream::connect(address).unwrap();
+    let mut stream = std::net::TcpStream::connect(address).unwrap();
+    stream
+        .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.ends_with(&rendered));
+
+    let mut stream = std::net::TcpStream::connect(address).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 404"));
+}
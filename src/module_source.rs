@@ -0,0 +1,250 @@
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use sha2::{Digest, Sha256};
+
+use crate::error::Error;
+use crate::Result;
+
+/// Where the bytes of a WASM module or component come from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModuleSource {
+    /// Module bytes that are already in memory, for example from `include_bytes!`
+    Bytes(Arc<[u8]>),
+    /// Module on the local file system
+    Path(PathBuf),
+    /// Module in a local plugin store, looked up by its sha256
+    Store { store: PluginStore, sha256: String },
+    /// Module that's fetched over HTTP and has to match the pinned sha256
+    #[cfg(feature = "web")]
+    Url { url: String, sha256: String },
+}
+
+impl ModuleSource {
+    pub fn from_static(bytes: &'static [u8]) -> Self {
+        Self::Bytes(Arc::from(bytes))
+    }
+
+    pub fn from_store(store: &PluginStore, sha256: &str) -> Self {
+        Self::Store {
+            store: store.clone(),
+            sha256: sha256.to_string(),
+        }
+    }
+
+    #[cfg(feature = "web")]
+    pub fn from_url(url: &str, sha256: &str) -> Self {
+        Self::Url {
+            url: url.to_string(),
+            sha256: sha256.to_string(),
+        }
+    }
+
+    /// Reads the module's bytes
+    pub fn load(&self) -> Result<Arc<[u8]>> {
+        match self {
+            ModuleSource::Bytes(bytes) => Ok(bytes.clone()),
+            ModuleSource::Path(path) => Ok(Arc::from(std::fs::read(path)?)),
+            ModuleSource::Store { store, sha256 } => store.get(sha256),
+            #[cfg(feature = "web")]
+            ModuleSource::Url { url, sha256 } => fetch_pinned(url, sha256),
+        }
+    }
+}
+
+impl Display for ModuleSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModuleSource::Bytes(bytes) => write!(f, "<{} bytes>", bytes.len()),
+            ModuleSource::Path(path) => write!(f, "{}", path.display()),
+            ModuleSource::Store { sha256, .. } => write!(f, "sha256:{sha256}"),
+            #[cfg(feature = "web")]
+            ModuleSource::Url { url, .. } => write!(f, "{url}"),
+        }
+    }
+}
+
+impl From<&str> for ModuleSource {
+    fn from(path: &str) -> Self {
+        Self::Path(PathBuf::from(path))
+    }
+}
+
+impl From<PathBuf> for ModuleSource {
+    fn from(path: PathBuf) -> Self {
+        Self::Path(path)
+    }
+}
+
+impl From<&'static [u8]> for ModuleSource {
+    fn from(bytes: &'static [u8]) -> Self {
+        Self::from_static(bytes)
+    }
+}
+
+impl From<Vec<u8>> for ModuleSource {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::Bytes(Arc::from(bytes))
+    }
+}
+
+/// Directory of modules, which are stored under their sha256
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginStore {
+    root: PathBuf,
+}
+
+impl PluginStore {
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        PluginStore {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Only accepts a sha256 in hex, so that it can't point outside of the store
+    fn path(&self, sha256: &str) -> Result<PathBuf> {
+        if sha256.len() != 64 || !sha256.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(Error::InvalidSha256 {
+                sha256: sha256.to_string(),
+            });
+        }
+        Ok(self
+            .root
+            .join(format!("{}.wasm", sha256.to_ascii_lowercase())))
+    }
+
+    /// Stores the module and returns the sha256 that it can be looked up with
+    pub fn insert(&self, bytes: &[u8]) -> Result<String> {
+        let sha256 = sha256(bytes);
+        std::fs::create_dir_all(&self.root)?;
+        std::fs::write(self.path(&sha256)?, bytes)?;
+        Ok(sha256)
+    }
+
+    pub fn contains(&self, sha256: &str) -> bool {
+        self.path(sha256).is_ok_and(|path| path.exists())
+    }
+
+    pub fn get(&self, sha256: &str) -> Result<Arc<[u8]>> {
+        let bytes = std::fs::read(self.path(sha256)?)?;
+        verify(&bytes, sha256)?;
+        Ok(Arc::from(bytes))
+    }
+}
+
+pub fn sha256(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+fn verify(bytes: &[u8], expected: &str) -> Result<()> {
+    let actual = sha256(bytes);
+    if actual.eq_ignore_ascii_case(expected) {
+        Ok(())
+    } else {
+        Err(Error::ModuleHashMismatch {
+            expected: expected.to_string(),
+            actual,
+        })
+    }
+}
+
+#[cfg(feature = "web")]
+fn fetch_pinned(url: &str, sha256: &str) -> Result<Arc<[u8]>> {
+    use std::collections::BTreeMap;
+    use std::io::Read as _;
+    use std::sync::{Mutex, OnceLock};
+    use tracing::trace;
+
+    /// Modules that were already fetched, keyed by their sha256
+    static FETCHED: OnceLock<Mutex<BTreeMap<String, Arc<[u8]>>>> = OnceLock::new();
+
+    let fetched = FETCHED.get_or_init(Default::default);
+    if let Some(bytes) = fetched.lock().unwrap().get(sha256) {
+        return Ok(bytes.clone());
+    }
+
+    trace!("Fetching module from {url}");
+    let mut bytes = vec![];
+    ureq::get(url)
+        .call()?
+        .into_reader()
+        .read_to_end(&mut bytes)?;
+    verify(&bytes, sha256)?;
+
+    let bytes: Arc<[u8]> = Arc::from(bytes);
+    fetched
+        .lock()
+        .unwrap()
+        .insert(sha256.to_string(), bytes.clone());
+
+    Ok(bytes)
+}
+
+#[test]
+pub fn test_plugin_store() {
+    let store = PluginStore::new(std::env::temp_dir().join(uuid::Uuid::new_v4().to_string()));
+
+    let sha256 = store.insert(b"\0asm\x01\0\0\0").unwrap();
+    assert!(store.contains(&sha256));
+
+    let source = ModuleSource::from_store(&store, &sha256);
+    assert_eq!(&*source.load().unwrap(), b"\0asm\x01\0\0\0");
+
+    assert!(store.contains(&sha256.to_uppercase()));
+    assert!(!store.contains("../../etc/passwd"));
+    assert!(matches!(
+        store.get(&format!("../{sha256}")),
+        Err(Error::InvalidSha256 { .. })
+    ));
+
+    std::fs::write(store.path(&sha256).unwrap(), b"tampered").unwrap();
+    assert!(matches!(
+        source.load(),
+        Err(Error::ModuleHashMismatch { .. })
+    ));
+
+    std::fs::remove_dir_all(&store.root).unwrap();
+}
+
+#[test]
+#[cfg(feature = "web")]
+pub fn test_fetch_pinned_module() {
+    use std::io::{Read as _, Write as _};
+    use std::net::TcpListener;
+
+    let module = b"\0asm\x01\0\0\0".to_vec();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/plugin.wasm", listener.local_addr().unwrap());
+
+    let body = module.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            )
+            .unwrap();
+            stream.write_all(&body).unwrap();
+        }
+    });
+
+    let wrong_hash = sha256(b"something else");
+    assert!(matches!(
+        ModuleSource::from_url(&url, &wrong_hash).load(),
+        Err(Error::ModuleHashMismatch { .. })
+    ));
+
+    let loaded = ModuleSource::from_url(&url, &sha256(&module))
+        .load()
+        .unwrap();
+    assert_eq!(&*loaded, module.as_slice());
+}
@@ -0,0 +1,104 @@
+use std::fmt::Debug;
+use std::future::Future;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::Arc;
+
+use crate::error::Error;
+use crate::job::JobContext;
+use crate::Result;
+
+type SyncFn = dyn Fn(&JobContext) -> Result<String> + Send + Sync;
+type BoxedFuture = Pin<Box<dyn Future<Output = Result<String>> + Send>>;
+type AsyncFn = dyn Fn(JobContext) -> BoxedFuture + Send + Sync;
+
+/// Rust closure that runs in-process as a job.
+///
+/// Closures can't be compared, so two native jobs are only equal if they share the same closure.
+#[derive(Clone)]
+pub enum NativeJob {
+    Sync(Arc<SyncFn>),
+    Async(Arc<AsyncFn>),
+}
+
+impl NativeJob {
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&JobContext) -> Result<String> + Send + Sync + 'static,
+    {
+        NativeJob::Sync(Arc::new(f))
+    }
+
+    pub fn new_async<F, Fut>(f: F) -> Self
+    where
+        F: Fn(JobContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<String>> + Send + 'static,
+    {
+        NativeJob::Async(Arc::new(move |context| Box::pin(f(context))))
+    }
+
+    /// Runs the closure on the current thread, blocking on it if it's async.
+    ///
+    /// A panic inside of the closure fails the job, instead of taking the pipeline down with it.
+    pub fn call(&self, context: &JobContext) -> Result<String> {
+        let result = catch_unwind(AssertUnwindSafe(|| match self {
+            NativeJob::Sync(f) => f(context),
+            NativeJob::Async(f) => futures_lite::future::block_on(f(context.clone())),
+        }));
+
+        result.unwrap_or_else(|panic| {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "Unknown panic".to_string());
+            Err(Error::NativePanic { message })
+        })
+    }
+}
+
+impl PartialEq for NativeJob {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (NativeJob::Sync(a), NativeJob::Sync(b)) => Arc::ptr_eq(a, b),
+            (NativeJob::Async(a), NativeJob::Async(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for NativeJob {}
+
+impl Debug for NativeJob {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NativeJob::Sync(_) => write!(f, "NativeJob::Sync(<closure>)"),
+            NativeJob::Async(_) => write!(f, "NativeJob::Async(<closure>)"),
+        }
+    }
+}
+
+#[test]
+pub fn test_native_job() {
+    let context = JobContext {
+        input: vec!["Hello".to_string(), "World!".to_string()],
+        ..Default::default()
+    };
+
+    let join = NativeJob::new(|context| Ok(context.input.join(", ")));
+    assert_eq!(join.call(&context).unwrap(), "Hello, World!");
+    assert_eq!(join, join.clone());
+    assert_ne!(join, NativeJob::new(|context| Ok(context.input.join(", "))));
+
+    let count = NativeJob::new_async(|context| async move {
+        futures_lite::future::yield_now().await;
+        Ok(context.input.len().to_string())
+    });
+    assert_eq!(count.call(&context).unwrap(), "2");
+
+    let panics = NativeJob::new(|_| panic!("Oh no"));
+    assert!(matches!(
+        panics.call(&context),
+        Err(Error::NativePanic { message }) if message == "Oh no"
+    ));
+}
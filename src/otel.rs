@@ -0,0 +1,173 @@
+//! Exports the pipeline and job spans to an OpenTelemetry collector over OTLP/HTTP
+
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{Protocol, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::{Tracer, TracerProvider};
+use opentelemetry_sdk::Resource;
+use tracing::{Level, Subscriber};
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::layer::{Layer, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+
+use crate::error::Error;
+use crate::Result;
+
+/// Sends the finished spans to an OTLP collector, shutting the exporter down when dropped
+#[derive(Debug)]
+pub struct Telemetry {
+    provider: TracerProvider,
+}
+
+impl Telemetry {
+    /// Exports to the collector at `endpoint`, e.g. `http://localhost:4318`
+    pub fn new(endpoint: &str, service_name: &str) -> Result<Self> {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_protocol(Protocol::HttpJson)
+            .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+            .build()
+            .map_err(telemetry_error)?;
+
+        let provider = TracerProvider::builder()
+            .with_simple_exporter(exporter)
+            .with_resource(Resource::new([KeyValue::new(
+                "service.name",
+                service_name.to_string(),
+            )]))
+            .build();
+
+        Ok(Self { provider })
+    }
+
+    /// Layer that hands the `tracing` spans over to the exporter
+    pub fn layer<S>(&self) -> OpenTelemetryLayer<S, Tracer>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        tracing_opentelemetry::layer().with_tracer(self.provider.tracer("waterflow"))
+    }
+
+    /// Exports the spans that haven't been sent yet
+    pub fn flush(&self) -> Result<()> {
+        self.provider
+            .force_flush()
+            .into_iter()
+            .collect::<std::result::Result<(), _>>()
+            .map_err(telemetry_error)
+    }
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        let _ = self.provider.shutdown();
+    }
+}
+
+/// Installs a global subscriber that prints to stderr and exports waterflow's spans to the collector.
+///
+/// Spans of other crates aren't exported, as the exporter's HTTP client would trace its own requests.
+pub fn init(endpoint: &str, service_name: &str) -> Result<Telemetry> {
+    let telemetry = Telemetry::new(endpoint, service_name)?;
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(std::io::stderr)
+                .with_filter(LevelFilter::INFO),
+        )
+        .with(
+            telemetry
+                .layer()
+                .with_filter(Targets::new().with_target("waterflow", Level::TRACE)),
+        )
+        .try_init()
+        .map_err(telemetry_error)?;
+    Ok(telemetry)
+}
+
+fn telemetry_error(e: impl std::fmt::Display) -> Error {
+    Error::Telemetry {
+        message: e.to_string(),
+    }
+}
+
+/// Starts a stand-in OTLP collector that passes the body of every request it receives to the channel
+#[cfg(test)]
+fn start_collector() -> (String, flume::Receiver<String>) {
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    let (tx, rx) = flume::unbounded();
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: 2\r\n\r\n{}")
+                .unwrap();
+            if tx.send(String::from_utf8_lossy(&body).to_string()).is_err() {
+                break;
+            }
+        }
+    });
+
+    (endpoint, rx)
+}
+
+#[test]
+pub fn test_otlp_export() {
+    use crate::job::Job;
+    use crate::job_type::JobType;
+    use crate::pipeline::Pipeline;
+
+    let (endpoint, bodies) = start_collector();
+    let telemetry = Telemetry::new(&endpoint, "waterflow-test").unwrap();
+    let subscriber = tracing_subscriber::registry().with(telemetry.layer());
+
+    let job1 = Job::new("Hello", JobType::new_bash("echo -n Hello"));
+    let mut job2 = Job::new("World", JobType::new_bash("echo -n '{INPUT} World'"));
+    job2.add_dependency(job1.get_id());
+
+    let mut pipeline = Pipeline::new();
+    pipeline.add_jobs(vec![job1, job2]);
+    tracing::subscriber::with_default(subscriber, || {
+        smol::block_on(pipeline.execute()).unwrap();
+    });
+    telemetry.flush().unwrap();
+
+    let mut exported = String::new();
+    while let Ok(body) = bodies.recv_timeout(std::time::Duration::from_secs(5)) {
+        exported.push_str(&body);
+        if exported.contains("\"pipeline\"") {
+            break;
+        }
+    }
+
+    assert!(exported.contains("waterflow-test"));
+    assert!(exported.contains("\"Hello\""));
+    assert!(exported.contains("\"World\""));
+    assert!(exported.contains("\"bash\""));
+    assert!(exported.contains("\"succeeded\""));
+    assert!(exported.contains(&pipeline.run_id().to_string()));
+}
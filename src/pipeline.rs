@@ -1,11 +1,124 @@
-use crate::job::{Job, JobStatus};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::{Instant, SystemTime};
+
+use crate::definition::{JobDefinition, PipelineDefinition};
+use crate::error::Error;
+use crate::event::PipelineEvent;
+use crate::executor::{ExecutorRegistry, JobExecutor};
+use crate::job::{Job, JobContext, JobLogs, JobRun, JobStatus, Selector};
+use crate::metrics::Metrics;
+use crate::pipeline_tree::PipelineTree;
+use crate::remote::Coordinator;
+use crate::state::{JobRecord, JobState, RunRecord, StateStore};
 use crate::Result;
-use tracing::trace;
+use futures_lite::future;
+use sha2::{Digest, Sha256};
+use tracing::{field, info_span, trace, warn, Instrument};
 use uuid::Uuid;
 
-#[derive(Debug, Default, Clone)]
+/// Job referred to by its id or by its name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobRef {
+    Id(Uuid),
+    Name(String),
+}
+
+impl From<Uuid> for JobRef {
+    fn from(value: Uuid) -> Self {
+        JobRef::Id(value)
+    }
+}
+
+impl From<&str> for JobRef {
+    fn from(value: &str) -> Self {
+        JobRef::Name(value.to_string())
+    }
+}
+
+impl From<String> for JobRef {
+    fn from(value: String) -> Self {
+        JobRef::Name(value)
+    }
+}
+
+impl Display for JobRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobRef::Id(id) => write!(f, "{id}"),
+            JobRef::Name(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+/// Where a job runs, which is decided by its [Selector]
+enum Placement {
+    Local,
+    Executor(Arc<dyn JobExecutor>),
+    Remote(Coordinator),
+}
+
+/// Stops a running pipeline before it starts its next job, and interrupts the jobs that are running
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle(Arc<AtomicBool>);
+
+impl CancelHandle {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Lets the pipeline run again
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Pipeline {
     pub(crate) jobs: Vec<Job>,
+    /// Executors for the job types that aren't built in
+    pub(crate) executors: ExecutorRegistry,
+    /// Id under which the jobs' states are checkpointed
+    run_id: Uuid,
+    /// When the run first started executing jobs
+    run_started_at: Option<SystemTime>,
+    /// State of the last run, which stays running when it stopped with an error
+    run_state: Option<JobState>,
+    /// Hash of the definition that the run started with
+    run_definition_hash: Option<String>,
+    state_store: Option<Arc<dyn StateStore>>,
+    metrics: Option<Metrics>,
+    listeners: Vec<flume::Sender<PipelineEvent>>,
+    cancel: CancelHandle,
+    /// Hands the jobs to remote workers instead of running them locally
+    coordinator: Option<Coordinator>,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Pipeline {
+            jobs: vec![],
+            executors: ExecutorRegistry::default(),
+            run_id: Uuid::new_v4(),
+            run_started_at: None,
+            run_state: None,
+            run_definition_hash: None,
+            state_store: None,
+            metrics: None,
+            listeners: vec![],
+            cancel: CancelHandle::default(),
+            coordinator: None,
+        }
+    }
 }
 
 impl Pipeline {
@@ -13,6 +126,62 @@ impl Pipeline {
         Pipeline::default()
     }
 
+    pub fn run_id(&self) -> Uuid {
+        self.run_id
+    }
+
+    /// How the last run ended, or [`JobState::Running`] while it runs or when it stopped with an error
+    pub fn run_state(&self) -> Option<JobState> {
+        self.run_state
+    }
+
+    /// Checkpoints every change of a job's status to the store
+    pub fn with_state_store(mut self, state_store: impl StateStore + 'static) -> Self {
+        self.set_state_store(state_store);
+        self
+    }
+
+    pub fn set_state_store(&mut self, state_store: impl StateStore + 'static) {
+        self.state_store = Some(Arc::new(state_store));
+    }
+
+    /// Records the jobs' counters and timings into the metrics
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.set_metrics(metrics);
+        self
+    }
+
+    pub fn set_metrics(&mut self, metrics: Metrics) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Runs the jobs that select a worker on the coordinator's workers
+    pub fn with_coordinator(mut self, coordinator: Coordinator) -> Self {
+        self.set_coordinator(coordinator);
+        self
+    }
+
+    pub fn set_coordinator(&mut self, coordinator: Coordinator) {
+        self.coordinator = Some(coordinator);
+    }
+
+    /// Receives the changes of the run's and jobs' states, and the lines that the jobs log
+    pub fn subscribe(&mut self) -> flume::Receiver<PipelineEvent> {
+        let (tx, rx) = flume::unbounded();
+        self.listeners.push(tx);
+        rx
+    }
+
+    /// Stops sending events, which ends the iterators of the receivers
+    pub fn unsubscribe(&mut self) {
+        self.listeners.clear();
+    }
+
+    /// Cancelling stops the run before its next job and interrupts the Bash and WASM jobs that are running
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.cancel.clone()
+    }
+
     pub fn add_job(&mut self, job: Job) {
         self.jobs.push(job);
     }
@@ -21,6 +190,107 @@ impl Pipeline {
         self.jobs.append(&mut jobs)
     }
 
+    pub fn with_executor(mut self, executor: impl JobExecutor + 'static) -> Self {
+        self.register_executor(executor);
+        self
+    }
+
+    pub fn register_executor(&mut self, executor: impl JobExecutor + 'static) {
+        self.executors.register(executor);
+    }
+
+    /// Adds the jobs of the definition.
+    ///
+    /// Dependencies are looked up by name, among the definition's jobs and the ones that are
+    /// already in the pipeline.
+    pub fn load_definition(&mut self, definition: &PipelineDefinition) -> Result<()> {
+        let mut jobs = definition
+            .jobs
+            .iter()
+            .map(|job| job.to_job(&self.executors))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut ids = BTreeMap::new();
+        for job in self.jobs.iter().chain(&jobs) {
+            if ids.insert(job.name.clone(), job.get_id()).is_some() {
+                return Err(Error::Definition {
+                    message: format!("Job name {} is used more than once", job.name),
+                });
+            }
+        }
+
+        for (job, job_definition) in jobs.iter_mut().zip(&definition.jobs) {
+            for dependency in &job_definition.depends_on {
+                let Some(id) = ids.get(dependency) else {
+                    return Err(Error::Definition {
+                        message: format!("Job {} depends on unknown job {dependency}", job.name),
+                    });
+                };
+                if *id == job.get_id() {
+                    return Err(Error::Definition {
+                        message: format!("Job {} depends on itself", job.name),
+                    });
+                }
+                job.add_dependency(*id);
+            }
+        }
+
+        self.add_jobs(jobs);
+        Ok(())
+    }
+
+    /// Describes the pipeline in the declarative format
+    pub fn to_definition(&self) -> Result<PipelineDefinition> {
+        let names = self
+            .jobs
+            .iter()
+            .map(|job| (job.get_id(), job.name.clone()))
+            .collect::<BTreeMap<_, _>>();
+        if let Some(job) = self
+            .jobs
+            .iter()
+            .find(|job| self.jobs.iter().filter(|j| j.name == job.name).count() > 1)
+        {
+            return Err(Error::Definition {
+                message: format!("Job name {} is used more than once", job.name),
+            });
+        }
+
+        let jobs = self
+            .jobs
+            .iter()
+            .map(|job| {
+                let depends_on = job
+                    .dependencies
+                    .iter()
+                    .filter_map(|dependency| names.get(dependency).cloned())
+                    .collect();
+                JobDefinition::from_job(job, depends_on)
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(PipelineDefinition { jobs })
+    }
+
+    /// Looks up the job, which fails when its name is used by more than one job
+    pub fn find_job(&self, job: impl Into<JobRef>) -> Result<Uuid> {
+        let job = job.into();
+        let mut found = self.jobs.iter().filter(|j| match &job {
+            JobRef::Id(id) => j.get_id() == *id,
+            JobRef::Name(name) => j.name == *name,
+        });
+
+        match (found.next(), found.next()) {
+            (Some(found), None) => Ok(found.get_id()),
+            (None, _) => Err(Error::UnknownJob {
+                job: job.to_string(),
+            }),
+            (Some(_), Some(_)) => Err(Error::Definition {
+                message: format!("Job name {job} is used more than once"),
+            }),
+        }
+    }
+
     pub fn get_job_statuses(&self) -> Vec<(Uuid, JobStatus)> {
         self.jobs
             .iter()
@@ -28,19 +298,23 @@ impl Pipeline {
             .collect::<Vec<_>>()
     }
 
-    /// Gets the tasks that can currently be run and haven't been run yet
-    fn get_runnable_jobs(jobs: &[Job]) -> Vec<Uuid> {
+    /// Gets the selected tasks that can currently be run and haven't been run yet
+    fn get_runnable_jobs(jobs: &[Job], selected: &BTreeSet<Uuid>) -> Vec<Uuid> {
         jobs.iter()
             // Only check the waiting jobs
             .filter(|j| j.get_status() == JobStatus::Waiting)
+            .filter(|j| selected.contains(&j.get_id()))
             // Only keep the jobs that can be executed
             .filter(|j| j.can_execute(jobs))
             .map(|j| j.get_id())
             .collect::<Vec<_>>()
     }
 
-    fn all_jobs_completed(jobs: &[Job]) -> bool {
-        jobs.iter().all(|j| !j.get_status().is_running())
+    /// Gets a selected job that's marked as running, which a previous run may have left behind
+    fn get_running_job<'a>(jobs: &'a [Job], selected: &BTreeSet<Uuid>) -> Option<&'a Job> {
+        jobs.iter()
+            .filter(|j| selected.contains(&j.get_id()))
+            .find(|j| j.get_status().is_running())
     }
 
     fn get_mut_job(&mut self, job_id: Uuid) -> &mut Job {
@@ -50,55 +324,421 @@ impl Pipeline {
             .expect("Tried to get a job from a job_id, which was gotten from the jobs")
     }
 
-    fn get_job(&self, job_id: Uuid) -> &Job {
+    pub(crate) fn get_job(&self, job_id: Uuid) -> &Job {
         self.jobs
             .iter()
             .find(|j| j.get_id() == job_id)
             .expect("Tried to get a job from a job_id, which was gotten from the jobs")
     }
 
+    /// When the job's last dependency finished, or `fallback` if it didn't have to wait for one
+    fn ready_at(&self, job_id: Uuid, fallback: Instant) -> Instant {
+        self.get_job(job_id)
+            .dependencies
+            .iter()
+            .map(|dep| self.get_job(*dep))
+            .filter_map(|dep| Some(dep.started_at? + dep.get_duration()?))
+            .fold(fallback, Instant::max)
+    }
+
+    /// The job's fixed inputs, followed by the outputs of its dependencies
     fn get_dep_inputs(&self, job_id: Uuid) -> Vec<String> {
         let job = self.get_job(job_id);
-        job.dependencies
+        let outputs = job.dependencies.iter().map(|dep| {
+            let job_status = self.get_job(*dep).get_status();
+            let JobStatus::Succeeded { msg, duration: _ } = job_status else {
+                panic!("Tried to read output from a parent dependency that hasn't succeeded?");
+            };
+            msg
+        });
+        job.fixed_input.iter().cloned().chain(outputs).collect()
+    }
+
+    /// Validates every job, so that the pipeline fails before running any of them
+    pub fn validate(&self) -> Result<()> {
+        self.validate_jobs(&self.job_ids())
+    }
+
+    fn validate_jobs(&self, selected: &BTreeSet<Uuid>) -> Result<()> {
+        PipelineTree::new(self).topological_order()?;
+        self.jobs
             .iter()
-            .map(|dep| {
-                let job_status = self.get_job(*dep).get_status();
-                let JobStatus::Succeeded { msg, duration: _ } = job_status else {
-                    panic!("Tried to read output from a parent dependency that hasn't succeeded?");
-                };
-                msg
+            .filter(|j| selected.contains(&j.get_id()))
+            .try_for_each(|j| {
+                self.placement(j)?;
+                j.job_type.validate()
             })
-            .collect::<Vec<String>>()
+    }
+
+    /// Where the job runs, as its selector and the pipeline's coordinator allow
+    fn placement(&self, job: &Job) -> Result<Placement> {
+        match (&job.selector, &self.coordinator) {
+            (Some(Selector::Local) | None, _) => Ok(Placement::Local),
+            (Some(Selector::Executor(name)), _) => match self.executors.get(name) {
+                Some(executor) => Ok(Placement::Executor(executor)),
+                None => Err(Error::UnknownExecutor {
+                    job: job.name.clone(),
+                    name: name.clone(),
+                }),
+            },
+            (Some(Selector::Worker(_)), Some(coordinator)) => {
+                Ok(Placement::Remote(coordinator.clone()))
+            }
+            (Some(Selector::Worker(_)), None) => Err(Error::NoCoordinator {
+                job: job.name.clone(),
+            }),
+        }
+    }
+
+    /// Jobs that have any of the tags, in the order in which they were added
+    pub fn tagged<T: AsRef<str>>(&self, tags: &[T]) -> Vec<Uuid> {
+        self.jobs
+            .iter()
+            .filter(|job| tags.iter().any(|tag| job.has_tag(tag.as_ref())))
+            .map(|job| job.get_id())
+            .collect()
+    }
+
+    fn job_ids(&self) -> BTreeSet<Uuid> {
+        self.jobs.iter().map(|j| j.get_id()).collect()
+    }
+
+    /// Puts the jobs back in the waiting state, so that they run again
+    fn reset_jobs(&mut self, selected: &BTreeSet<Uuid>) -> Result<()> {
+        for job in self
+            .jobs
+            .iter_mut()
+            .filter(|j| selected.contains(&j.get_id()))
+        {
+            job.set_status(&JobStatus::Waiting);
+            job.set_output("");
+        }
+        selected
+            .iter()
+            .try_for_each(|job_id| self.checkpoint(*job_id))
+    }
+
+    /// Sha256 of the pipeline's definition, if it can be described by one
+    pub fn definition_hash(&self) -> Option<String> {
+        let json = self.to_definition().ok()?.to_json().ok()?;
+        Some(format!("{:x}", Sha256::digest(json)))
+    }
+
+    fn emit(&self, event: PipelineEvent) {
+        for listener in &self.listeners {
+            let _ = listener.send(event.clone());
+        }
+    }
+
+    fn save_run(&mut self, state: JobState) -> Result<()> {
+        self.run_state = Some(state);
+        let run = RunRecord {
+            run_id: self.run_id,
+            started_at: *self.run_started_at.get_or_insert_with(SystemTime::now),
+            finished_at: (state != JobState::Running).then(SystemTime::now),
+            definition_hash: self.run_definition_hash.clone(),
+            state,
+        };
+        self.emit(PipelineEvent::Run { run: run.clone() });
+        match &self.state_store {
+            Some(store) => store.save_run(&run),
+            None => Ok(()),
+        }
+    }
+
+    fn checkpoint(&self, job_id: Uuid) -> Result<()> {
+        let job = JobRecord::from_job(self.get_job(job_id));
+        self.emit(PipelineEvent::Job {
+            run_id: self.run_id,
+            job: job.clone(),
+        });
+        match &self.state_store {
+            Some(store) => store.save_job(self.run_id, &job),
+            None => Ok(()),
+        }
+    }
+
+    /// Context for running the job, which passes its logs on to the listeners as they come in
+    fn job_context(&self, job_id: Uuid) -> JobContext {
+        let run_id = self.run_id;
+        let listeners = self.listeners.clone();
+        let logs = if listeners.is_empty() {
+            JobLogs::default()
+        } else {
+            JobLogs::with_listener(move |line| {
+                for listener in &listeners {
+                    let _ = listener.send(PipelineEvent::Log {
+                        run_id,
+                        job_id,
+                        line: line.to_string(),
+                    });
+                }
+            })
+        };
+
+        JobContext {
+            logs,
+            metrics: self.metrics.clone(),
+            cancel: self.cancel.clone(),
+            ..self.get_job(job_id).context()
+        }
     }
 
     pub async fn execute(&mut self) -> Result<()> {
-        loop {
-            let runnable_jobs = Pipeline::get_runnable_jobs(&self.jobs);
+        self.validate()?;
+        self.run(&self.job_ids()).await
+    }
 
-            // If we don't have any more jobs to run and all of the jobs that we have been waiting for have completed, stop executing
-            if runnable_jobs.is_empty() && Pipeline::all_jobs_completed(&self.jobs) {
-                trace!("We ran out of jobs to run");
-                break;
+    /// Runs the targets and everything they transitively depend on, like `make target`.
+    ///
+    /// These jobs run again even when they already succeeded, the other jobs are left alone.
+    pub async fn execute_targets<T: Into<JobRef>>(
+        &mut self,
+        targets: impl IntoIterator<Item = T>,
+    ) -> Result<()> {
+        let tree = PipelineTree::new(self);
+        let mut selected = BTreeSet::new();
+        for target in targets {
+            let target = self.find_job(target)?;
+            selected.extend(tree.ancestors(target));
+            selected.insert(target);
+        }
+
+        self.validate_jobs(&selected)?;
+        self.reset_jobs(&selected)?;
+        self.run(&selected).await
+    }
+
+    /// Reruns the job and everything that depends on it.
+    ///
+    /// The jobs before it aren't run again, their stored outputs are reused instead.
+    pub async fn execute_from(&mut self, job: impl Into<JobRef>) -> Result<()> {
+        let start = self.find_job(job)?;
+        let mut selected = PipelineTree::new(self).descendants(start);
+        selected.insert(start);
+
+        for job in self.jobs.iter().filter(|j| selected.contains(&j.get_id())) {
+            for dependency in &job.dependencies {
+                if selected.contains(dependency) {
+                    continue;
+                }
+                let dependency = self.jobs.iter().find(|j| j.get_id() == *dependency);
+                if !dependency.is_some_and(|d| d.get_status().is_succeeded()) {
+                    return Err(Error::MissingOutput {
+                        job: job.name.clone(),
+                        dependency: dependency.map(|d| d.name.clone()).unwrap_or_default(),
+                    });
+                }
             }
+        }
+
+        self.validate_jobs(&selected)?;
+        self.reset_jobs(&selected)?;
+        self.run(&selected).await
+    }
+
+    /// Continues a run that was checkpointed to the state store.
+    ///
+    /// Jobs that succeeded keep their stored output, the others run again. Stored jobs are matched
+    /// by their id, or by their name when the pipeline was rebuilt, in which case the jobs take
+    /// over the stored ids.
+    pub async fn resume(&mut self, run_id: Uuid) -> Result<()> {
+        let store = self.state_store.clone().ok_or(Error::NoStateStore)?;
+        let run = store.load_run(run_id)?;
+        let records = store.load_jobs(run_id)?;
+        if run.is_none() && records.is_empty() {
+            return Err(Error::UnknownRun { run_id });
+        }
+
+        self.validate()?;
+        self.run_id = run_id;
+        self.run_started_at = run.map(|run| run.started_at);
+
+        let renamed = self
+            .jobs
+            .iter()
+            .filter(|job| !records.iter().any(|r| r.job_id == job.get_id()))
+            .filter_map(|job| {
+                let record = records.iter().find(|r| r.name == job.name)?;
+                Some((job.get_id(), record.job_id))
+            })
+            .collect::<BTreeMap<_, _>>();
+        for job in &mut self.jobs {
+            job.job_id = renamed.get(&job.job_id).copied().unwrap_or(job.job_id);
+            for dependency in &mut job.dependencies {
+                *dependency = renamed.get(dependency).copied().unwrap_or(*dependency);
+            }
+        }
+
+        for job in &mut self.jobs {
+            let record = records.iter().find(|r| r.job_id == job.get_id());
+            match record {
+                Some(record) if record.state == JobState::Succeeded => record.restore(job),
+                _ => {
+                    job.attempts = record.map_or(0, |record| record.attempts);
+                    job.set_status(&JobStatus::Waiting);
+                    job.set_output("");
+                }
+            }
+        }
+
+        self.run(&self.job_ids()).await
+    }
+
+    async fn run(&mut self, selected: &BTreeSet<Uuid>) -> Result<()> {
+        let span = info_span!(
+            "pipeline",
+            run_id = %self.run_id,
+            jobs = selected.len(),
+            status = field::Empty,
+        );
+        self.run_definition_hash = self.definition_hash();
+        self.save_run(JobState::Running)?;
+        let state = match self.run_jobs(selected).instrument(span.clone()).await {
+            Err(Error::Cancelled) => JobState::Cancelled,
+            Err(e) => {
+                span.record("status", JobState::Failed.as_str());
+                self.save_run(JobState::Failed)?;
+                return Err(e);
+            }
+            Ok(())
+                if self
+                    .jobs
+                    .iter()
+                    .filter(|j| selected.contains(&j.get_id()))
+                    .all(|j| j.get_status().is_succeeded()) =>
+            {
+                JobState::Succeeded
+            }
+            Ok(()) => JobState::Failed,
+        };
+        span.record("status", state.as_str());
+        self.save_run(state)?;
+
+        match state {
+            JobState::Cancelled => Err(Error::Cancelled),
+            _ => Ok(()),
+        }
+    }
+
+    async fn run_jobs(&mut self, selected: &BTreeSet<Uuid>) -> Result<()> {
+        let mut running: Vec<RunningJob> = vec![];
+        let result = self.schedule_jobs(selected, &mut running).await;
+
+        // The jobs that already started still get to finish, so that none stays marked as running
+        if result.is_err() {
+            while !running.is_empty() {
+                let (job_id, run) = next_finished(&mut running).await;
+                self.finish_job(job_id, run);
+                if let Err(e) = self.checkpoint(job_id) {
+                    warn!("Failed to save the state of job {job_id}! {e}");
+                }
+            }
+        }
+        result
+    }
+
+    /// Starts the jobs as they become runnable, until none is left or one of them can't be started
+    async fn schedule_jobs(
+        &mut self,
+        selected: &BTreeSet<Uuid>,
+        running: &mut Vec<RunningJob>,
+    ) -> Result<()> {
+        let started_at = Instant::now();
+        let metrics = self.metrics.clone();
 
+        loop {
+            // Jobs that are running get interrupted, so only wait for them to wrap up
+            let cancelled = self.cancel.is_cancelled();
+            let runnable_jobs = if cancelled {
+                vec![]
+            } else {
+                Pipeline::get_runnable_jobs(&self.jobs, selected)
+            };
             trace!("Running the following jobs: {:?}", runnable_jobs);
 
-            // TODO: Make this be paralelizable
-            // TODO: Make this run an even when a job completes
             for job_id in runnable_jobs {
+                let placement = self.placement(self.get_job(job_id))?;
                 let inputs = self.get_dep_inputs(job_id);
+                let queue_wait = self.ready_at(job_id, started_at).elapsed();
                 let job = self.get_mut_job(job_id);
-                trace!("Executing: {:?}", job.name);
+                trace!("Executing: {:?} ({})", job.name, job.job_type.describe());
 
                 job.set_input(inputs);
+                job.start();
+                if let Some(metrics) = &metrics {
+                    metrics.job_started(job.job_type.kind(), queue_wait);
+                }
 
-                job.execute().await?;
+                let context = self.job_context(job_id);
+                let job = self.get_job(job_id);
+                let run: Pin<Box<dyn Future<Output = JobRun> + Send>> = match placement {
+                    Placement::Local => Box::pin(job.run(context)),
+                    Placement::Executor(executor) => Box::pin(job.run_on(context, executor)),
+                    Placement::Remote(coordinator) => {
+                        Box::pin(job.run_remote(context, coordinator))
+                    }
+                };
+                running.push(Box::pin(async move { (job_id, run.await) }));
+                self.checkpoint(job_id)?;
+            }
 
-                trace!("Finished: {:?}", job.name);
+            // If no job is running, there's nothing left that could make another job runnable
+            if running.is_empty() {
+                if cancelled {
+                    return Err(Error::Cancelled);
+                }
+                // A job that's marked as running would never finish, as it was started by
+                // a previous run
+                if let Some(job) = Pipeline::get_running_job(&self.jobs, selected) {
+                    return Err(Error::JobStuck {
+                        job: job.name.clone(),
+                    });
+                }
+                trace!("We ran out of jobs to run");
+                return Ok(());
             }
+
+            let (job_id, run) = next_finished(running).await;
+            self.finish_job(job_id, run);
+            self.checkpoint(job_id)?;
         }
-        Ok(())
     }
+
+    fn finish_job(&mut self, job_id: Uuid, run: JobRun) {
+        let metrics = self.metrics.clone();
+        let job = self.get_mut_job(job_id);
+        let status = job.finish(run);
+        if let Some(metrics) = &metrics {
+            metrics.job_finished(job.job_type.kind(), &status);
+        }
+        trace!("Finished: {:?}", job.name);
+    }
+}
+
+/// Job that was started by [Pipeline::run_jobs], which resolves to its id once it finishes
+type RunningJob = Pin<Box<dyn Future<Output = (Uuid, JobRun)> + Send>>;
+
+/// Waits for whichever of the running jobs finishes first, and removes it
+async fn next_finished(running: &mut Vec<RunningJob>) -> (Uuid, JobRun) {
+    future::poll_fn(|cx| {
+        let finished =
+            running
+                .iter_mut()
+                .enumerate()
+                .find_map(|(i, job)| match job.as_mut().poll(cx) {
+                    Poll::Ready(finished) => Some((i, finished)),
+                    Poll::Pending => None,
+                });
+        match finished {
+            Some((i, finished)) => {
+                drop(running.swap_remove(i));
+                Poll::Ready(finished)
+            }
+            None => Poll::Pending,
+        }
+    })
+    .await
 }
 
 #[test]
@@ -141,6 +781,484 @@ pub fn test_pipeline_execution() {
     assert_eq!(job3.output, "Hello World!");
 }
 
+#[test]
+pub fn test_native_pipeline() {
+    let job1 = Job::from_fn("Hello", |_| Ok("Hello".to_string()));
+    let job2 = Job::from_async_fn("World", |_| async { Ok("World!".to_string()) });
+    let mut job3 = Job::from_fn("Shout", |context| {
+        Ok(context.input.join(" ").to_uppercase())
+    });
+
+    job3.add_dependency(job1.get_id());
+    job3.add_dependency(job2.get_id());
+    let job3_id = job3.get_id();
+
+    let mut pipeline = Pipeline::new();
+    pipeline.add_jobs(vec![job1, job2, job3]);
+
+    smol::block_on(pipeline.execute()).unwrap();
+
+    assert_eq!(pipeline.get_job(job3_id).output, "HELLO WORLD!");
+}
+
+#[test]
+pub fn test_execute_subsets() {
+    use std::sync::{Arc, Mutex};
+
+    let runs = Arc::new(Mutex::new(vec![]));
+    let job = |name: &'static str| {
+        let runs = runs.clone();
+        Job::from_fn(name, move |context| {
+            runs.lock().unwrap().push(name);
+            Ok(format!("{name}({})", context.input.join(",")))
+        })
+    };
+
+    //   top
+    //  /   \
+    // left right   lone
+    //  \   /
+    //  bottom
+    let top = job("Top");
+    let mut left = job("Left");
+    let mut right = job("Right");
+    let mut bottom = job("Bottom");
+    let lone = job("Lone");
+    left.add_dependency(top.get_id());
+    right.add_dependency(top.get_id());
+    bottom.add_dependency(left.get_id());
+    bottom.add_dependency(right.get_id());
+    let bottom_id = bottom.get_id();
+
+    let mut pipeline = Pipeline::new();
+    pipeline.add_jobs(vec![top, left, right, bottom, lone]);
+
+    assert!(matches!(
+        smol::block_on(pipeline.execute_targets(["Missing"])),
+        Err(Error::UnknownJob { job }) if job == "Missing"
+    ));
+    assert!(matches!(
+        smol::block_on(pipeline.execute_from("Bottom")),
+        Err(Error::MissingOutput { job, dependency }) if job == "Bottom" && dependency == "Left"
+    ));
+    assert!(runs.lock().unwrap().is_empty());
+
+    smol::block_on(pipeline.execute_targets(["Left"])).unwrap();
+    assert_eq!(*runs.lock().unwrap(), ["Top", "Left"]);
+    assert!(pipeline.get_job(bottom_id).get_status().is_waiting());
+
+    smol::block_on(pipeline.execute_targets([bottom_id])).unwrap();
+    assert_eq!(
+        runs.lock().unwrap()[2..],
+        ["Top", "Left", "Right", "Bottom"]
+    );
+
+    runs.lock().unwrap().clear();
+    smol::block_on(pipeline.execute_from("Right")).unwrap();
+    assert_eq!(*runs.lock().unwrap(), ["Right", "Bottom"]);
+    assert_eq!(
+        pipeline.get_job(bottom_id).output,
+        "Bottom(Left(Top()),Right(Top()))"
+    );
+    assert!(pipeline
+        .jobs
+        .iter()
+        .find(|j| j.name == "Lone")
+        .unwrap()
+        .get_status()
+        .is_waiting());
+}
+
+#[test]
+pub fn test_selectors_and_tags() {
+    use crate::executor::JobConfig;
+    use crate::job_type::JobType;
+
+    /// Runs any job by describing it
+    #[derive(Debug)]
+    struct Sandbox;
+
+    impl JobExecutor for Sandbox {
+        fn kind(&self) -> &str {
+            "sandbox"
+        }
+
+        fn execute(&self, _config: &JobConfig, _context: &JobContext) -> Result<String> {
+            Ok(String::new())
+        }
+
+        fn execute_job(&self, job: &JobDefinition, _context: &JobContext) -> Result<String> {
+            Ok(format!("sandboxed {} {}", job.kind, job.config["command"]))
+        }
+    }
+
+    let lint = Job::new("Lint", JobType::new_bash("echo -n linted"))
+        .with_tag("lint")
+        .with_selector(Selector::Local);
+    let unit = Job::new("Unit", JobType::new_bash("echo -n unit"))
+        .with_tag("test")
+        .with_selector(Selector::Executor("sandbox".to_string()));
+    let mut integration = Job::new("Integration", JobType::new_bash("echo -n integration"))
+        .with_tag("test")
+        .with_tag("slow");
+    integration.add_dependency(lint.get_id());
+    let deploy = Job::new("Deploy", JobType::new_bash("echo -n deployed"))
+        .with_selector(Selector::Worker(vec!["production".to_string()]));
+    let ids = [&lint, &unit, &integration, &deploy].map(|job| job.get_id());
+
+    let mut pipeline = Pipeline::new();
+    pipeline.add_jobs(vec![lint, unit, integration, deploy]);
+    assert_eq!(pipeline.tagged(&["test"]), [ids[1], ids[2]]);
+    assert_eq!(pipeline.tagged(&["lint", "slow"]), [ids[0], ids[2]]);
+    assert!(pipeline.tagged(&["docs"]).is_empty());
+
+    assert!(matches!(
+        pipeline.validate(),
+        Err(Error::UnknownExecutor { job, name }) if job == "Unit" && name == "sandbox"
+    ));
+    pipeline.register_executor(Sandbox);
+    assert!(matches!(
+        pipeline.validate(),
+        Err(Error::NoCoordinator { job }) if job == "Deploy"
+    ));
+
+    // Deploy isn't selected, so it doesn't need a worker
+    let tests = pipeline.tagged(&["test"]);
+    smol::block_on(pipeline.execute_targets(tests)).unwrap();
+    assert_eq!(pipeline.get_job(ids[0]).output, "linted");
+    assert_eq!(
+        pipeline.get_job(ids[1]).output,
+        "sandboxed bash echo -n unit"
+    );
+    assert_eq!(pipeline.get_job(ids[2]).output, "integration");
+    assert!(pipeline.get_job(ids[3]).get_status().is_waiting());
+
+    let definition = pipeline.to_definition().unwrap();
+    assert_eq!(definition.jobs[1].tags, ["test"]);
+    let json = definition.to_json().unwrap();
+    assert!(json.contains("\"selector\": \"local\""));
+    assert!(json.contains("\"executor\": \"sandbox\""));
+    let mut reloaded = Pipeline::new().with_executor(Sandbox);
+    reloaded
+        .load_definition(&PipelineDefinition::from_json(&json).unwrap())
+        .unwrap();
+    assert_eq!(reloaded.to_definition().unwrap(), definition);
+}
+
+#[test]
+pub fn test_unfinished_jobs() {
+    use crate::executor::JobConfig;
+    use crate::job_type::JobType;
+
+    #[derive(Debug)]
+    struct Panicking;
+
+    impl JobExecutor for Panicking {
+        fn kind(&self) -> &str {
+            "panicking"
+        }
+
+        fn execute(&self, _config: &JobConfig, _context: &JobContext) -> Result<String> {
+            panic!("the executor broke");
+        }
+    }
+
+    let panicking = Job::new("Panicking", JobType::Noop)
+        .with_selector(Selector::Executor("panicking".to_string()));
+    let mut after = Job::new("After", JobType::Noop);
+    after.add_dependency(panicking.get_id());
+    let (panicking_id, after_id) = (panicking.get_id(), after.get_id());
+
+    let mut pipeline = Pipeline::new().with_executor(Panicking);
+    pipeline.add_jobs(vec![panicking, after]);
+    smol::block_on(pipeline.execute()).unwrap();
+    assert!(matches!(
+        pipeline.get_job(panicking_id).get_status(),
+        JobStatus::Failed { msg, .. } if msg == "The job's executor panicked"
+    ));
+    assert!(pipeline.get_job(after_id).get_status().is_waiting());
+    assert_eq!(pipeline.run_state(), Some(JobState::Failed));
+
+    // A job that a previous run left running doesn't hold up the others, but isn't waited on
+    let mut stale = Job::new("Stale", JobType::Noop);
+    stale.set_status(&JobStatus::InProgress {
+        started_at: Instant::now(),
+    });
+    let fresh = Job::new("Fresh", JobType::Noop);
+    let fresh_id = fresh.get_id();
+
+    let mut pipeline = Pipeline::new();
+    pipeline.add_jobs(vec![stale, fresh]);
+    smol::block_on(pipeline.execute_from(fresh_id)).unwrap();
+    assert!(pipeline.get_job(fresh_id).get_status().is_succeeded());
+
+    let result = smol::block_on(pipeline.execute());
+    assert!(matches!(result, Err(Error::JobStuck { job }) if job == "Stale"));
+    assert_eq!(pipeline.run_state(), Some(JobState::Failed));
+}
+
+#[test]
+pub fn test_resume_pipeline() {
+    use crate::state::JsonStateStore;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let root = std::env::temp_dir().join(format!("waterflow-resume-{}", Uuid::new_v4()));
+    let runs = Arc::new(AtomicUsize::new(0));
+    let build = |fail: bool| {
+        let runs = runs.clone();
+        let fetch = Job::from_fn("Fetch", move |_| {
+            runs.fetch_add(1, Ordering::SeqCst);
+            Ok("data".to_string())
+        });
+        let mut process = Job::from_fn("Process", move |context| {
+            if fail {
+                return Err(Error::Bash {
+                    e: "crashed".to_string(),
+                });
+            }
+            Ok(context.input.join("").to_uppercase())
+        });
+        process.add_dependency(fetch.get_id());
+
+        let mut pipeline = Pipeline::new().with_state_store(JsonStateStore::new(&root).unwrap());
+        pipeline.add_jobs(vec![fetch, process]);
+        pipeline
+    };
+
+    let mut crashed = build(true);
+    smol::block_on(crashed.execute()).unwrap();
+    let run_id = crashed.run_id();
+
+    // Pretend that the runner died while the job was running
+    let process = crashed.find_job("Process").unwrap();
+    crashed.get_mut_job(process).start();
+    crashed.checkpoint(process).unwrap();
+
+    let mut resumed = build(false);
+    assert!(matches!(
+        smol::block_on(resumed.resume(Uuid::new_v4())),
+        Err(Error::UnknownRun { .. })
+    ));
+    smol::block_on(resumed.resume(run_id)).unwrap();
+
+    assert_eq!(runs.load(Ordering::SeqCst), 1);
+    assert_eq!(resumed.run_id(), run_id);
+    let process = resumed.find_job("Process").unwrap();
+    assert_eq!(resumed.get_job(process).output, "DATA");
+
+    let store = JsonStateStore::new(&root).unwrap();
+    let run = store.load_run(run_id).unwrap().unwrap();
+    assert_eq!(run.state, JobState::Succeeded);
+    let stored = JsonStateStore::new(&root)
+        .unwrap()
+        .load_jobs(run_id)
+        .unwrap();
+    assert_eq!(stored.len(), 2);
+    assert_eq!(stored[1].job_id, process);
+    assert_eq!(stored[1].state, JobState::Succeeded);
+    assert_eq!(stored[1].attempts, 3);
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+pub fn test_failed_checkpoint() {
+    use crate::job_type::JobType;
+    use crate::state::JsonStateStore;
+
+    /// Fails to save the fast job once it finished
+    #[derive(Debug)]
+    struct FailingStore(JsonStateStore);
+
+    impl StateStore for FailingStore {
+        fn save_job(&self, run_id: Uuid, job: &JobRecord) -> Result<()> {
+            if job.name == "Fast" && job.state == JobState::Succeeded {
+                return Err(Error::Io {
+                    e: std::io::Error::other("The disk is full"),
+                });
+            }
+            self.0.save_job(run_id, job)
+        }
+
+        fn load_jobs(&self, run_id: Uuid) -> Result<Vec<JobRecord>> {
+            self.0.load_jobs(run_id)
+        }
+
+        fn save_run(&self, run: &RunRecord) -> Result<()> {
+            self.0.save_run(run)
+        }
+
+        fn load_run(&self, run_id: Uuid) -> Result<Option<RunRecord>> {
+            self.0.load_run(run_id)
+        }
+
+        fn runs(&self) -> Result<Vec<RunRecord>> {
+            self.0.runs()
+        }
+    }
+
+    let root = std::env::temp_dir().join(format!("waterflow-checkpoint-{}", Uuid::new_v4()));
+    let metrics = Metrics::new();
+    let mut pipeline = Pipeline::new()
+        .with_metrics(metrics.clone())
+        .with_state_store(FailingStore(JsonStateStore::new(&root).unwrap()));
+    pipeline.add_jobs(vec![
+        Job::new("Slow", JobType::new_bash("sleep 0.3; echo -n slow")),
+        Job::new("Fast", JobType::new_bash("echo -n fast")),
+    ]);
+
+    // The slow job still finishes before the run stops with the error
+    assert!(matches!(
+        smol::block_on(pipeline.execute()),
+        Err(Error::Io { .. })
+    ));
+    assert_eq!(pipeline.run_state(), Some(JobState::Failed));
+    let slow = pipeline.find_job("Slow").unwrap();
+    assert_eq!(
+        JobState::from(&pipeline.get_job(slow).status),
+        JobState::Succeeded
+    );
+    assert!(metrics
+        .render()
+        .lines()
+        .any(|line| line == "waterflow_jobs_running 0"));
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+pub fn test_pipeline_events() {
+    let mut pipeline = Pipeline::new();
+    let events = pipeline.subscribe();
+    let cancel = pipeline.cancel_handle();
+
+    let first = Job::from_fn("First", move |context| {
+        context.log("cancelling");
+        cancel.cancel();
+        Ok("done".to_string())
+    });
+    let mut second = Job::from_fn("Second", |_| Ok("never".to_string()));
+    second.add_dependency(first.get_id());
+    let (first_id, second_id) = (first.get_id(), second.get_id());
+    pipeline.add_jobs(vec![first, second]);
+
+    assert!(matches!(
+        smol::block_on(pipeline.execute()),
+        Err(Error::Cancelled)
+    ));
+    assert_eq!(pipeline.get_job(second_id).get_status(), JobStatus::Waiting);
+
+    drop(pipeline);
+    let events = events.iter().collect::<Vec<_>>();
+    let kinds = events.iter().map(|e| e.kind()).collect::<Vec<_>>();
+    assert_eq!(kinds, ["run", "job", "log", "job", "run"]);
+    assert!(matches!(
+        &events[2],
+        PipelineEvent::Log { job_id, line, .. } if *job_id == first_id && line == "cancelling"
+    ));
+    assert!(matches!(
+        &events[3],
+        PipelineEvent::Job { job, .. } if job.state == JobState::Succeeded && job.logs == ["cancelling"]
+    ));
+    assert!(matches!(
+        &events[4],
+        PipelineEvent::Run { run } if run.state == JobState::Cancelled && run.finished_at.is_some()
+    ));
+}
+
+#[test]
+pub fn test_cancel_running_job() {
+    use crate::job_type::JobType;
+
+    // Bash runs the sleep in a child process, which has to be killed as well
+    let slow = Job::new("Slow", JobType::new_bash("sleep 5; echo -n late"));
+    let slow_id = slow.get_id();
+    let mut pipeline = Pipeline::new();
+    pipeline.add_job(slow);
+
+    let cancel = pipeline.cancel_handle();
+    std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        cancel.cancel();
+    });
+    let started_at = Instant::now();
+    assert!(matches!(
+        smol::block_on(pipeline.execute()),
+        Err(Error::Cancelled)
+    ));
+    assert!(started_at.elapsed() < std::time::Duration::from_secs(2));
+    assert!(matches!(
+        pipeline.get_job(slow_id).get_status(),
+        JobStatus::Failed { msg, .. } if msg == "The run was cancelled"
+    ));
+    assert_eq!(pipeline.run_state(), Some(JobState::Cancelled));
+}
+
+#[test]
+pub fn test_tracing_spans() {
+    use crate::job_type::JobType;
+    use std::fmt::Debug;
+    use std::sync::Mutex;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+
+    /// Collects the fields of every span as `name.field=value`
+    #[derive(Clone, Default)]
+    struct Spans(Arc<Mutex<Vec<String>>>);
+
+    struct Fields<'a>(&'a str, &'a mut Vec<String>);
+
+    impl Visit for Fields<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            self.1
+                .push(format!("{}.{}={:?}", self.0, field.name(), value));
+        }
+    }
+
+    impl<S: tracing::Subscriber> Layer<S> for Spans {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _: &Id, _: Context<'_, S>) {
+            attrs.record(&mut Fields(
+                attrs.metadata().name(),
+                &mut self.0.lock().unwrap(),
+            ));
+        }
+
+        fn on_record(&self, _: &Id, values: &Record<'_>, _: Context<'_, S>) {
+            values.record(&mut Fields("record", &mut self.0.lock().unwrap()));
+        }
+    }
+
+    let mut job1 = Job::new("Hello", JobType::new_bash("echo -n Hello"));
+    job1.attempts = 1;
+    let mut job2 = Job::new("Fail", JobType::new_bash("exit 1"));
+    job2.add_dependency(job1.get_id());
+    let job1_id = job1.get_id();
+
+    let mut pipeline = Pipeline::new();
+    pipeline.add_jobs(vec![job1, job2]);
+
+    let spans = Spans::default();
+    let subscriber = tracing_subscriber::registry().with(spans.clone());
+    tracing::subscriber::with_default(subscriber, || {
+        smol::block_on(pipeline.execute()).unwrap();
+    });
+
+    let spans = spans.0.lock().unwrap();
+    for field in [
+        format!("pipeline.run_id={}", pipeline.run_id()),
+        "pipeline.jobs=2".to_string(),
+        format!("job.job_id={job1_id}"),
+        "job.name=Hello".to_string(),
+        "job.job_type=\"bash\"".to_string(),
+        "job.attempt=2".to_string(),
+        "job.attempt=1".to_string(),
+        "record.status=\"succeeded\"".to_string(),
+        "record.status=\"failed\"".to_string(),
+    ] {
+        assert!(spans.contains(&field), "{field} is missing from {spans:?}");
+    }
+    assert_eq!(spans.last().unwrap(), "record.status=\"failed\"");
+}
+
 #[test]
 #[cfg(feature = "wasm")]
 #[ignore = "This needs to have the wasm_example built"]
@@ -191,3 +1309,28 @@ pub fn test_execute_pipeline() {
 
     assert!(all_succeeded)
 }
+
+#[test]
+#[cfg(feature = "wasm")]
+#[ignore = "This needs to have the wasm_example built"]
+pub fn test_execute_failing_plugin() {
+    use crate::job_type::JobType;
+
+    let mut pipeline = Pipeline::new();
+
+    let file_name = "tests/wasm_example/pkg/wasm_example_bg.wasm";
+
+    let job = Job::new("Empty join", JobType::new_wasm("checked_join", file_name));
+    let job_id = job.get_id();
+
+    pipeline.add_job(job);
+
+    smol::block_on(async { pipeline.execute().await })
+        .expect("Something went wrong while trying to run the pipeline!");
+
+    let JobStatus::Failed { msg, .. } = pipeline.get_job(job_id).get_status() else {
+        panic!("The plugin should have failed the job");
+    };
+
+    assert_eq!(msg, "There is nothing to join");
+}
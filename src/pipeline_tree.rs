@@ -1,73 +1,284 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 
 use uuid::Uuid;
 
+use crate::error::Error;
+use crate::job::JobStatus;
+use crate::job_type::JobType;
 use crate::pipeline::Pipeline;
+use crate::Result;
 
-type Deps = Vec<PipelineTree>;
-type AllJobs = BTreeMap<Uuid, (String, Vec<Uuid>)>;
-type DepCache = BTreeMap<Uuid, PipelineTree>;
+/// Job in the dependency graph
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PipelineNode {
+    pub job_id: Uuid,
+    pub name: String,
+    pub job_type: JobType,
+    pub status: JobStatus,
+    /// Jobs that this job depends on
+    pub dependencies: Vec<Uuid>,
+    /// Jobs that depend on this job
+    pub dependants: Vec<Uuid>,
+}
 
+/// Dependency graph of a pipeline, where every job is stored once
 #[derive(Clone, Debug)]
 pub struct PipelineTree {
-    pub name: String,
-    pub dependency_of: Deps,
+    nodes: BTreeMap<Uuid, PipelineNode>,
+    /// Job ids in the order in which they were added to the pipeline
+    order: Vec<Uuid>,
 }
 
 impl PipelineTree {
+    /// Builds the graph, ignoring dependencies on jobs that aren't in the pipeline
     pub fn new(pipeline: &Pipeline) -> Self {
-        Self::generate_with_dependants(pipeline)
-    }
-
-    fn get_all_jobs_with_deps(pipeline: &Pipeline) -> AllJobs {
-        pipeline
+        let mut nodes = pipeline
             .jobs
             .iter()
-            .map(|job| (job.get_id(), (job.name.clone(), job.dependencies.clone())))
+            .map(|job| {
+                let node = PipelineNode {
+                    job_id: job.get_id(),
+                    name: job.name.clone(),
+                    job_type: job.job_type.clone(),
+                    status: job.get_status(),
+                    dependencies: vec![],
+                    dependants: vec![],
+                };
+                (job.get_id(), node)
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        for job in &pipeline.jobs {
+            for dependency in &job.dependencies {
+                if !nodes.contains_key(dependency) {
+                    continue;
+                }
+                if let Some(node) = nodes.get_mut(&job.get_id()) {
+                    node.dependencies.push(*dependency);
+                }
+                if let Some(node) = nodes.get_mut(dependency) {
+                    node.dependants.push(job.get_id());
+                }
+            }
+        }
+
+        PipelineTree {
+            nodes,
+            order: pipeline.jobs.iter().map(|job| job.get_id()).collect(),
+        }
+    }
+
+    pub fn node(&self, job_id: Uuid) -> Option<&PipelineNode> {
+        self.nodes.get(&job_id)
+    }
+
+    /// Nodes in the order in which the jobs were added to the pipeline
+    pub fn nodes(&self) -> impl Iterator<Item = &PipelineNode> {
+        self.order.iter().filter_map(|id| self.nodes.get(id))
+    }
+
+    /// Jobs that don't depend on anything
+    pub fn roots(&self) -> Vec<Uuid> {
+        self.nodes()
+            .filter(|node| node.dependencies.is_empty())
+            .map(|node| node.job_id)
             .collect()
     }
 
-    fn get_deps_for_uuid(job_id: &Uuid, all_jobs: &AllJobs, dep_cache: &mut DepCache) -> Deps {
-        all_jobs
-            .iter()
-            .filter(|(_, (_, deps))| deps.contains(job_id))
-            .map(|(id, (name, _))| {
-                if let Some(cached) = dep_cache.get(id) {
-                    cached.clone()
-                } else {
-                    let new_leaf = PipelineTree {
-                        name: name.to_string(),
-                        dependency_of: PipelineTree::get_deps_for_uuid(id, all_jobs, dep_cache),
-                    };
-
-                    dep_cache.insert(*id, new_leaf.clone());
-                    new_leaf
+    /// Jobs that nothing depends on
+    pub fn leaves(&self) -> Vec<Uuid> {
+        self.nodes()
+            .filter(|node| node.dependants.is_empty())
+            .map(|node| node.job_id)
+            .collect()
+    }
+
+    /// Jobs that have to succeed before this one can run
+    pub fn ancestors(&self, job_id: Uuid) -> BTreeSet<Uuid> {
+        self.reachable(job_id, |node| &node.dependencies)
+    }
+
+    /// Jobs that can only run after this one succeeded
+    pub fn descendants(&self, job_id: Uuid) -> BTreeSet<Uuid> {
+        self.reachable(job_id, |node| &node.dependants)
+    }
+
+    fn reachable(
+        &self,
+        job_id: Uuid,
+        edges: impl Fn(&PipelineNode) -> &Vec<Uuid>,
+    ) -> BTreeSet<Uuid> {
+        let mut found = BTreeSet::new();
+        let mut queue = VecDeque::from([job_id]);
+
+        while let Some(id) = queue.pop_front() {
+            let Some(node) = self.nodes.get(&id) else {
+                continue;
+            };
+            for next in edges(node) {
+                if found.insert(*next) {
+                    queue.push_back(*next);
                 }
-            })
-            .collect::<Vec<_>>()
+            }
+        }
+
+        found.remove(&job_id);
+        found
     }
 
-    fn generate_with_dependants(pipeline: &Pipeline) -> Self {
-        let all_jobs = Self::get_all_jobs_with_deps(pipeline);
+    /// Orders the jobs so that every job comes after its dependencies.
+    ///
+    /// Jobs that are ready at the same time keep the order in which they were added to the pipeline.
+    pub fn topological_order(&self) -> Result<Vec<Uuid>> {
+        let mut remaining = self
+            .nodes()
+            .map(|node| (node.job_id, node.dependencies.len()))
+            .collect::<BTreeMap<_, _>>();
+        let mut order = Vec::with_capacity(self.order.len());
 
-        let root_jobs = all_jobs
-            .iter()
-            .filter(|(_, (_, deps))| deps.is_empty())
-            .collect::<Vec<_>>();
+        while order.len() < self.order.len() {
+            let ready = self
+                .order
+                .iter()
+                .filter(|id| remaining.get(id) == Some(&0))
+                .copied()
+                .collect::<Vec<_>>();
 
-        let mut dep_cache: DepCache = BTreeMap::new();
+            if ready.is_empty() {
+                let job = self
+                    .nodes()
+                    .find(|node| remaining.contains_key(&node.job_id))
+                    .map(|node| node.name.clone())
+                    .unwrap_or_default();
+                return Err(Error::DependencyCycle { job });
+            }
 
-        let root_jobs = root_jobs
-            .into_iter()
-            .map(|(job_id, (name, _))| PipelineTree {
-                name: name.to_string(),
-                dependency_of: PipelineTree::get_deps_for_uuid(job_id, &all_jobs, &mut dep_cache),
-            })
-            .collect::<Vec<_>>();
+            for id in ready {
+                remaining.remove(&id);
+                for dependant in &self.nodes[&id].dependants {
+                    if let Some(count) = remaining.get_mut(dependant) {
+                        *count -= 1;
+                    }
+                }
+                order.push(id);
+            }
+        }
 
-        PipelineTree {
-            name: "ROOT".to_string(),
-            dependency_of: root_jobs,
+        Ok(order)
+    }
+
+    /// Length of the longest chain of dependencies above the job, so roots have a depth of 0
+    pub fn depth(&self, job_id: Uuid) -> Option<usize> {
+        self.depths().get(&job_id).copied()
+    }
+
+    /// Depth of every job. Dependency cycles are cut where they're found.
+    pub fn depths(&self) -> BTreeMap<Uuid, usize> {
+        fn depth(
+            tree: &PipelineTree,
+            id: Uuid,
+            depths: &mut BTreeMap<Uuid, usize>,
+            visiting: &mut BTreeSet<Uuid>,
+        ) -> usize {
+            if let Some(depth) = depths.get(&id) {
+                return *depth;
+            }
+
+            visiting.insert(id);
+            let mut d = 0;
+            for dependency in &tree.nodes[&id].dependencies {
+                if !visiting.contains(dependency) {
+                    d = d.max(depth(tree, *dependency, depths, visiting) + 1);
+                }
+            }
+            visiting.remove(&id);
+
+            depths.insert(id, d);
+            d
+        }
+
+        let mut depths = BTreeMap::new();
+        let mut visiting = BTreeSet::new();
+        for id in &self.order {
+            depth(self, *id, &mut depths, &mut visiting);
         }
+        depths
     }
 }
+
+#[test]
+pub fn test_pipeline_tree_queries() {
+    use crate::job::Job;
+
+    //   top
+    //  /   \
+    // left right   lone
+    //  \   /
+    //  bottom
+    let top = Job::new("Top", JobType::Noop);
+    let mut left = Job::new("Left", JobType::Noop);
+    let mut right = Job::new("Right", JobType::Noop);
+    let mut bottom = Job::new("Bottom", JobType::Noop);
+    let lone = Job::new("Lone", JobType::Noop);
+
+    left.add_dependency(top.get_id());
+    right.add_dependency(top.get_id());
+    bottom.add_dependency(right.get_id());
+    bottom.add_dependency(left.get_id());
+
+    let ids @ [top_id, left_id, right_id, bottom_id, lone_id] =
+        [&top, &left, &right, &bottom, &lone].map(|job| job.get_id());
+
+    let mut pipeline = Pipeline::new();
+    pipeline.add_jobs(vec![bottom, lone, right, left, top]);
+    let tree = PipelineTree::new(&pipeline);
+
+    assert_eq!(tree.nodes().count(), ids.len());
+    assert_eq!(
+        tree.node(bottom_id).unwrap().dependencies,
+        [right_id, left_id]
+    );
+    assert_eq!(tree.node(top_id).unwrap().dependants, [right_id, left_id]);
+
+    assert_eq!(tree.roots(), [lone_id, top_id]);
+    assert_eq!(tree.leaves(), [bottom_id, lone_id]);
+    assert_eq!(
+        tree.ancestors(bottom_id),
+        BTreeSet::from([top_id, left_id, right_id])
+    );
+    assert_eq!(
+        tree.descendants(top_id),
+        BTreeSet::from([left_id, right_id, bottom_id])
+    );
+    assert!(tree.descendants(lone_id).is_empty());
+
+    assert_eq!(
+        tree.topological_order().unwrap(),
+        [lone_id, top_id, right_id, left_id, bottom_id]
+    );
+    assert_eq!(tree.depth(top_id), Some(0));
+    assert_eq!(tree.depth(left_id), Some(1));
+    assert_eq!(tree.depth(bottom_id), Some(2));
+    assert_eq!(tree.depth(Uuid::new_v4()), None);
+}
+
+#[test]
+pub fn test_pipeline_tree_cycle() {
+    use crate::job::Job;
+
+    let mut first = Job::new("First", JobType::Noop);
+    let mut second = Job::new("Second", JobType::Noop);
+    first.add_dependency(second.get_id());
+    second.add_dependency(first.get_id());
+
+    let mut pipeline = Pipeline::new();
+    pipeline.add_jobs(vec![first, second]);
+    let tree = PipelineTree::new(&pipeline);
+
+    assert!(matches!(
+        tree.topological_order(),
+        Err(Error::DependencyCycle { job }) if job == "First"
+    ));
+    assert_eq!(tree.depths().len(), 2);
+    assert!(pipeline.validate().is_err());
+}
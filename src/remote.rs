@@ -0,0 +1,879 @@
+//! Runs jobs on worker agents, which connect to a coordinator over TCP.
+//!
+//! Workers register with their labels and the coordinator's token, and pull jobs one at a time.
+//! Only jobs with a [`Selector::Worker`] run remotely. The coordinator hands such a job
+//! to an idle worker that has all of the labels of the job's selector, the worker
+//! streams back the job's logs and reports whether it succeeded. When a worker disconnects or
+//! stops sending heartbeats, its job is queued again for another worker. A job that no worker
+//! picks up within the coordinator's queue timeout fails, and the worker that's running a job
+//! whose run is cancelled gets told to stop it.
+//!
+//! Jobs see the worker's name in the `WATERFLOW_WORKER` environment variable.
+//!
+//! Messages are JSON objects, one per line. Workers that don't know the token are turned away
+//! before they get to see any job or its secrets. The connection isn't encrypted though, so the
+//! coordinator should only be reachable from a trusted network.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::definition::JobDefinition;
+use crate::error::Error;
+use crate::executor::{ExecutorRegistry, JobExecutor};
+use crate::job::{JobContext, JobLogs, Secrets, Selector};
+use crate::pipeline::CancelHandle;
+use crate::Result;
+
+/// How often workers tell the coordinator that they're still there
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(2);
+/// How long the coordinator waits for a message before it gives up on a worker
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the coordinator, which holds the lock of its state while it sends, waits for a worker
+/// to take a message
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a job waits for a worker by default, before it fails
+const DEFAULT_QUEUE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// How often a job that's waiting for its result checks whether its run was cancelled
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Job as it's sent to a worker
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Task {
+    pub task_id: Uuid,
+    pub job_id: Uuid,
+    pub job: JobDefinition,
+    pub fixed_input: Vec<String>,
+    pub input: Vec<String>,
+    pub secrets: Secrets,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum TaskResult {
+    Succeeded { output: String },
+    Failed { error: String },
+}
+
+/// Message from a worker to the coordinator
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum WorkerMessage {
+    /// First message of every connection
+    Register {
+        name: String,
+        labels: Vec<String>,
+        /// Shared secret that the coordinator was started with
+        token: String,
+    },
+    /// Asks for the next job
+    Ready,
+    Heartbeat,
+    Log {
+        task_id: Uuid,
+        line: String,
+    },
+    Finished {
+        task_id: Uuid,
+        result: TaskResult,
+    },
+}
+
+/// Message from the coordinator to a worker
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum CoordinatorMessage {
+    Task {
+        task: Box<Task>,
+    },
+    /// Sent instead of any job when the worker's registration is refused
+    Rejected {
+        message: String,
+    },
+    /// Stops the task, whose run was cancelled
+    Cancel {
+        task_id: Uuid,
+    },
+}
+
+/// Worker as it's seen by the coordinator
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkerInfo {
+    pub worker_id: Uuid,
+    pub name: String,
+    pub labels: Vec<String>,
+    /// Task that the worker is running
+    pub task: Option<Uuid>,
+}
+
+/// Writes one message per line, from whichever thread has something to send
+fn send(stream: &Mutex<TcpStream>, message: &impl Serialize) -> Result<()> {
+    let mut line = serde_json::to_string(message)?;
+    line.push('\n');
+    lock(stream).write_all(line.as_bytes())?;
+    Ok(())
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Worker that registered with the coordinator's token, so that it may see the jobs' secrets
+#[derive(Debug)]
+struct Connection {
+    info: WorkerInfo,
+    stream: Mutex<TcpStream>,
+    /// Whether the worker asked for a job
+    ready: bool,
+}
+
+impl Connection {
+    /// Sends the message, or drops the connection so that its reader removes the worker
+    fn send(&self, message: &CoordinatorMessage) -> Result<()> {
+        let result = send(&self.stream, message);
+        if result.is_err() {
+            let _ = lock(&self.stream).shutdown(Shutdown::Both);
+        }
+        result
+    }
+}
+
+#[derive(Debug)]
+struct Pending {
+    task: Task,
+    labels: Vec<String>,
+    /// When the task was last put in the queue
+    queued_at: Instant,
+    logs: JobLogs,
+    result: flume::Sender<Result<String>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    workers: BTreeMap<Uuid, Connection>,
+    /// Tasks that are waiting for a worker, in the order in which they're handed out
+    queue: VecDeque<Uuid>,
+    tasks: BTreeMap<Uuid, Pending>,
+}
+
+impl State {
+    /// Hands the queued tasks to the ready workers with matching labels
+    fn dispatch(&mut self) {
+        let mut waiting = VecDeque::new();
+        while let Some(task_id) = self.queue.pop_front() {
+            let Some(pending) = self.tasks.get(&task_id) else {
+                continue;
+            };
+            let worker = self.workers.values_mut().find(|worker| {
+                worker.ready
+                    && pending
+                        .labels
+                        .iter()
+                        .all(|label| worker.info.labels.contains(label))
+            });
+            let Some(worker) = worker else {
+                waiting.push_back(task_id);
+                continue;
+            };
+
+            let message = CoordinatorMessage::Task {
+                task: Box::new(pending.task.clone()),
+            };
+            worker.ready = false;
+            match worker.send(&message) {
+                Ok(()) => {
+                    info!(
+                        "Job {} runs on worker {}",
+                        pending.task.job.name, worker.info.name
+                    );
+                    worker.info.task = Some(task_id);
+                }
+                // The worker is removed once its reader notices the dropped connection
+                Err(e) => {
+                    warn!("Failed to send a job to worker {}: {e}", worker.info.name);
+                    waiting.push_back(task_id);
+                }
+            }
+        }
+        self.queue = waiting;
+    }
+
+    /// Puts the worker's task back at the front of the queue
+    fn remove_worker(&mut self, worker_id: Uuid) {
+        let Some(worker) = self.workers.remove(&worker_id) else {
+            return;
+        };
+        warn!("Lost worker {}", worker.info.name);
+        if let Some(task_id) = worker.info.task {
+            if let Some(pending) = self.tasks.get_mut(&task_id) {
+                pending.queued_at = Instant::now();
+                self.queue.push_front(task_id);
+            }
+        }
+        self.dispatch();
+    }
+
+    /// Forgets the task, and tells the worker that's running it to stop
+    fn remove_task(&mut self, task_id: Uuid) {
+        self.tasks.remove(&task_id);
+        self.queue.retain(|queued| *queued != task_id);
+
+        let worker = self
+            .workers
+            .values_mut()
+            .find(|worker| worker.info.task == Some(task_id));
+        if let Some(worker) = worker {
+            // The worker asks for the next job once it stopped, and its result is dropped
+            worker.info.task = None;
+            if let Err(e) = worker.send(&CoordinatorMessage::Cancel { task_id }) {
+                warn!("Failed to cancel a job on worker {}: {e}", worker.info.name);
+            }
+        }
+    }
+}
+
+/// Hands jobs to the workers that connect to it, cloning it shares the same workers
+#[derive(Debug, Clone)]
+pub struct Coordinator {
+    address: SocketAddr,
+    state: Arc<Mutex<State>>,
+    queue_timeout: Duration,
+    token: Arc<str>,
+}
+
+impl Coordinator {
+    /// Accepts the workers that register with the token from a background thread, until the
+    /// process exits
+    pub fn bind(address: impl ToSocketAddrs, token: &str) -> Result<Self> {
+        if token.is_empty() {
+            return Err(Error::MissingToken);
+        }
+        let listener = TcpListener::bind(address)?;
+        let coordinator = Coordinator {
+            address: listener.local_addr()?,
+            state: Arc::default(),
+            queue_timeout: DEFAULT_QUEUE_TIMEOUT,
+            token: token.into(),
+        };
+
+        let accepting = coordinator.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let coordinator = accepting.clone();
+                match stream {
+                    Ok(stream) => {
+                        std::thread::spawn(move || coordinator.connect(stream));
+                    }
+                    Err(e) => warn!("Failed to accept a worker: {e}"),
+                }
+            }
+        });
+
+        Ok(coordinator)
+    }
+
+    /// Fails the jobs that no worker picked up within the timeout, which is 10 minutes by default
+    pub fn with_queue_timeout(mut self, timeout: Duration) -> Self {
+        self.queue_timeout = timeout;
+        self
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Workers that are connected, in no particular order
+    pub fn workers(&self) -> Vec<WorkerInfo> {
+        self.state()
+            .workers
+            .values()
+            .map(|worker| worker.info.clone())
+            .collect()
+    }
+
+    /// Queues the job until a worker with all of its labels pulls it, then waits for its result.
+    ///
+    /// The job fails when it's queued for longer than the queue timeout, or when its run is
+    /// cancelled.
+    pub fn execute(&self, job: JobDefinition, context: &JobContext) -> Result<String> {
+        let (tx, rx) = flume::bounded(1);
+        let task_id = Uuid::new_v4();
+        let labels = match &job.selector {
+            Some(Selector::Worker(labels)) => labels.clone(),
+            _ => vec![],
+        };
+        let task = Task {
+            task_id,
+            job_id: context.job_id,
+            job,
+            fixed_input: context.fixed_input.clone(),
+            input: context.input.clone(),
+            secrets: context.secrets.clone(),
+        };
+
+        {
+            let mut state = self.state();
+            state.tasks.insert(
+                task_id,
+                Pending {
+                    task,
+                    labels,
+                    queued_at: Instant::now(),
+                    logs: context.logs.clone(),
+                    result: tx,
+                },
+            );
+            state.queue.push_back(task_id);
+            state.dispatch();
+        }
+
+        loop {
+            match rx.recv_timeout(CANCEL_POLL_INTERVAL) {
+                Ok(result) => return result,
+                Err(flume::RecvTimeoutError::Timeout) => {}
+                Err(flume::RecvTimeoutError::Disconnected) => return Err(Error::Flume),
+            }
+
+            // Workers hand in their results while holding the lock
+            let mut state = self.state();
+            if let Ok(result) = rx.try_recv() {
+                return result;
+            }
+            if context.cancel.is_cancelled() {
+                state.remove_task(task_id);
+                return Err(Error::Cancelled);
+            }
+            let timed_out = state.tasks.get(&task_id).is_some_and(|pending| {
+                state.queue.contains(&task_id) && pending.queued_at.elapsed() > self.queue_timeout
+            });
+            if timed_out {
+                state.remove_task(task_id);
+                return Err(Error::QueueTimeout {
+                    job: context.name.clone(),
+                    timeout: self.queue_timeout,
+                });
+            }
+        }
+    }
+
+    /// Reads the worker's messages until it disconnects
+    fn connect(&self, stream: TcpStream) {
+        let peer = stream
+            .peer_addr()
+            .map_or("unknown address".to_string(), |a| a.to_string());
+        let worker_id = Uuid::new_v4();
+        if let Err(e) = self.serve(worker_id, stream) {
+            warn!("Worker at {peer} disconnected: {e}");
+        }
+        self.state().remove_worker(worker_id);
+    }
+
+    fn serve(&self, worker_id: Uuid, stream: TcpStream) -> Result<()> {
+        stream.set_read_timeout(Some(HEARTBEAT_TIMEOUT))?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        let mut lines = BufReader::new(stream.try_clone()?).lines();
+
+        let Some(WorkerMessage::Register {
+            name,
+            labels,
+            token,
+        }) = read(&mut lines)?
+        else {
+            return Err(Error::WorkerProtocol {
+                message: "workers have to register first".to_string(),
+            });
+        };
+        if !tokens_match(&token, &self.token) {
+            let message = format!("worker {name} registered with the wrong token");
+            let _ = send(
+                &Mutex::new(stream),
+                &CoordinatorMessage::Rejected {
+                    message: "wrong token".to_string(),
+                },
+            );
+            return Err(Error::WorkerProtocol { message });
+        }
+        info!("Worker {name} registered with labels {labels:?}");
+        self.state().workers.insert(
+            worker_id,
+            Connection {
+                info: WorkerInfo {
+                    worker_id,
+                    name,
+                    labels,
+                    task: None,
+                },
+                stream: Mutex::new(stream),
+                ready: false,
+            },
+        );
+
+        while let Some(message) = read(&mut lines)? {
+            let mut state = self.state();
+            match message {
+                WorkerMessage::Register { .. } | WorkerMessage::Heartbeat => {}
+                WorkerMessage::Ready => {
+                    if let Some(worker) = state.workers.get_mut(&worker_id) {
+                        worker.ready = true;
+                    }
+                    state.dispatch();
+                }
+                WorkerMessage::Log { task_id, line } => {
+                    if let Some(pending) = state.tasks.get(&task_id) {
+                        pending.logs.push(line);
+                    }
+                }
+                WorkerMessage::Finished { task_id, result } => {
+                    let Some(worker) = state.workers.get_mut(&worker_id) else {
+                        continue;
+                    };
+                    if worker.info.task != Some(task_id) {
+                        continue;
+                    }
+                    worker.info.task = None;
+                    let worker = worker.info.name.clone();
+                    if let Some(pending) = state.tasks.remove(&task_id) {
+                        let result = match result {
+                            TaskResult::Succeeded { output } => Ok(output),
+                            TaskResult::Failed { error } => Err(Error::Remote {
+                                worker,
+                                message: error,
+                            }),
+                        };
+                        let _ = pending.result.send(result);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compares every byte, so that the time it takes doesn't give away how much of the token matched
+pub(crate) fn tokens_match(token: &str, expected: &str) -> bool {
+    token.len() == expected.len()
+        && token
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// Next message, or none once the other side closed the connection
+fn read<T: for<'de> Deserialize<'de>>(
+    lines: &mut impl Iterator<Item = std::io::Result<String>>,
+) -> Result<Option<T>> {
+    match lines.next() {
+        Some(line) => Ok(Some(serde_json::from_str(&line?)?)),
+        None => Ok(None),
+    }
+}
+
+/// Agent that runs the jobs it pulls from a coordinator
+#[derive(Debug, Clone)]
+pub struct Worker {
+    name: String,
+    labels: Vec<String>,
+    executors: ExecutorRegistry,
+    token: String,
+}
+
+impl Worker {
+    /// Creates a worker that registers with the coordinator's token
+    pub fn new(name: &str, token: &str) -> Self {
+        Worker {
+            name: name.to_string(),
+            labels: vec![],
+            executors: ExecutorRegistry::new(),
+            token: token.to_string(),
+        }
+    }
+
+    pub fn with_label(mut self, label: &str) -> Self {
+        self.labels.push(label.to_string());
+        self
+    }
+
+    /// Makes the executor's job type available to the jobs that the worker runs
+    pub fn with_executor(mut self, executor: impl JobExecutor + 'static) -> Self {
+        self.executors.register(executor);
+        self
+    }
+
+    /// Registers with the coordinator and runs its jobs from a background thread
+    pub fn connect(self, coordinator: impl ToSocketAddrs) -> Result<WorkerHandle> {
+        let stream = TcpStream::connect(coordinator)?;
+        let lines = BufReader::new(stream.try_clone()?).lines();
+        let connection = stream.try_clone()?;
+        let writer = Arc::new(Mutex::new(stream));
+
+        send(
+            &writer,
+            &WorkerMessage::Register {
+                name: self.name.clone(),
+                labels: self.labels.clone(),
+                token: self.token.clone(),
+            },
+        )?;
+        send(&writer, &WorkerMessage::Ready)?;
+
+        let heartbeat = writer.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(HEARTBEAT_INTERVAL);
+            if send(&heartbeat, &WorkerMessage::Heartbeat).is_err() {
+                break;
+            }
+        });
+
+        let thread = std::thread::spawn(move || self.run(lines, writer));
+        Ok(WorkerHandle { connection, thread })
+    }
+
+    fn run(
+        &self,
+        lines: impl Iterator<Item = std::io::Result<String>>,
+        writer: Arc<Mutex<TcpStream>>,
+    ) -> Result<()> {
+        let running = Arc::default();
+        let result = self.receive(lines, &writer, &running);
+
+        // The coordinator hands the tasks to other workers once the connection is gone
+        for cancel in lock(&running).values() {
+            cancel.cancel();
+        }
+        result
+    }
+
+    /// Runs every task on its own thread, so that the coordinator can still cancel it
+    fn receive(
+        &self,
+        mut lines: impl Iterator<Item = std::io::Result<String>>,
+        writer: &Arc<Mutex<TcpStream>>,
+        running: &Arc<Mutex<BTreeMap<Uuid, CancelHandle>>>,
+    ) -> Result<()> {
+        while let Some(message) = read(&mut lines)? {
+            let task = match message {
+                CoordinatorMessage::Task { task } => task,
+                CoordinatorMessage::Rejected { message } => {
+                    return Err(Error::WorkerRejected { message })
+                }
+                CoordinatorMessage::Cancel { task_id } => {
+                    if let Some(cancel) = lock(running).get(&task_id) {
+                        cancel.cancel();
+                    }
+                    continue;
+                }
+            };
+
+            let cancel = CancelHandle::default();
+            lock(running).insert(task.task_id, cancel.clone());
+            let (worker, writer, running) = (self.clone(), writer.clone(), running.clone());
+            std::thread::spawn(move || {
+                let result = match worker.execute(&task, &writer, cancel) {
+                    Ok(output) => TaskResult::Succeeded { output },
+                    Err(e) => TaskResult::Failed {
+                        error: e.to_string(),
+                    },
+                };
+                lock(&running).remove(&task.task_id);
+
+                let finished = WorkerMessage::Finished {
+                    task_id: task.task_id,
+                    result,
+                };
+                if let Err(e) =
+                    send(&writer, &finished).and_then(|()| send(&writer, &WorkerMessage::Ready))
+                {
+                    warn!("Failed to report job {}: {e}", task.job.name);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    fn execute(
+        &self,
+        task: &Task,
+        writer: &Arc<Mutex<TcpStream>>,
+        cancel: CancelHandle,
+    ) -> Result<String> {
+        info!("Running job {}", task.job.name);
+        let job = task.job.to_job(&self.executors)?;
+
+        let task_id = task.task_id;
+        let writer = writer.clone();
+        let mut env = job.env.clone();
+        env.insert("WATERFLOW_WORKER".to_string(), self.name.clone());
+        let context = JobContext {
+            job_id: task.job_id,
+            name: job.name.clone(),
+            fixed_input: task.fixed_input.clone(),
+            input: task.input.clone(),
+            env,
+            secrets: task.secrets.clone(),
+            logs: JobLogs::with_listener(move |line| {
+                let line = line.to_string();
+                let _ = send(&writer, &WorkerMessage::Log { task_id, line });
+            }),
+            metrics: None,
+            cancel,
+        };
+        job.job_type.execute(&context)
+    }
+}
+
+#[derive(Debug)]
+pub struct WorkerHandle {
+    connection: TcpStream,
+    thread: JoinHandle<Result<()>>,
+}
+
+impl WorkerHandle {
+    /// Disconnects from the coordinator, which hands the worker's job to another worker
+    pub fn stop(&self) {
+        let _ = self.connection.shutdown(Shutdown::Both);
+    }
+
+    /// Waits until the coordinator disconnects
+    pub fn join(self) -> Result<()> {
+        self.thread.join().unwrap_or(Err(Error::Flume))
+    }
+}
+
+#[cfg(test)]
+const TEST_TOKEN: &str = "s3cret";
+
+#[cfg(test)]
+fn wait_for(coordinator: &Coordinator, condition: impl Fn(&[WorkerInfo]) -> bool) {
+    while !condition(&coordinator.workers()) {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+pub fn test_remote_workers() {
+    use crate::job::Job;
+    use crate::job_type::JobType;
+    use crate::pipeline::Pipeline;
+
+    let coordinator = Coordinator::bind("127.0.0.1:0", TEST_TOKEN).unwrap();
+    let _linux = Worker::new("linux", TEST_TOKEN)
+        .with_label("linux")
+        .connect(coordinator.address())
+        .unwrap();
+    let _big = Worker::new("big", TEST_TOKEN)
+        .with_label("linux")
+        .with_label("big-mem")
+        .connect(coordinator.address())
+        .unwrap();
+    wait_for(&coordinator, |workers| workers.len() == 2);
+
+    let hello = Job::new(
+        "Hello",
+        JobType::new_bash("echo working >&2; echo -n Hello"),
+    )
+    .with_selector(Selector::Worker(vec!["linux".to_string()]));
+    let mut big = Job::new(
+        "Big",
+        JobType::new_bash("echo -n \"{INPUT} from $WATERFLOW_WORKER\""),
+    )
+    .with_selector(Selector::Worker(vec!["big-mem".to_string()]));
+    big.add_dependency(hello.get_id());
+    let native =
+        Job::from_fn("Native", |_| Ok(String::new())).with_selector(Selector::Worker(vec![]));
+    let local = Job::from_fn("Local", |_| Ok(String::new())).with_selector(Selector::Local);
+    let default = Job::new(
+        "Default",
+        JobType::new_bash("echo -n ${WATERFLOW_WORKER:-local}"),
+    );
+    let (hello_id, big_id) = (hello.get_id(), big.get_id());
+    let (native_id, local_id, default_id) = (native.get_id(), local.get_id(), default.get_id());
+
+    let mut pipeline = Pipeline::new().with_coordinator(coordinator.clone());
+    pipeline.add_jobs(vec![hello, big, native, local, default]);
+    smol::block_on(pipeline.execute()).unwrap();
+
+    assert_eq!(pipeline.get_job(hello_id).logs, ["working"]);
+    assert_eq!(pipeline.get_job(big_id).output, "Hello from big");
+    // Closures can't be sent to a worker
+    assert!(pipeline.get_job(native_id).get_status().is_failed());
+    assert!(pipeline.get_job(local_id).get_status().is_succeeded());
+    // Jobs without a selector don't leave the machine
+    assert_eq!(pipeline.get_job(default_id).output, "local");
+
+    // A worker without the token never gets to register
+    let intruder = Worker::new("intruder", "guess")
+        .connect(coordinator.address())
+        .unwrap();
+    assert!(matches!(intruder.join(), Err(Error::WorkerRejected { .. })));
+    assert_eq!(coordinator.workers().len(), 2);
+    assert!(matches!(
+        Coordinator::bind("127.0.0.1:0", ""),
+        Err(Error::MissingToken)
+    ));
+}
+
+#[test]
+pub fn test_parallel_workers() {
+    use crate::job::Job;
+    use crate::job_type::JobType;
+    use crate::pipeline::Pipeline;
+    use std::time::Instant;
+
+    let coordinator = Coordinator::bind("127.0.0.1:0", TEST_TOKEN).unwrap();
+    let _first = Worker::new("first", TEST_TOKEN)
+        .connect(coordinator.address())
+        .unwrap();
+    let _second = Worker::new("second", TEST_TOKEN)
+        .connect(coordinator.address())
+        .unwrap();
+    wait_for(&coordinator, |workers| workers.len() == 2);
+
+    let job = |name| {
+        Job::new(
+            name,
+            JobType::new_bash("sleep 0.5; echo -n $WATERFLOW_WORKER"),
+        )
+        .with_selector(Selector::Worker(vec![]))
+    };
+    let (left, right) = (job("Left"), job("Right"));
+    let (left_id, right_id) = (left.get_id(), right.get_id());
+    let mut pipeline = Pipeline::new().with_coordinator(coordinator.clone());
+    pipeline.add_jobs(vec![left, right]);
+
+    let started_at = Instant::now();
+    smol::block_on(pipeline.execute()).unwrap();
+    assert!(started_at.elapsed() < Duration::from_millis(900));
+
+    let mut workers = [left_id, right_id].map(|id| pipeline.get_job(id).output.clone());
+    workers.sort();
+    assert_eq!(workers, ["first", "second"]);
+}
+
+#[test]
+pub fn test_unclaimed_jobs() {
+    use crate::error::Error;
+    use crate::job::{Job, JobStatus};
+    use crate::job_type::JobType;
+    use crate::pipeline::Pipeline;
+
+    let coordinator = Coordinator::bind("127.0.0.1:0", TEST_TOKEN)
+        .unwrap()
+        .with_queue_timeout(Duration::from_millis(200));
+    let job = || {
+        Job::new("Gpu", JobType::new_bash("echo -n trained"))
+            .with_selector(Selector::Worker(vec!["gpu".to_string()]))
+    };
+
+    let gpu = job();
+    let gpu_id = gpu.get_id();
+    let mut pipeline = Pipeline::new().with_coordinator(coordinator.clone());
+    pipeline.add_job(gpu);
+    smol::block_on(pipeline.execute()).unwrap();
+    assert!(matches!(
+        pipeline.get_job(gpu_id).get_status(),
+        JobStatus::Failed { msg, .. } if msg == "No worker picked up job Gpu within 0.2s"
+    ));
+
+    let coordinator = coordinator.with_queue_timeout(Duration::from_secs(60));
+    let mut pipeline = Pipeline::new().with_coordinator(coordinator.clone());
+    pipeline.add_job(job());
+    let cancel = pipeline.cancel_handle();
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(100));
+        cancel.cancel();
+    });
+    assert!(matches!(
+        smol::block_on(pipeline.execute()),
+        Err(Error::Cancelled)
+    ));
+
+    let state = coordinator.state();
+    assert!(state.tasks.is_empty() && state.queue.is_empty());
+}
+
+#[test]
+pub fn test_remote_cancel() {
+    use crate::error::Error;
+    use crate::job::Job;
+    use crate::job_type::JobType;
+    use crate::pipeline::Pipeline;
+
+    let coordinator = Coordinator::bind("127.0.0.1:0", TEST_TOKEN).unwrap();
+    let _worker = Worker::new("only", TEST_TOKEN)
+        .connect(coordinator.address())
+        .unwrap();
+    wait_for(&coordinator, |workers| workers.len() == 1);
+    let job = |command: &str| {
+        Job::new("Remote", JobType::new_bash(command)).with_selector(Selector::Worker(vec![]))
+    };
+
+    let mut pipeline = Pipeline::new().with_coordinator(coordinator.clone());
+    pipeline.add_job(job("sleep 10"));
+    let cancel = pipeline.cancel_handle();
+    let running = coordinator.clone();
+    std::thread::spawn(move || {
+        wait_for(&running, |workers| workers[0].task.is_some());
+        cancel.cancel();
+    });
+    assert!(matches!(
+        smol::block_on(pipeline.execute()),
+        Err(Error::Cancelled)
+    ));
+
+    // The only worker is free again long before the cancelled job would have finished
+    let started = Instant::now();
+    let mut pipeline = Pipeline::new().with_coordinator(coordinator.clone());
+    pipeline.add_job(job("echo -n next"));
+    smol::block_on(pipeline.execute()).unwrap();
+    assert_eq!(pipeline.jobs[0].output, "next");
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+pub fn test_worker_loss() {
+    use crate::job::Job;
+    use crate::job_type::JobType;
+    use crate::pipeline::Pipeline;
+
+    let coordinator = Coordinator::bind("127.0.0.1:0", TEST_TOKEN).unwrap();
+    let lost = Worker::new("lost", TEST_TOKEN)
+        .connect(coordinator.address())
+        .unwrap();
+
+    let job = Job::new(
+        "Slow",
+        JobType::new_bash("sleep 0.3; echo -n \"done on $WATERFLOW_WORKER\""),
+    )
+    .with_selector(Selector::Worker(vec![]));
+    let job_id = job.get_id();
+    let mut pipeline = Pipeline::new().with_coordinator(coordinator.clone());
+    pipeline.add_job(job);
+    let run = std::thread::spawn(move || {
+        smol::block_on(pipeline.execute()).unwrap();
+        pipeline
+    });
+
+    wait_for(&coordinator, |workers| {
+        workers.iter().any(|worker| worker.task.is_some())
+    });
+    lost.stop();
+    wait_for(&coordinator, |workers| workers.is_empty());
+    let _replacement = Worker::new("replacement", TEST_TOKEN)
+        .connect(coordinator.address())
+        .unwrap();
+
+    let pipeline = run.join().unwrap();
+    assert_eq!(pipeline.get_job(job_id).output, "done on replacement");
+}
@@ -0,0 +1,316 @@
+//! Renders the dependency graph of a pipeline, drawing every job once.
+//!
+//! When the pipeline has already run, the jobs are coloured by their status.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::str::FromStr;
+
+use uuid::Uuid;
+
+use crate::job::JobStatus;
+use crate::pipeline::Pipeline;
+use crate::pipeline_tree::{PipelineNode, PipelineTree};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RenderFormat {
+    /// Graphviz DOT
+    Dot,
+    /// Mermaid flowchart
+    Mermaid,
+    /// Plain text, for the terminal
+    #[default]
+    Ascii,
+}
+
+impl FromStr for RenderFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "dot" => Ok(RenderFormat::Dot),
+            "mermaid" => Ok(RenderFormat::Mermaid),
+            "ascii" => Ok(RenderFormat::Ascii),
+            _ => Err(format!(
+                "Unknown format {s}, expected dot, mermaid or ascii"
+            )),
+        }
+    }
+}
+
+/// Renders the pipeline, where `colour` decides whether ascii output may contain ANSI escapes
+pub fn render(pipeline: &Pipeline, format: RenderFormat, colour: bool) -> String {
+    match format {
+        RenderFormat::Dot => to_dot(pipeline),
+        RenderFormat::Mermaid => to_mermaid(pipeline),
+        RenderFormat::Ascii => to_ascii(pipeline, colour),
+    }
+}
+
+pub fn to_dot(pipeline: &Pipeline) -> String {
+    let graph = Graph::new(pipeline);
+    let mut dot = String::from("digraph pipeline {\n    rankdir=TB;\n    node [shape=box];\n");
+
+    for (i, job) in graph.nodes() {
+        let label = job.name.replace('\\', "\\\\").replace('"', "\\\"");
+        if graph.has_run {
+            let _ = writeln!(
+                dot,
+                "    job{i} [label=\"{label}\", style=filled, fillcolor=\"{}\"];",
+                status_colour(&job.status)
+            );
+        } else {
+            let _ = writeln!(dot, "    job{i} [label=\"{label}\"];");
+        }
+    }
+    for (from, to) in graph.edges() {
+        let _ = writeln!(dot, "    job{from} -> job{to};");
+    }
+
+    dot.push_str("}\n");
+    dot
+}
+
+pub fn to_mermaid(pipeline: &Pipeline) -> String {
+    let graph = Graph::new(pipeline);
+    let mut mermaid = String::from("flowchart TD\n");
+
+    for (i, job) in graph.nodes() {
+        let label = job.name.replace('"', "#quot;");
+        let _ = writeln!(mermaid, "    job{i}[\"{label}\"]");
+    }
+    for (from, to) in graph.edges() {
+        let _ = writeln!(mermaid, "    job{from} --> job{to}");
+    }
+
+    if graph.has_run {
+        for (status, colour) in STATUS_COLOURS {
+            let jobs = graph
+                .nodes()
+                .filter(|(_, job)| status_name(&job.status) == status)
+                .map(|(i, _)| format!("job{i}"))
+                .collect::<Vec<_>>();
+            if jobs.is_empty() {
+                continue;
+            }
+
+            let _ = writeln!(mermaid, "    classDef {status} fill:{colour}");
+            let _ = writeln!(mermaid, "    class {} {status}", jobs.join(","));
+        }
+    }
+
+    mermaid
+}
+
+/// Draws the jobs in stages, where every job only depends on jobs from earlier stages
+pub fn to_ascii(pipeline: &Pipeline, colour: bool) -> String {
+    let graph = Graph::new(pipeline);
+    let mut ascii = String::new();
+
+    let depths = graph.tree.depths();
+    let stages = depths.values().copied().max().map_or(0, |max| max + 1);
+    for stage in 0..stages {
+        let _ = writeln!(ascii, "Stage {}", stage + 1);
+
+        let jobs = graph
+            .tree
+            .nodes()
+            .filter(|node| depths[&node.job_id] == stage)
+            .collect::<Vec<_>>();
+        for (n, job) in jobs.iter().enumerate() {
+            let last = n + 1 == jobs.len();
+
+            let mut line = job.name.clone();
+            if graph.has_run {
+                line = format!("{line} [{}]", status_name(&job.status));
+                if colour {
+                    line = format!("{}{line}\x1b[0m", status_ansi(&job.status));
+                }
+            }
+            let _ = writeln!(ascii, "+-- {line}");
+
+            for dependency in &job.dependencies {
+                let branch = if last { " " } else { "|" };
+                let name = &graph.tree.node(*dependency).unwrap().name;
+                let _ = writeln!(ascii, "{branch}     <- {name}");
+            }
+        }
+    }
+
+    ascii
+}
+
+/// Dependency graph with the jobs numbered in the order they were added to the pipeline
+struct Graph {
+    tree: PipelineTree,
+    indices: BTreeMap<Uuid, usize>,
+    has_run: bool,
+}
+
+impl Graph {
+    fn new(pipeline: &Pipeline) -> Self {
+        let tree = PipelineTree::new(pipeline);
+        let indices = tree
+            .nodes()
+            .enumerate()
+            .map(|(i, node)| (node.job_id, i))
+            .collect();
+
+        Graph {
+            tree,
+            indices,
+            has_run: has_run(pipeline),
+        }
+    }
+
+    fn nodes(&self) -> impl Iterator<Item = (usize, &PipelineNode)> {
+        self.tree.nodes().enumerate()
+    }
+
+    fn edges(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.nodes().flat_map(move |(child, node)| {
+            node.dependencies
+                .iter()
+                .map(move |dependency| (self.indices[dependency], child))
+        })
+    }
+}
+
+fn has_run(pipeline: &Pipeline) -> bool {
+    pipeline.jobs.iter().any(|job| !job.status.is_waiting())
+}
+
+pub(crate) fn status_name(status: &JobStatus) -> &'static str {
+    match status {
+        JobStatus::Waiting => "waiting",
+        JobStatus::InProgress { .. } => "running",
+        JobStatus::Failed { .. } => "failed",
+        JobStatus::Succeeded { .. } => "succeeded",
+    }
+}
+
+/// Fill colours of the statuses in DOT and Mermaid
+const STATUS_COLOURS: [(&str, &str); 4] = [
+    ("waiting", "#d0d0d0"),
+    ("running", "#8ec5ff"),
+    ("failed", "#ff8e8e"),
+    ("succeeded", "#9be39b"),
+];
+
+pub(crate) fn status_colour(status: &JobStatus) -> &'static str {
+    let name = status_name(status);
+    STATUS_COLOURS
+        .iter()
+        .find(|(status, _)| *status == name)
+        .map_or("white", |(_, colour)| colour)
+}
+
+fn status_ansi(status: &JobStatus) -> &'static str {
+    match status {
+        JobStatus::Waiting => "\x1b[90m",
+        JobStatus::InProgress { .. } => "\x1b[34m",
+        JobStatus::Failed { .. } => "\x1b[31m",
+        JobStatus::Succeeded { .. } => "\x1b[32m",
+    }
+}
+
+#[test]
+pub fn test_render_diamond() {
+    use crate::error::Error;
+    use crate::job::Job;
+
+    let top = Job::from_fn("Top", |_| Ok("top".to_string()));
+    let mut left = Job::from_fn("Left", |_| Ok("left".to_string()));
+    let mut right = Job::from_fn("Right", |_| {
+        Err(Error::Bash {
+            e: "right".to_string(),
+        })
+    });
+    let mut bottom = Job::from_fn("Bottom \"quoted\"", |_| Ok("bottom".to_string()));
+
+    left.add_dependency(top.get_id());
+    right.add_dependency(top.get_id());
+    bottom.add_dependency(left.get_id());
+    bottom.add_dependency(right.get_id());
+
+    let mut pipeline = Pipeline::new();
+    pipeline.add_jobs(vec![top, left, right, bottom]);
+
+    let dot = to_dot(&pipeline);
+    assert_eq!(dot.matches("job3 [").count(), 1);
+    assert!(dot.contains("job3 [label=\"Bottom \\\"quoted\\\"\"];"));
+    assert!(dot.contains("job1 -> job3;"));
+    assert!(dot.contains("job2 -> job3;"));
+    assert!(!dot.contains("fillcolor"));
+
+    let mermaid = to_mermaid(&pipeline);
+    assert!(mermaid.starts_with("flowchart TD\n"));
+    assert!(mermaid.contains("job3[\"Bottom #quot;quoted#quot;\"]"));
+    assert!(mermaid.contains("job0 --> job1"));
+    assert!(!mermaid.contains("classDef"));
+
+    let expected = [
+        "Stage 1",
+        "+-- Top",
+        "Stage 2",
+        "+-- Left",
+        "|     <- Top",
+        "+-- Right",
+        "      <- Top",
+        "Stage 3",
+        "+-- Bottom \"quoted\"",
+        "      <- Left",
+        "      <- Right",
+        "",
+    ];
+    assert_eq!(to_ascii(&pipeline, false), expected.join("\n"));
+
+    smol::block_on(pipeline.execute()).unwrap();
+
+    let dot = to_dot(&pipeline);
+    assert!(dot.contains("job0 [label=\"Top\", style=filled, fillcolor=\"#9be39b\"];"));
+    assert!(dot.contains("job2 [label=\"Right\", style=filled, fillcolor=\"#ff8e8e\"];"));
+    assert!(dot
+        .contains("job3 [label=\"Bottom \\\"quoted\\\"\", style=filled, fillcolor=\"#d0d0d0\"];"));
+
+    let mermaid = to_mermaid(&pipeline);
+    assert!(mermaid.contains("class job0,job1 succeeded"));
+    assert!(mermaid.contains("class job2 failed"));
+
+    let ascii = to_ascii(&pipeline, false);
+    assert!(ascii.contains("+-- Right [failed]\n"));
+    assert!(ascii.contains("+-- Bottom \"quoted\" [waiting]\n"));
+}
+
+#[test]
+pub fn test_render_shared_dependencies() {
+    use crate::job::Job;
+    use crate::job_type::JobType;
+
+    let first = Job::new("First", JobType::Noop);
+    let second = Job::new("Second", JobType::Noop);
+    let mut reverse = Job::new("Reverse join", JobType::Noop);
+    let mut normal = Job::new("Normal join", JobType::Noop);
+    for job in [&mut reverse, &mut normal] {
+        job.add_dependency(first.get_id());
+        job.add_dependency(second.get_id());
+    }
+
+    let mut pipeline = Pipeline::new();
+    pipeline.add_jobs(vec![first, second, reverse, normal]);
+
+    let expected = [
+        "Stage 1",
+        "+-- First",
+        "+-- Second",
+        "Stage 2",
+        "+-- Reverse join",
+        "|     <- First",
+        "|     <- Second",
+        "+-- Normal join",
+        "      <- First",
+        "      <- Second",
+        "",
+    ];
+    assert_eq!(to_ascii(&pipeline, false), expected.join("\n"));
+}
@@ -0,0 +1,563 @@
+//! Reports of a finished pipeline, for CI systems, other tools and people.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::job::{Job, JobStatus};
+use crate::pipeline::Pipeline;
+use crate::pipeline_tree::PipelineTree;
+use crate::render::{status_colour, status_name};
+use crate::state::JobState;
+use crate::Result;
+
+/// Version of the JSON report, which is bumped whenever a field changes or is removed
+pub const REPORT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Junit,
+    Json,
+    /// Single page with the dependency graph, a timeline and the jobs' details
+    Html,
+}
+
+impl ReportFormat {
+    /// Guesses the format from the file's extension
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = path.rsplit_once('.')?.1;
+        extension.parse().ok()
+    }
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "junit" | "xml" => Ok(ReportFormat::Junit),
+            "json" => Ok(ReportFormat::Json),
+            "html" | "htm" => Ok(ReportFormat::Html),
+            _ => Err(format!(
+                "Unknown report format {s}, expected junit, json or html"
+            )),
+        }
+    }
+}
+
+pub fn report(pipeline: &Pipeline, format: ReportFormat) -> Result<String> {
+    match format {
+        ReportFormat::Junit => Ok(to_junit(pipeline)),
+        ReportFormat::Json => to_json(pipeline),
+        ReportFormat::Html => Ok(to_html(pipeline)),
+    }
+}
+
+/// Machine-readable summary of a run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunReport {
+    pub version: u32,
+    pub run_id: Uuid,
+    /// How the run ended, which is failed when any job failed or didn't finish
+    pub status: JobState,
+    /// Seconds spent in jobs
+    pub duration: f64,
+    pub jobs: Vec<JobReport>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobReport {
+    pub id: Uuid,
+    pub name: String,
+    /// What the job does, like `bash echo hi`
+    pub description: String,
+    pub status: JobState,
+    /// Seconds the job took, if it finished
+    pub duration: Option<f64>,
+    pub attempts: u32,
+    /// Names of the jobs that this job depends on
+    pub dependencies: Vec<String>,
+    pub output: Option<String>,
+    pub error: Option<String>,
+    pub logs: Vec<String>,
+}
+
+impl RunReport {
+    pub fn new(pipeline: &Pipeline) -> Self {
+        let jobs = &pipeline.jobs;
+        let names = jobs
+            .iter()
+            .map(|job| (job.get_id(), job.name.clone()))
+            .collect::<BTreeMap<_, _>>();
+
+        let jobs = jobs
+            .iter()
+            .map(|job| {
+                let status = job.get_status();
+                let (output, error) = match &status {
+                    JobStatus::Succeeded { msg, .. } => (Some(msg.clone()), None),
+                    JobStatus::Failed { msg, .. } => (None, Some(msg.clone())),
+                    _ => (None, None),
+                };
+
+                JobReport {
+                    id: job.get_id(),
+                    name: job.name.clone(),
+                    description: job.job_type.describe(),
+                    status: JobState::from(&status),
+                    duration: job.get_duration().map(|d| d.as_secs_f64()),
+                    attempts: job.attempts,
+                    dependencies: job
+                        .dependencies
+                        .iter()
+                        .filter_map(|dependency| names.get(dependency).cloned())
+                        .collect(),
+                    output,
+                    error,
+                    logs: job.logs.clone(),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // Pipelines that weren't run by `execute` only have their jobs to go by
+        let status = match pipeline.run_state() {
+            Some(JobState::Running) | None => {
+                if jobs.iter().all(|job| job.status == JobState::Succeeded) {
+                    JobState::Succeeded
+                } else {
+                    JobState::Failed
+                }
+            }
+            Some(state) => state,
+        };
+        RunReport {
+            version: REPORT_VERSION,
+            run_id: pipeline.run_id(),
+            status,
+            duration: jobs.iter().filter_map(|job| job.duration).sum(),
+            jobs,
+        }
+    }
+}
+
+pub fn to_json(pipeline: &Pipeline) -> Result<String> {
+    Ok(serde_json::to_string_pretty(&RunReport::new(pipeline))?)
+}
+
+/// One test case per job, where jobs that didn't run are skipped
+pub fn to_junit(pipeline: &Pipeline) -> String {
+    let jobs = &pipeline.jobs;
+    let count = |f: fn(&Job) -> bool| jobs.iter().filter(|job| f(job)).count();
+    let failures = count(|job| job.status.is_failed());
+    let skipped = count(|job| job.status.is_waiting() || job.status.is_running());
+    let time = jobs
+        .iter()
+        .filter_map(|job| job.get_duration())
+        .map(|d| d.as_secs_f64())
+        .sum::<f64>();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let summary = format!(
+        "tests=\"{}\" failures=\"{failures}\" errors=\"0\" skipped=\"{skipped}\" time=\"{time:.3}\"",
+        jobs.len()
+    );
+    let _ = writeln!(xml, "<testsuites name=\"waterflow\" {summary}>");
+    let _ = writeln!(
+        xml,
+        "  <testsuite name=\"waterflow\" id=\"{}\" {summary}>",
+        pipeline.run_id()
+    );
+
+    for job in jobs {
+        let time = job.get_duration().unwrap_or_default().as_secs_f64();
+        let _ = writeln!(
+            xml,
+            "    <testcase name=\"{}\" classname=\"waterflow\" time=\"{time:.3}\">",
+            escape(&job.name)
+        );
+        match job.get_status() {
+            JobStatus::Failed { msg, .. } => {
+                let message = msg.lines().next().unwrap_or_default();
+                let _ = writeln!(
+                    xml,
+                    "      <failure message=\"{}\">{}</failure>",
+                    escape(message),
+                    escape(&msg)
+                );
+            }
+            JobStatus::Succeeded { msg, .. } => {
+                let _ = writeln!(xml, "      <system-out>{}</system-out>", escape(&msg));
+            }
+            JobStatus::Waiting | JobStatus::InProgress { .. } => {
+                let _ = writeln!(xml, "      <skipped message=\"The job didn't run\"/>");
+            }
+        }
+        let _ = writeln!(xml, "    </testcase>");
+    }
+
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    xml
+}
+
+const HTML_STYLE: &str = "
+body { font-family: sans-serif; margin: 2em auto; max-width: 1100px; color: #222; }
+h1 { font-size: 1.5em; }
+.status { border-radius: 4px; padding: 0 0.4em; font-size: 0.8em; font-weight: normal; }
+.job { border-top: 1px solid #ddd; padding: 0.5em 0; }
+.job th { text-align: left; padding-right: 1em; font-weight: normal; color: #666; }
+pre { background: #f5f5f5; padding: 0.5em; overflow-x: auto; white-space: pre-wrap; }
+svg text { font-size: 12px; }
+";
+
+/// Single HTML file with the dependency graph, a timeline of when the jobs ran and their details
+pub fn to_html(pipeline: &Pipeline) -> String {
+    let tree = PipelineTree::new(pipeline);
+    let indices = pipeline
+        .jobs
+        .iter()
+        .enumerate()
+        .map(|(i, job)| (job.get_id(), i))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut html = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    let _ = writeln!(html, "<title>waterflow run {}</title>", pipeline.run_id());
+    let _ = writeln!(html, "<style>{HTML_STYLE}</style>\n</head>\n<body>");
+
+    let report = RunReport::new(pipeline);
+    let count = |state: JobState| report.jobs.iter().filter(|job| job.status == state).count();
+    let _ = writeln!(html, "<h1>Run {}</h1>", pipeline.run_id());
+    let _ = writeln!(
+        html,
+        "<p>{} jobs: {} succeeded, {} failed, {} didn't finish. {:.3}s spent in jobs.</p>",
+        report.jobs.len(),
+        count(JobState::Succeeded),
+        count(JobState::Failed),
+        count(JobState::Waiting) + count(JobState::Running),
+        report.duration
+    );
+
+    html.push_str("<h2>Dependency graph</h2>\n");
+    html.push_str(&graph_svg(pipeline, &tree, &indices));
+    html.push_str("<h2>Timeline</h2>\n");
+    html.push_str(&timeline_svg(pipeline));
+
+    html.push_str("<h2>Jobs</h2>\n");
+    for (i, job) in pipeline.jobs.iter().enumerate() {
+        let status = job.get_status();
+        let _ = writeln!(
+            html,
+            "<section class=\"job\" id=\"job{i}\">\n<h3>{} <span class=\"status\" style=\"background: {}\">{}</span></h3>",
+            escape(&job.name),
+            status_colour(&status),
+            status_name(&status)
+        );
+
+        let dependencies = job
+            .dependencies
+            .iter()
+            .filter_map(|dependency| {
+                let index = indices.get(dependency)?;
+                let name = &pipeline.jobs[*index].name;
+                Some(format!("<a href=\"#job{index}\">{}</a>", escape(name)))
+            })
+            .collect::<Vec<_>>();
+        let _ = writeln!(
+            html,
+            "<table>\n<tr><th>Type</th><td>{}</td></tr>\n<tr><th>Duration</th><td>{}</td></tr>\n<tr><th>Attempts</th><td>{}</td></tr>\n<tr><th>Depends on</th><td>{}</td></tr>\n</table>",
+            escape(&job.job_type.describe()),
+            job.get_duration()
+                .map_or("-".to_string(), |d| format!("{:.3}s", d.as_secs_f64())),
+            job.attempts,
+            if dependencies.is_empty() {
+                "-".to_string()
+            } else {
+                dependencies.join(", ")
+            }
+        );
+
+        for (n, input) in job.input.iter().enumerate() {
+            html.push_str(&text_block(&format!("Input {}", n + 1), input, false));
+        }
+        match &status {
+            JobStatus::Succeeded { msg, .. } => html.push_str(&text_block("Output", msg, true)),
+            JobStatus::Failed { msg, .. } => html.push_str(&text_block("Error", msg, true)),
+            _ => {}
+        }
+        if !job.logs.is_empty() {
+            let title = format!("Logs ({} lines)", job.logs.len());
+            html.push_str(&text_block(&title, &job.logs.join("\n"), false));
+        }
+        html.push_str("</section>\n");
+    }
+
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn text_block(title: &str, text: &str, open: bool) -> String {
+    format!(
+        "<details{}><summary>{}</summary><pre>{}</pre></details>\n",
+        if open { " open" } else { "" },
+        escape(title),
+        escape(text)
+    )
+}
+
+/// Draws the jobs in columns by their depth, with arrows from every dependency to its dependants
+fn graph_svg(pipeline: &Pipeline, tree: &PipelineTree, indices: &BTreeMap<Uuid, usize>) -> String {
+    const NODE_WIDTH: usize = 170;
+    const NODE_HEIGHT: usize = 36;
+    const COLUMN: usize = 220;
+    const ROW: usize = 56;
+    const MARGIN: usize = 10;
+
+    let depths = tree.depths();
+    let mut rows = BTreeMap::<usize, usize>::new();
+    let mut positions = BTreeMap::new();
+    for node in tree.nodes() {
+        let column = depths[&node.job_id];
+        let row = rows.entry(column).or_default();
+        positions.insert(node.job_id, (MARGIN + column * COLUMN, MARGIN + *row * ROW));
+        *row += 1;
+    }
+
+    let columns = rows.len();
+    let max_rows = rows.values().copied().max().unwrap_or(0);
+    let width = 2 * MARGIN + (columns * COLUMN).saturating_sub(COLUMN - NODE_WIDTH);
+    let height = 2 * MARGIN + (max_rows * ROW).saturating_sub(ROW - NODE_HEIGHT);
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\">\n"
+    );
+    svg.push_str("<defs><marker id=\"arrow\" viewBox=\"0 0 10 10\" refX=\"10\" refY=\"5\" markerWidth=\"8\" markerHeight=\"8\" orient=\"auto\"><path d=\"M 0 0 L 10 5 L 0 10 z\" fill=\"#555\"/></marker></defs>\n");
+
+    for node in tree.nodes() {
+        let (x2, y2) = positions[&node.job_id];
+        let y2 = y2 + NODE_HEIGHT / 2;
+        for dependency in &node.dependencies {
+            let (x1, y1) = positions[dependency];
+            let (x1, y1) = (x1 + NODE_WIDTH, y1 + NODE_HEIGHT / 2);
+            let middle = (x1 + x2) / 2;
+            let _ = writeln!(
+                svg,
+                "<path d=\"M {x1} {y1} C {middle} {y1}, {middle} {y2}, {x2} {y2}\" fill=\"none\" stroke=\"#555\" marker-end=\"url(#arrow)\"/>"
+            );
+        }
+    }
+
+    for node in tree.nodes() {
+        let (x, y) = positions[&node.job_id];
+        let job = &pipeline.jobs[indices[&node.job_id]];
+        let _ = writeln!(
+            svg,
+            "<a href=\"#job{}\"><g><title>{}</title><rect x=\"{x}\" y=\"{y}\" width=\"{NODE_WIDTH}\" height=\"{NODE_HEIGHT}\" rx=\"6\" fill=\"{}\" stroke=\"#555\"/><text x=\"{}\" y=\"{}\">{}</text></g></a>",
+            indices[&node.job_id],
+            escape(&job.name),
+            status_colour(&job.status),
+            x + 10,
+            y + 23,
+            escape(&shorten(&job.name, 22))
+        );
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Gantt chart of when every job started and how long it took
+fn timeline_svg(pipeline: &Pipeline) -> String {
+    const LABEL: f64 = 180.0;
+    const WIDTH: f64 = 900.0;
+    const ROW: f64 = 26.0;
+    const BAR: f64 = 18.0;
+    const AXIS: f64 = 24.0;
+    const TICKS: u32 = 5;
+
+    let first = pipeline.jobs.iter().filter_map(|job| job.started_at).min();
+    let intervals = pipeline
+        .jobs
+        .iter()
+        .map(|job| {
+            let start = job.started_at?.duration_since(first?);
+            Some((start, job.get_duration()?))
+        })
+        .collect::<Vec<_>>();
+
+    let total = intervals
+        .iter()
+        .flatten()
+        .map(|(start, duration)| *start + *duration)
+        .max()
+        .unwrap_or_default()
+        .max(Duration::from_millis(1));
+    let scale = (WIDTH - LABEL - 20.0) / total.as_secs_f64();
+    let height = AXIS + ROW * pipeline.jobs.len() as f64;
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{WIDTH}\" height=\"{height}\">\n"
+    );
+    for tick in 0..=TICKS {
+        let seconds = total.as_secs_f64() * f64::from(tick) / f64::from(TICKS);
+        let x = LABEL + seconds * scale;
+        let _ = writeln!(
+            svg,
+            "<line x1=\"{x:.1}\" y1=\"{AXIS}\" x2=\"{x:.1}\" y2=\"{height}\" stroke=\"#ddd\"/><text x=\"{x:.1}\" y=\"14\" text-anchor=\"middle\">{seconds:.2}s</text>"
+        );
+    }
+
+    for (row, (job, interval)) in pipeline.jobs.iter().zip(&intervals).enumerate() {
+        let y = AXIS + ROW * row as f64;
+        let _ = writeln!(
+            svg,
+            "<text x=\"0\" y=\"{:.1}\">{}</text>",
+            y + BAR - 4.0,
+            escape(&shorten(&job.name, 24))
+        );
+
+        let Some((start, duration)) = interval else {
+            continue;
+        };
+        let _ = writeln!(
+            svg,
+            "<rect x=\"{:.1}\" y=\"{y:.1}\" width=\"{:.1}\" height=\"{BAR}\" fill=\"{}\"><title>{}: started at {:.3}s, took {:.3}s</title></rect>",
+            LABEL + start.as_secs_f64() * scale,
+            (duration.as_secs_f64() * scale).max(1.0),
+            status_colour(&job.status),
+            escape(&job.name),
+            start.as_secs_f64(),
+            duration.as_secs_f64()
+        );
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn shorten(text: &str, length: usize) -> String {
+    if text.chars().count() > length {
+        format!("{}...", text.chars().take(length - 3).collect::<String>())
+    } else {
+        text.to_string()
+    }
+}
+
+/// Escapes the text for XML, dropping the control characters that XML 1.0 can't contain
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\n' | '\r' | '\t' => escaped.push(c),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[test]
+pub fn test_reports() {
+    use crate::error::Error;
+
+    let build = Job::from_fn("Build <app>", |_| Ok("built & \"ready\"".to_string()));
+    let mut test = Job::from_fn("Test", |_| {
+        Err(Error::Bash {
+            e: "2 tests failed\nsee the log".to_string(),
+        })
+    });
+    let mut deploy = Job::from_fn("Deploy", |_| Ok("deployed".to_string()));
+    test.add_dependency(build.get_id());
+    deploy.add_dependency(test.get_id());
+
+    let mut pipeline = Pipeline::new();
+    pipeline.add_jobs(vec![build, test, deploy]);
+    smol::block_on(pipeline.execute()).unwrap();
+
+    let junit = to_junit(&pipeline);
+    assert!(junit.contains("tests=\"3\" failures=\"1\" errors=\"0\" skipped=\"1\""));
+    assert!(junit.contains("<testcase name=\"Build &lt;app&gt;\" classname=\"waterflow\""));
+    assert!(junit.contains("<system-out>built &amp; &quot;ready&quot;</system-out>"));
+    assert!(junit.contains(
+        "<failure message=\"Bash execution failed! 2 tests failed\">Bash execution failed! 2 tests failed\nsee the log</failure>"
+    ));
+    assert!(junit.contains("<skipped message=\"The job didn't run\"/>"));
+    assert!(junit.ends_with("</testsuites>\n"));
+
+    let json = to_json(&pipeline).unwrap();
+    let report = serde_json::from_str::<RunReport>(&json).unwrap();
+    assert_eq!(report.run_id, pipeline.run_id());
+    assert_eq!(report.version, REPORT_VERSION);
+    assert_eq!(report.status, JobState::Failed);
+    assert_eq!(report.jobs[1].dependencies, ["Build <app>"]);
+    assert_eq!(report.jobs[1].attempts, 1);
+    assert!(report.jobs[1].error.is_some());
+    assert_eq!(report.jobs[2].status, JobState::Waiting);
+    assert_eq!(report.jobs[2].duration, None);
+
+    assert_eq!(
+        ReportFormat::from_path("target/junit.xml"),
+        Some(ReportFormat::Junit)
+    );
+    assert_eq!(ReportFormat::from_path("report"), None);
+}
+
+#[test]
+pub fn test_unfinished_report() {
+    let mut pipeline = Pipeline::new();
+    let cancel = pipeline.cancel_handle();
+    let first = Job::from_fn("First", move |_| {
+        cancel.cancel();
+        Ok("first".to_string())
+    });
+    let mut second = Job::from_fn("Second", |_| Ok("second".to_string()));
+    second.add_dependency(first.get_id());
+    pipeline.add_jobs(vec![first, second]);
+
+    assert!(smol::block_on(pipeline.execute()).is_err());
+    let report = RunReport::new(&pipeline);
+    assert_eq!(report.status, JobState::Cancelled);
+    assert_eq!(report.jobs[1].status, JobState::Waiting);
+
+    // Without a run, a job that didn't finish fails the report
+    let mut pipeline = Pipeline::new();
+    pipeline.add_job(Job::from_fn("Unrun", |_| Ok(String::new())));
+    assert_eq!(RunReport::new(&pipeline).status, JobState::Failed);
+}
+
+#[test]
+pub fn test_html_report() {
+    let build = Job::from_fn("Build", |context| {
+        context.log("compiling <main>");
+        Ok("built".to_string())
+    });
+    let mut test = Job::from_fn("Test & lint", |context| Ok(context.input.join("")));
+    test.add_dependency(build.get_id());
+    let waiting = Job::new("Never run", crate::job_type::JobType::Noop);
+
+    let mut pipeline = Pipeline::new();
+    pipeline.add_jobs(vec![build, test]);
+    smol::block_on(pipeline.execute()).unwrap();
+    pipeline.add_job(waiting);
+
+    let html = to_html(&pipeline);
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("3 jobs: 2 succeeded, 0 failed, 1 didn't finish."));
+    assert_eq!(html.matches("<svg").count(), 2);
+    assert_eq!(html.matches("marker-end").count(), 1);
+    assert!(html.contains("<a href=\"#job0\">Build</a>"));
+    assert!(html.contains("Test &amp; lint"));
+    assert!(html.contains("<summary>Logs (1 lines)</summary><pre>compiling &lt;main&gt;</pre>"));
+    assert!(html.contains("<summary>Input 1</summary><pre>built</pre>"));
+    assert!(!html.contains("<script"));
+
+    // Jobs that didn't run have a row on the timeline, but no bar
+    assert_eq!(html.matches("<rect").count(), 3 + 2);
+}
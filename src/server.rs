@@ -0,0 +1,962 @@
+//! REST API that queues and runs pipeline definitions.
+//!
+//! | Request                            | Response                                                 |
+//! |------------------------------------|----------------------------------------------------------|
+//! | `POST /runs`                       | Queues the pipeline definition in the body               |
+//! | `GET /runs`                        | Runs, newest first                                       |
+//! | `GET /runs/<run>`                  | State of the run and its jobs                            |
+//! | `GET /runs/<run>/events`           | Server-sent events of the run's changes                  |
+//! | `GET /runs/<run>/jobs/<job>/logs`  | Job's logs, `?follow` streams them as server-sent events |
+//! | `GET /runs/<run>/graph`            | Jobs with their depth in the graph and dependencies      |
+//! | `POST /runs/<run>/cancel`          | Cancels the run                                          |
+//! | `POST /runs/<run>/jobs/<job>/retry`| Runs the failed job and its dependants again             |
+//! | `POST /runs/<run>/jobs/<job>/rerun`| Runs the job and its dependants again                    |
+//! | `GET /metrics`                     | Job metrics in the Prometheus format                     |
+//! | `GET /`                            | Dashboard of the runs                                    |
+//!
+//! Jobs can be referred to by their id or by their name.
+//!
+//! The `POST` requests need the server's token as `Authorization: Bearer <token>`, and a JSON
+//! content type, which browsers don't send from other sites without asking the server first.
+
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+use tiny_http::{Header, Method, Request, Response};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::definition::PipelineDefinition;
+use crate::error::Error;
+use crate::event::PipelineEvent;
+use crate::executor::{ExecutorRegistry, JobExecutor};
+use crate::metrics::Metrics;
+use crate::pipeline::{CancelHandle, Pipeline};
+use crate::pipeline_tree::PipelineTree;
+use crate::remote::{tokens_match, Coordinator};
+use crate::state::{JobRecord, JobState};
+use crate::Result;
+
+/// Run as it's shown by the API
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunStatus {
+    pub run_id: Uuid,
+    /// Runs are waiting while they're queued
+    pub state: JobState,
+    pub submitted_at: SystemTime,
+    pub started_at: Option<SystemTime>,
+    pub finished_at: Option<SystemTime>,
+    /// Why the run couldn't be executed
+    pub error: Option<String>,
+    pub jobs: Vec<JobRecord>,
+}
+
+impl RunStatus {
+    fn job(&self, job: &str) -> Option<&JobRecord> {
+        self.jobs
+            .iter()
+            .find(|j| j.job_id.to_string() == job || j.name == job)
+    }
+}
+
+/// Job as it's drawn in the dashboard's graph
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GraphNode {
+    pub job_id: Uuid,
+    pub name: String,
+    pub job_type: String,
+    /// Length of the longest path from a job without dependencies
+    pub depth: usize,
+    pub dependencies: Vec<Uuid>,
+}
+
+#[derive(Debug)]
+struct Run {
+    status: RunStatus,
+    graph: Vec<GraphNode>,
+    cancel: CancelHandle,
+    /// Streams that are following the run
+    subscribers: Vec<flume::Sender<PipelineEvent>>,
+    finished: bool,
+    /// Pipeline after it ran, which is kept to run some of its jobs again
+    pipeline: Option<Pipeline>,
+}
+
+impl Run {
+    fn apply(&mut self, event: &PipelineEvent) {
+        let status = &mut self.status;
+        match event {
+            PipelineEvent::Run { run } => {
+                status.state = run.state;
+                status.started_at = Some(run.started_at);
+                status.finished_at = run.finished_at;
+            }
+            PipelineEvent::Job { job, .. } => {
+                if let Some(record) = status.jobs.iter_mut().find(|j| j.job_id == job.job_id) {
+                    *record = job.clone();
+                }
+            }
+            PipelineEvent::Log { job_id, line, .. } => {
+                if let Some(record) = status.jobs.iter_mut().find(|j| j.job_id == *job_id) {
+                    record.logs.push(line.clone());
+                }
+            }
+        }
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    /// Follows the run, which gets nothing if the run already finished
+    fn subscribe(&mut self) -> flume::Receiver<PipelineEvent> {
+        let (tx, rx) = flume::unbounded();
+        if !self.finished {
+            self.subscribers.push(tx);
+        }
+        rx
+    }
+}
+
+/// Builds the server, which runs the submitted pipelines with its executors and metrics
+#[derive(Debug, Clone)]
+pub struct Server {
+    executors: ExecutorRegistry,
+    metrics: Metrics,
+    workers: usize,
+    coordinator: Option<Coordinator>,
+    token: String,
+    kept_runs: usize,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Server {
+            executors: ExecutorRegistry::default(),
+            metrics: Metrics::default(),
+            workers: 1,
+            coordinator: None,
+            token: String::new(),
+            kept_runs: 100,
+        }
+    }
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Server::default()
+    }
+
+    pub fn with_executor(mut self, executor: impl JobExecutor + 'static) -> Self {
+        self.executors.register(executor);
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// How many pipelines can run at the same time, one by default
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// Runs the jobs that select a worker on the coordinator's remote workers
+    pub fn with_coordinator(mut self, coordinator: Coordinator) -> Self {
+        self.coordinator = Some(coordinator);
+        self
+    }
+
+    /// How many finished runs are kept, 100 by default, after which the oldest are forgotten
+    pub fn with_kept_runs(mut self, kept_runs: usize) -> Self {
+        self.kept_runs = kept_runs;
+        self
+    }
+
+    /// Token that the `POST` requests need, the server doesn't start without one
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.token = token.to_string();
+        self
+    }
+
+    /// Starts listening, the requests are handled and the pipelines run on background threads
+    pub fn serve(self, address: impl ToSocketAddrs) -> Result<ServerHandle> {
+        if self.token.is_empty() {
+            return Err(Error::MissingToken);
+        }
+        let address = address.to_socket_addrs()?.next().ok_or(Error::Io {
+            e: std::io::Error::other("No address to listen on"),
+        })?;
+        let http = tiny_http::Server::http(address).map_err(|e| Error::Io {
+            e: std::io::Error::other(e),
+        })?;
+        let address = http.server_addr().to_ip().unwrap_or(address);
+        let http = Arc::new(http);
+
+        let (queue, queued) = flume::unbounded();
+        let shared = Arc::new(Shared {
+            server: self,
+            runs: Mutex::default(),
+            queue,
+        });
+
+        for _ in 0..shared.server.workers {
+            let queued = queued.clone();
+            let shared = shared.clone();
+            std::thread::spawn(move || shared.run_queued(queued));
+        }
+
+        let listener = {
+            let http = http.clone();
+            std::thread::spawn(move || {
+                for request in http.incoming_requests() {
+                    let shared = shared.clone();
+                    std::thread::spawn(move || {
+                        if let Err(e) = shared.handle(request) {
+                            warn!("Failed to respond to a request: {e}");
+                        }
+                    });
+                }
+            })
+        };
+
+        info!("Listening on http://{address}");
+        Ok(ServerHandle {
+            address,
+            http,
+            listener,
+        })
+    }
+}
+
+/// Server that's running in the background
+pub struct ServerHandle {
+    address: SocketAddr,
+    http: Arc<tiny_http::Server>,
+    listener: JoinHandle<()>,
+}
+
+impl std::fmt::Debug for ServerHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ServerHandle")
+            .field("address", &self.address)
+            .finish()
+    }
+}
+
+impl ServerHandle {
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Blocks until the server is shut down
+    pub fn join(self) {
+        let _ = self.listener.join();
+    }
+
+    /// Stops accepting requests, the queued pipelines still run
+    pub fn shutdown(self) {
+        self.http.unblock();
+        self.join();
+    }
+}
+
+/// Largest pipeline definition that can be submitted
+const MAX_BODY_SIZE: u64 = 1024 * 1024;
+
+const DASHBOARD_HTML: &str = include_str!("dashboard/index.html");
+const DASHBOARD_JS: &str = include_str!("dashboard/dashboard.js");
+const DASHBOARD_CSS: &str = include_str!("dashboard/dashboard.css");
+
+type EventFormat = Box<dyn Fn(&PipelineEvent) -> Option<String> + Send>;
+
+/// Pipeline that's waiting for a worker
+#[derive(Debug)]
+struct Queued {
+    pipeline: Pipeline,
+    /// Job to run again with its dependants, instead of running the whole pipeline
+    from: Option<Uuid>,
+}
+
+/// A response, or a stream of server-sent events that ends when the run finishes
+enum Reply {
+    Json(u16, String),
+    Text(u16, String),
+    Asset(&'static str, &'static str),
+    Events {
+        first: Vec<String>,
+        events: flume::Receiver<PipelineEvent>,
+        format: EventFormat,
+    },
+}
+
+#[derive(Debug)]
+struct Shared {
+    server: Server,
+    runs: Mutex<BTreeMap<Uuid, Run>>,
+    queue: flume::Sender<Queued>,
+}
+
+impl Shared {
+    fn runs(&self) -> MutexGuard<'_, BTreeMap<Uuid, Run>> {
+        self.runs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn run_queued(&self, queued: flume::Receiver<Queued>) {
+        for Queued { mut pipeline, from } in queued.iter() {
+            let result = futures_lite::future::block_on(async {
+                match from {
+                    Some(job) => pipeline.execute_from(job).await,
+                    None => pipeline.execute().await,
+                }
+            });
+
+            // The run is marked as finished once its events end, by then it has the pipeline back
+            let mut runs = self.runs();
+            pipeline.unsubscribe();
+            let Some(run) = runs.get_mut(&pipeline.run_id()) else {
+                continue;
+            };
+            if let Err(e) = result {
+                if !matches!(e, Error::Cancelled) {
+                    warn!("Run {} failed: {e}", pipeline.run_id());
+                    run.status.state = JobState::Failed;
+                    run.status.error = Some(e.to_string());
+                }
+            }
+            run.pipeline = Some(pipeline);
+        }
+    }
+
+    /// Applies the pipeline's events to its run, until the pipeline unsubscribes after running
+    fn follow(self: &Arc<Self>, pipeline: &mut Pipeline) {
+        let run_id = pipeline.run_id();
+        let events = pipeline.subscribe();
+        let shared = self.clone();
+        std::thread::spawn(move || {
+            for event in events.iter() {
+                if let Some(run) = shared.runs().get_mut(&run_id) {
+                    run.apply(&event);
+                }
+            }
+            let mut runs = shared.runs();
+            if let Some(run) = runs.get_mut(&run_id) {
+                run.finished = true;
+                run.subscribers.clear();
+            }
+            shared.forget_finished(&mut runs);
+        });
+    }
+
+    /// Drops the oldest finished runs with their pipelines, beyond the ones that are kept
+    fn forget_finished(&self, runs: &mut BTreeMap<Uuid, Run>) {
+        let mut finished = runs
+            .values()
+            .filter(|run| run.finished)
+            .map(|run| (run.status.submitted_at, run.status.run_id))
+            .collect::<Vec<_>>();
+        finished.sort();
+        let forgotten = finished.len().saturating_sub(self.server.kept_runs);
+        for (_, run_id) in &finished[..forgotten] {
+            runs.remove(run_id);
+        }
+    }
+
+    fn submit(self: &Arc<Self>, body: &str) -> Result<RunStatus> {
+        let definition = PipelineDefinition::from_json(body)?;
+        let mut pipeline = Pipeline::new().with_metrics(self.server.metrics.clone());
+        pipeline.executors = self.server.executors.clone();
+        if let Some(coordinator) = &self.server.coordinator {
+            pipeline.set_coordinator(coordinator.clone());
+        }
+        pipeline.load_definition(&definition)?;
+        pipeline.validate()?;
+
+        let tree = PipelineTree::new(&pipeline);
+        let depths = tree.depths();
+        let graph = tree
+            .nodes()
+            .map(|node| GraphNode {
+                job_id: node.job_id,
+                name: node.name.clone(),
+                job_type: node.job_type.kind().to_string(),
+                depth: depths[&node.job_id],
+                dependencies: node.dependencies.clone(),
+            })
+            .collect();
+
+        let run_id = pipeline.run_id();
+        let status = RunStatus {
+            run_id,
+            state: JobState::Waiting,
+            submitted_at: SystemTime::now(),
+            started_at: None,
+            finished_at: None,
+            error: None,
+            jobs: pipeline.jobs.iter().map(JobRecord::from_job).collect(),
+        };
+        self.runs().insert(
+            run_id,
+            Run {
+                status: status.clone(),
+                graph,
+                cancel: pipeline.cancel_handle(),
+                subscribers: vec![],
+                finished: false,
+                pipeline: None,
+            },
+        );
+
+        self.follow(&mut pipeline);
+        self.queue
+            .send(Queued {
+                pipeline,
+                from: None,
+            })
+            .map_err(|_| Error::Flume)?;
+        Ok(status)
+    }
+
+    /// Queues the job and its dependants to run again, with the outputs of the other jobs
+    fn rerun(self: &Arc<Self>, run: &mut Run, job: &str, only_failed: bool) -> Reply {
+        let Some(job) = run.status.job(job) else {
+            return error(404, format!("There's no job {job} in the run"));
+        };
+        if only_failed && job.state != JobState::Failed {
+            return error(409, format!("Job {} didn't fail", job.name));
+        }
+        let job_id = job.job_id;
+        if !run.finished {
+            return error(409, "The run hasn't finished yet");
+        }
+        let Some(mut pipeline) = run.pipeline.take() else {
+            return error(409, "The run hasn't finished yet");
+        };
+
+        run.cancel.reset();
+        run.finished = false;
+        run.status.state = JobState::Waiting;
+        run.status.finished_at = None;
+        run.status.error = None;
+        self.follow(&mut pipeline);
+        match self.queue.send(Queued {
+            pipeline,
+            from: Some(job_id),
+        }) {
+            Ok(()) => json(202, &run.status),
+            Err(_) => error(500, Error::Flume),
+        }
+    }
+
+    fn handle(self: &Arc<Self>, mut request: Request) -> std::io::Result<()> {
+        let url = request.url().to_string();
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+        let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+
+        if *request.method() == Method::Post {
+            if let Some(reply) = self.reject(&request) {
+                return respond(request, reply);
+            }
+        }
+
+        let reply = match (request.method(), segments.as_slice()) {
+            (Method::Post, ["runs"]) => {
+                let mut body = String::new();
+                request
+                    .as_reader()
+                    .take(MAX_BODY_SIZE + 1)
+                    .read_to_string(&mut body)?;
+                if body.len() as u64 > MAX_BODY_SIZE {
+                    error(
+                        413,
+                        format!("The body is larger than {MAX_BODY_SIZE} bytes"),
+                    )
+                } else {
+                    match self.submit(&body) {
+                        Ok(status) => json(201, &status),
+                        Err(e) => error(400, e),
+                    }
+                }
+            }
+            (Method::Get, ["runs"]) => {
+                let mut runs = self
+                    .runs()
+                    .values()
+                    .map(|run| run.status.clone())
+                    .collect::<Vec<_>>();
+                runs.sort_by_key(|run| std::cmp::Reverse(run.submitted_at));
+                json(200, &runs)
+            }
+            (Method::Get, ["runs", run_id]) => self.with_run(run_id, |run| json(200, &run.status)),
+            (Method::Get, ["runs", run_id, "events"]) => {
+                self.with_run(run_id, |run| Reply::Events {
+                    first: vec![sse(
+                        "status",
+                        &serde_json::to_string(&run.status).unwrap_or_default(),
+                    )],
+                    events: run.subscribe(),
+                    format: Box::new(|event| {
+                        Some(sse(event.kind(), &serde_json::to_string(event).ok()?))
+                    }),
+                })
+            }
+            (Method::Get, ["runs", run_id, "jobs", job, "logs"]) => {
+                let follow = query
+                    .split('&')
+                    .any(|q| q == "follow" || q == "follow=true");
+                self.with_run(run_id, |run| {
+                    let Some(job) = run.status.job(job) else {
+                        return error(404, format!("There's no job {job} in the run"));
+                    };
+                    if !follow {
+                        return Reply::Text(
+                            200,
+                            job.logs.iter().map(|line| format!("{line}\n")).collect(),
+                        );
+                    }
+
+                    let job_id = job.job_id;
+                    Reply::Events {
+                        first: job.logs.iter().map(|line| sse("log", line)).collect(),
+                        events: run.subscribe(),
+                        format: Box::new(move |event| match event {
+                            PipelineEvent::Log {
+                                job_id: id, line, ..
+                            } if *id == job_id => Some(sse("log", line)),
+                            _ => None,
+                        }),
+                    }
+                })
+            }
+            (Method::Get, ["runs", run_id, "graph"]) => {
+                self.with_run(run_id, |run| json(200, &run.graph))
+            }
+            (Method::Post, ["runs", run_id, "jobs", job, action @ ("retry" | "rerun")]) => {
+                self.with_run(run_id, |run| self.rerun(run, job, *action == "retry"))
+            }
+            (Method::Post, ["runs", run_id, "cancel"]) => self.with_run(run_id, |run| {
+                if run.finished {
+                    return error(409, "The run already finished");
+                }
+                run.cancel.cancel();
+                json(202, &run.status)
+            }),
+            (Method::Get, ["metrics"]) => Reply::Text(200, self.server.metrics.render()),
+            (Method::Get, [""]) => Reply::Asset("text/html; charset=utf-8", DASHBOARD_HTML),
+            (Method::Get, ["dashboard.js"]) => Reply::Asset("text/javascript", DASHBOARD_JS),
+            (Method::Get, ["dashboard.css"]) => Reply::Asset("text/css", DASHBOARD_CSS),
+            _ => error(
+                404,
+                format!("There's nothing at {} {path}", request.method()),
+            ),
+        };
+
+        respond(request, reply)
+    }
+
+    /// Turns the `POST` request away without the token or a JSON body
+    fn reject(&self, request: &Request) -> Option<Reply> {
+        let header = |name: &'static str| {
+            request
+                .headers()
+                .iter()
+                .find(|header| header.field.equiv(name))
+                .map(|header| header.value.as_str())
+        };
+
+        let token = header("Authorization").and_then(|value| value.strip_prefix("Bearer "));
+        if !token.is_some_and(|token| tokens_match(token, &self.server.token)) {
+            return Some(error(401, "The request needs the server's token"));
+        }
+        let content_type = header("Content-Type").and_then(|value| value.split(';').next());
+        if !content_type.is_some_and(|value| value.trim().eq_ignore_ascii_case("application/json"))
+        {
+            return Some(error(
+                415,
+                "The request's content type must be application/json",
+            ));
+        }
+        None
+    }
+
+    fn with_run(&self, run_id: &str, reply: impl FnOnce(&mut Run) -> Reply) -> Reply {
+        let Ok(run_id) = run_id.parse::<Uuid>() else {
+            return error(400, format!("Invalid run id {run_id}"));
+        };
+        match self.runs().get_mut(&run_id) {
+            Some(run) => reply(run),
+            None => error(404, Error::UnknownRun { run_id }),
+        }
+    }
+}
+
+fn json(status: u16, value: &impl Serialize) -> Reply {
+    match serde_json::to_string(value) {
+        Ok(body) => Reply::Json(status, body),
+        Err(e) => error(500, e),
+    }
+}
+
+fn error(status: u16, e: impl std::fmt::Display) -> Reply {
+    Reply::Json(
+        status,
+        serde_json::json!({ "error": e.to_string() }).to_string(),
+    )
+}
+
+/// Formats a server-sent event, which needs a `data` field for every line
+fn sse(event: &str, data: &str) -> String {
+    let data = data
+        .lines()
+        .map(|line| format!("data: {line}\n"))
+        .collect::<String>();
+    format!("event: {event}\n{data}\n")
+}
+
+fn respond(request: Request, reply: Reply) -> std::io::Result<()> {
+    let (status, content_type, body) = match reply {
+        Reply::Json(status, body) => (status, "application/json", body),
+        Reply::Text(status, body) => (status, "text/plain; charset=utf-8", body),
+        Reply::Asset(content_type, body) => (200, content_type, body.to_string()),
+        Reply::Events {
+            first,
+            events,
+            format,
+        } => return stream(request, first, events, format),
+    };
+
+    let header = Header::from_bytes("Content-Type", content_type).expect("Valid header");
+    request.respond(
+        Response::from_string(body)
+            .with_status_code(status)
+            .with_header(header),
+    )
+}
+
+/// Writes the events as they come in.
+///
+/// The response is chunked by hand, because tiny_http buffers the chunks of its responses.
+fn stream(
+    request: Request,
+    first: Vec<String>,
+    events: flume::Receiver<PipelineEvent>,
+    format: impl Fn(&PipelineEvent) -> Option<String>,
+) -> std::io::Result<()> {
+    let mut writer = request.into_writer();
+    write!(
+        writer,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nTransfer-Encoding: chunked\r\n\r\n"
+    )?;
+
+    let messages = first
+        .into_iter()
+        .chain(events.iter().filter_map(|event| format(&event)));
+    for message in messages {
+        write!(writer, "{:x}\r\n{message}\r\n", message.len())?;
+        writer.flush()?;
+    }
+    write!(writer, "0\r\n\r\n")?;
+    writer.flush()
+}
+
+#[cfg(test)]
+const TEST_TOKEN: &str = "s3cret";
+
+/// Sends a request with the token, and returns the response's status and body, which is still
+/// chunked for streams
+#[cfg(test)]
+fn request(address: SocketAddr, method: &str, path: &str, body: &str) -> (u16, String) {
+    let headers =
+        format!("Authorization: Bearer {TEST_TOKEN}\r\nContent-Type: application/json\r\n");
+    send(address, method, path, &headers, body)
+}
+
+/// Sends a request with the headers, which each end with a line break
+#[cfg(test)]
+fn send(address: SocketAddr, method: &str, path: &str, headers: &str, body: &str) -> (u16, String) {
+    let mut stream = std::net::TcpStream::connect(address).unwrap();
+    write!(
+        stream,
+        "{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{headers}Content-Length: {}\r\n\r\n{body}",
+        body.len()
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+    (status, body.to_string())
+}
+
+#[test]
+pub fn test_server() {
+    let server = Server::new()
+        .with_token(TEST_TOKEN)
+        .serve("127.0.0.1:0")
+        .unwrap();
+    let address = server.address();
+
+    let definition = r#"{ "jobs": [
+        { "name": "Hello", "type": "bash", "config": { "command": "sleep 0.2; echo -n Hello; echo working >&2" } },
+        { "name": "Wait", "type": "bash", "config": { "command": "sleep 0.3; echo -n '{INPUT}'" }, "depends_on": ["Hello"] },
+        { "name": "World", "type": "bash", "config": { "command": "echo -n '{INPUT} World'" }, "depends_on": ["Wait"] }
+    ] }"#;
+    let (status, body) = request(address, "POST", "/runs", definition);
+    assert_eq!(status, 201, "{body}");
+    let submitted: RunStatus = serde_json::from_str(&body).unwrap();
+    let run_id = submitted.run_id;
+
+    // The stream ends when the run finishes
+    let (status, events) = request(address, "GET", &format!("/runs/{run_id}/events"), "");
+    assert_eq!(status, 200);
+    assert!(events.contains("event: status\n"));
+    assert!(events.contains("event: log\n"));
+    assert!(events.contains("\"line\":\"working\""));
+    assert!(events.contains("\"state\":\"succeeded\""));
+    assert!(events.ends_with("0\r\n\r\n"));
+
+    let (status, body) = request(address, "GET", &format!("/runs/{run_id}"), "");
+    assert_eq!(status, 200);
+    let run: RunStatus = serde_json::from_str(&body).unwrap();
+    assert_eq!(run.state, JobState::Succeeded);
+    assert!(run.finished_at.is_some());
+    assert_eq!(run.jobs[2].message, "Hello World");
+
+    let (status, logs) = request(
+        address,
+        "GET",
+        &format!("/runs/{run_id}/jobs/Hello/logs"),
+        "",
+    );
+    assert_eq!((status, logs.as_str()), (200, "working\n"));
+    let (_, logs) = request(
+        address,
+        "GET",
+        &format!("/runs/{run_id}/jobs/Hello/logs?follow"),
+        "",
+    );
+    assert!(logs.contains("event: log\ndata: working\n\n"));
+
+    let (status, body) = request(address, "GET", "/runs", "");
+    assert_eq!(status, 200);
+    assert_eq!(
+        serde_json::from_str::<Vec<RunStatus>>(&body).unwrap(),
+        [run]
+    );
+
+    // Cancel a run while its second job is running
+    let (_, body) = request(address, "POST", "/runs", definition);
+    let run_id = serde_json::from_str::<RunStatus>(&body).unwrap().run_id;
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+    loop {
+        let (_, body) = request(address, "GET", &format!("/runs/{run_id}"), "");
+        let run: RunStatus = serde_json::from_str(&body).unwrap();
+        if run.jobs[1].state == JobState::Running {
+            break;
+        }
+        assert!(
+            std::time::Instant::now() < deadline,
+            "The second job never started"
+        );
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    let (status, _) = request(address, "POST", &format!("/runs/{run_id}/cancel"), "");
+    assert_eq!(status, 202);
+    request(address, "GET", &format!("/runs/{run_id}/events"), "");
+    let (_, body) = request(address, "GET", &format!("/runs/{run_id}"), "");
+    let run: RunStatus = serde_json::from_str(&body).unwrap();
+    assert_eq!(run.state, JobState::Cancelled);
+    assert_eq!(run.jobs[1].state, JobState::Failed);
+    assert_eq!(run.jobs[1].message, "The run was cancelled");
+    assert_eq!(run.jobs[2].state, JobState::Waiting);
+    let (status, _) = request(address, "POST", &format!("/runs/{run_id}/cancel"), "");
+    assert_eq!(status, 409);
+
+    let (status, body) = request(
+        address,
+        "POST",
+        "/runs",
+        "{ \"jobs\": [{ \"name\": \"A\", \"type\": \"nope\" }] }",
+    );
+    assert_eq!(status, 400);
+    assert!(body.contains("nope"));
+    let (status, _) = request(address, "GET", &format!("/runs/{}", Uuid::new_v4()), "");
+    assert_eq!(status, 404);
+    let (status, _) = request(address, "GET", "/runs/nope/events", "");
+    assert_eq!(status, 400);
+
+    // Runs can only be changed with the token and a JSON body
+    let json = "Content-Type: application/json\r\n";
+    let (status, _) = send(address, "POST", "/runs", json, definition);
+    assert_eq!(status, 401);
+    let wrong = format!("Authorization: Bearer nope\r\n{json}");
+    let (status, _) = send(address, "POST", "/runs", &wrong, definition);
+    assert_eq!(status, 401);
+    let form = format!("Authorization: Bearer {TEST_TOKEN}\r\nContent-Type: text/plain\r\n");
+    let (status, _) = send(
+        address,
+        "POST",
+        &format!("/runs/{run_id}/cancel"),
+        &form,
+        "",
+    );
+    assert_eq!(status, 415);
+    let large = " ".repeat(MAX_BODY_SIZE as usize + 1);
+    let (status, _) = request(address, "POST", "/runs", &large);
+    assert_eq!(status, 413);
+    assert_eq!(
+        serde_json::from_str::<Vec<RunStatus>>(&request(address, "GET", "/runs", "").1)
+            .unwrap()
+            .len(),
+        2
+    );
+    assert!(matches!(
+        Server::new().serve("127.0.0.1:0"),
+        Err(Error::MissingToken)
+    ));
+
+    // Only the newest finished runs are kept
+    let small = Server::new()
+        .with_token(TEST_TOKEN)
+        .with_kept_runs(1)
+        .serve("127.0.0.1:0")
+        .unwrap();
+    let noop = r#"{ "jobs": [{ "name": "Noop", "type": "noop" }] }"#;
+    let runs = (0..2)
+        .map(|_| {
+            let (_, body) = request(small.address(), "POST", "/runs", noop);
+            let run_id = serde_json::from_str::<RunStatus>(&body).unwrap().run_id;
+            request(
+                small.address(),
+                "GET",
+                &format!("/runs/{run_id}/events"),
+                "",
+            );
+            run_id
+        })
+        .collect::<Vec<_>>();
+    let (status, _) = request(small.address(), "GET", &format!("/runs/{}", runs[0]), "");
+    assert_eq!(status, 404);
+    let (status, _) = request(small.address(), "GET", &format!("/runs/{}", runs[1]), "");
+    assert_eq!(status, 200);
+    small.shutdown();
+
+    let (status, metrics) = request(address, "GET", "/metrics", "");
+    assert_eq!(status, 200);
+    assert!(metrics.contains("waterflow_jobs_succeeded_total{job_type=\"bash\"} 4"));
+
+    server.shutdown();
+}
+
+#[test]
+pub fn test_dashboard() {
+    let server = Server::new()
+        .with_token(TEST_TOKEN)
+        .serve("127.0.0.1:0")
+        .unwrap();
+    let address = server.address();
+
+    // Fetch waits until it's released, and Build fails the first time and succeeds when it's retried
+    let release = std::env::temp_dir().join(format!("waterflow-release-{}", Uuid::new_v4()));
+    let marker = std::env::temp_dir().join(format!("waterflow-retry-{}", Uuid::new_v4()));
+    let definition = serde_json::json!({ "jobs": [
+        { "name": "Fetch", "type": "bash", "config": {
+            "command": format!("while [ ! -e {} ]; do sleep 0.01; done; echo -n sources", release.display())
+        } },
+        { "name": "Build", "type": "bash", "config": {
+            "command": format!("test -f {0} || {{ touch {0}; exit 1; }}; echo -n '{{INPUT}} built'", marker.display())
+        }, "depends_on": ["Fetch"] },
+        { "name": "Lint", "type": "noop", "depends_on": ["Fetch"] },
+        { "name": "Test", "type": "bash", "config": { "command": "echo -n '{INPUT} tested'" }, "depends_on": ["Build"] }
+    ] });
+    let (_, body) = request(address, "POST", "/runs", &definition.to_string());
+    let run_id = serde_json::from_str::<RunStatus>(&body).unwrap().run_id;
+
+    let (status, body) = request(address, "GET", &format!("/runs/{run_id}/graph"), "");
+    assert_eq!(status, 200);
+    let graph: Vec<GraphNode> = serde_json::from_str(&body).unwrap();
+    let depths = graph
+        .iter()
+        .map(|node| (node.name.as_str(), node.depth))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        depths,
+        [("Fetch", 0), ("Build", 1), ("Lint", 1), ("Test", 2)]
+    );
+    assert_eq!(graph[3].dependencies, [graph[1].job_id]);
+    assert_eq!(graph[0].job_type, "bash");
+
+    // Jobs can't run again before the run finished
+    let (status, _) = request(
+        address,
+        "POST",
+        &format!("/runs/{run_id}/jobs/Fetch/rerun"),
+        "",
+    );
+    assert_eq!(status, 409);
+    std::fs::write(&release, "").unwrap();
+    request(address, "GET", &format!("/runs/{run_id}/events"), "");
+    let (_, body) = request(address, "GET", &format!("/runs/{run_id}"), "");
+    let run: RunStatus = serde_json::from_str(&body).unwrap();
+    assert_eq!(run.state, JobState::Failed);
+    assert_eq!(run.jobs[1].state, JobState::Failed);
+
+    let (status, _) = request(
+        address,
+        "POST",
+        &format!("/runs/{run_id}/jobs/Fetch/retry"),
+        "",
+    );
+    assert_eq!(status, 409);
+    let (status, _) = request(
+        address,
+        "POST",
+        &format!("/runs/{run_id}/jobs/Nope/retry"),
+        "",
+    );
+    assert_eq!(status, 404);
+    let (status, _) = request(
+        address,
+        "POST",
+        &format!("/runs/{run_id}/jobs/Build/retry"),
+        "",
+    );
+    assert_eq!(status, 202);
+    request(address, "GET", &format!("/runs/{run_id}/events"), "");
+    let (_, body) = request(address, "GET", &format!("/runs/{run_id}"), "");
+    let run: RunStatus = serde_json::from_str(&body).unwrap();
+    assert_eq!(run.state, JobState::Succeeded);
+    assert_eq!(run.jobs[0].attempts, 1);
+    assert_eq!(run.jobs[1].attempts, 2);
+    assert_eq!(run.jobs[3].message, "sources built tested");
+    std::fs::remove_file(&marker).unwrap();
+
+    let (status, _) = request(
+        address,
+        "POST",
+        &format!("/runs/{run_id}/jobs/Build/rerun"),
+        "",
+    );
+    assert_eq!(status, 202);
+    request(address, "GET", &format!("/runs/{run_id}/events"), "");
+    let (_, body) = request(address, "GET", &format!("/runs/{run_id}"), "");
+    let run: RunStatus = serde_json::from_str(&body).unwrap();
+    assert_eq!(run.state, JobState::Failed);
+    assert_eq!(run.jobs[3].state, JobState::Waiting);
+    std::fs::remove_file(&marker).unwrap();
+    std::fs::remove_file(&release).unwrap();
+
+    let (status, html) = request(address, "GET", "/", "");
+    assert_eq!(status, 200);
+    assert!(html.contains("/dashboard.js"));
+    let (status, _) = request(address, "GET", "/dashboard.js", "");
+    assert_eq!(status, 200);
+
+    server.shutdown();
+}
@@ -0,0 +1,437 @@
+//! Checkpoints of the jobs' states, so that a run can be resumed after the runner died.
+
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::job::{Job, JobStatus};
+use crate::Result;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Waiting,
+    Running,
+    Failed,
+    Succeeded,
+    /// Run that was stopped before all of its jobs ran
+    Cancelled,
+}
+
+impl JobState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobState::Waiting => "waiting",
+            JobState::Running => "running",
+            JobState::Failed => "failed",
+            JobState::Succeeded => "succeeded",
+            JobState::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl From<&JobStatus> for JobState {
+    fn from(value: &JobStatus) -> Self {
+        match value {
+            JobStatus::Waiting => JobState::Waiting,
+            JobStatus::InProgress { .. } => JobState::Running,
+            JobStatus::Failed { .. } => JobState::Failed,
+            JobStatus::Succeeded { .. } => JobState::Succeeded,
+        }
+    }
+}
+
+/// Snapshot of a job, as it's stored in a [StateStore]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobRecord {
+    pub job_id: Uuid,
+    pub name: String,
+    pub state: JobState,
+    /// Output of a succeeded job, or the error of a failed one
+    pub message: String,
+    pub input: Vec<String>,
+    pub started_at: Option<SystemTime>,
+    pub duration: Option<Duration>,
+    /// How many times the job was started during the run
+    #[serde(default)]
+    pub attempts: u32,
+    #[serde(default)]
+    pub logs: Vec<String>,
+}
+
+impl JobRecord {
+    pub fn from_job(job: &Job) -> Self {
+        let status = job.get_status();
+        let message = match &status {
+            JobStatus::Failed { msg, .. } | JobStatus::Succeeded { msg, .. } => msg.clone(),
+            _ => String::new(),
+        };
+
+        JobRecord {
+            job_id: job.get_id(),
+            name: job.name.clone(),
+            state: JobState::from(&status),
+            message,
+            input: job.input.clone(),
+            started_at: job.started_at.map(|started_at| {
+                SystemTime::now() - Instant::now().saturating_duration_since(started_at)
+            }),
+            duration: job.get_duration(),
+            attempts: job.attempts,
+            logs: job.logs.clone(),
+        }
+    }
+
+    /// Gives the job the stored status and output.
+    ///
+    /// Only finished jobs are restored, jobs that were running are put back in the waiting state.
+    pub(crate) fn restore(&self, job: &mut Job) {
+        let duration = self.duration.unwrap_or_default();
+        let status = match self.state {
+            JobState::Succeeded => JobStatus::Succeeded {
+                msg: self.message.clone(),
+                duration,
+            },
+            JobState::Failed => JobStatus::Failed {
+                msg: self.message.clone(),
+                duration,
+            },
+            JobState::Waiting | JobState::Running | JobState::Cancelled => JobStatus::Waiting,
+        };
+
+        job.attempts = self.attempts;
+        job.logs = self.logs.clone();
+        job.set_input(self.input.clone());
+        job.set_output(match status {
+            JobStatus::Waiting => "",
+            _ => &self.message,
+        });
+        job.set_status(&status);
+    }
+}
+
+/// One execution of a pipeline
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub run_id: Uuid,
+    pub started_at: SystemTime,
+    /// Unset while the run is going on, or when the runner died
+    pub finished_at: Option<SystemTime>,
+    /// Sha256 of the pipeline's definition, for pipelines that can be described by one
+    pub definition_hash: Option<String>,
+    /// Running, failed when any job failed or succeeded otherwise
+    pub state: JobState,
+}
+
+impl RunRecord {
+    pub fn duration(&self) -> Option<Duration> {
+        self.finished_at?.duration_since(self.started_at).ok()
+    }
+}
+
+/// Stores the state of every job of a run, whenever it changes
+pub trait StateStore: Debug + Send + Sync {
+    /// Saves the job's latest state, replacing the one that was stored before
+    fn save_job(&self, run_id: Uuid, job: &JobRecord) -> Result<()>;
+
+    /// Jobs of the run, in the order in which they were first saved. Unknown runs have no jobs.
+    fn load_jobs(&self, run_id: Uuid) -> Result<Vec<JobRecord>>;
+
+    /// Saves the run's latest state, replacing the one that was stored before
+    fn save_run(&self, run: &RunRecord) -> Result<()>;
+
+    fn load_run(&self, run_id: Uuid) -> Result<Option<RunRecord>>;
+
+    /// Every stored run, from the oldest to the newest
+    fn runs(&self) -> Result<Vec<RunRecord>>;
+}
+
+/// Stores every run as a JSON file in a directory
+#[derive(Debug)]
+pub struct JsonStateStore {
+    root: PathBuf,
+    /// Saving reads and rewrites the whole file, so only one job is saved at a time
+    lock: Mutex<()>,
+}
+
+impl JsonStateStore {
+    pub fn new(root: impl Into<PathBuf>) -> Result<Self> {
+        let root = root.into();
+        std::fs::create_dir_all(&root)?;
+
+        Ok(JsonStateStore {
+            root,
+            lock: Mutex::new(()),
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn run_path(&self, run_id: Uuid) -> PathBuf {
+        self.root.join(format!("{run_id}.json"))
+    }
+}
+
+impl JsonStateStore {
+    fn read(&self, path: &Path) -> Result<StoredRun> {
+        match std::fs::read(path) {
+            Ok(json) => Ok(serde_json::from_slice(&json)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(StoredRun::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Reads, changes and rewrites the run's file
+    fn update(&self, run_id: Uuid, change: impl FnOnce(&mut StoredRun)) -> Result<()> {
+        let _lock = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        let path = self.run_path(run_id);
+        let mut run = self.read(&path)?;
+        change(&mut run);
+
+        // Write the new state next to the old one first, so that a crash can't leave half a file
+        let temp = path.with_extension("json.tmp");
+        std::fs::write(&temp, serde_json::to_vec_pretty(&run)?)?;
+        std::fs::rename(temp, path)?;
+        Ok(())
+    }
+}
+
+/// Contents of a run's file in the [JsonStateStore]
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StoredRun {
+    run: Option<RunRecord>,
+    jobs: Vec<JobRecord>,
+}
+
+impl StateStore for JsonStateStore {
+    fn save_job(&self, run_id: Uuid, job: &JobRecord) -> Result<()> {
+        self.update(run_id, |run| {
+            match run.jobs.iter_mut().find(|j| j.job_id == job.job_id) {
+                Some(stored) => *stored = job.clone(),
+                None => run.jobs.push(job.clone()),
+            }
+        })
+    }
+
+    fn load_jobs(&self, run_id: Uuid) -> Result<Vec<JobRecord>> {
+        Ok(self.read(&self.run_path(run_id))?.jobs)
+    }
+
+    fn save_run(&self, run: &RunRecord) -> Result<()> {
+        self.update(run.run_id, |stored| stored.run = Some(run.clone()))
+    }
+
+    fn load_run(&self, run_id: Uuid) -> Result<Option<RunRecord>> {
+        Ok(self.read(&self.run_path(run_id))?.run)
+    }
+
+    fn runs(&self) -> Result<Vec<RunRecord>> {
+        let mut runs = vec![];
+        for entry in std::fs::read_dir(&self.root)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+            {
+                runs.extend(self.read(&path)?.run);
+            }
+        }
+
+        runs.sort_by_key(|run| run.started_at);
+        Ok(runs)
+    }
+}
+
+/// Stores the runs in an SQLite database
+#[cfg(feature = "sqlite")]
+#[derive(Debug)]
+pub struct SqliteStateStore {
+    connection: Mutex<rusqlite::Connection>,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteStateStore {
+    /// Opens the database, creating it if it doesn't exist yet
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_connection(rusqlite::Connection::open(path)?)
+    }
+
+    pub fn in_memory() -> Result<Self> {
+        Self::from_connection(rusqlite::Connection::open_in_memory()?)
+    }
+
+    fn from_connection(connection: rusqlite::Connection) -> Result<Self> {
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS jobs (
+                run_id TEXT NOT NULL,
+                job_id TEXT NOT NULL,
+                name TEXT NOT NULL,
+                state TEXT NOT NULL,
+                record TEXT NOT NULL,
+                PRIMARY KEY (run_id, job_id)
+            );
+            CREATE TABLE IF NOT EXISTS runs (
+                run_id TEXT PRIMARY KEY NOT NULL,
+                started_at INTEGER NOT NULL,
+                state TEXT NOT NULL,
+                record TEXT NOT NULL
+            );",
+        )?;
+
+        Ok(SqliteStateStore {
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl StateStore for SqliteStateStore {
+    fn save_job(&self, run_id: Uuid, job: &JobRecord) -> Result<()> {
+        let connection = self.connection.lock().unwrap_or_else(|e| e.into_inner());
+        // Updating the row in place keeps its rowid, which is what the jobs are ordered by
+        connection.execute(
+            "INSERT INTO jobs (run_id, job_id, name, state, record) VALUES (?1, ?2, ?3, ?4, ?5)
+            ON CONFLICT (run_id, job_id) DO UPDATE
+            SET name = excluded.name, state = excluded.state, record = excluded.record",
+            (
+                run_id.to_string(),
+                job.job_id.to_string(),
+                &job.name,
+                job.state.as_str(),
+                serde_json::to_string(job)?,
+            ),
+        )?;
+        Ok(())
+    }
+
+    fn load_jobs(&self, run_id: Uuid) -> Result<Vec<JobRecord>> {
+        self.query(
+            "SELECT record FROM jobs WHERE run_id = ?1 ORDER BY rowid",
+            [run_id.to_string()],
+        )
+    }
+
+    fn save_run(&self, run: &RunRecord) -> Result<()> {
+        let started_at = run
+            .started_at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as i64;
+
+        let connection = self.connection.lock().unwrap_or_else(|e| e.into_inner());
+        connection.execute(
+            "INSERT INTO runs (run_id, started_at, state, record) VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (run_id) DO UPDATE
+            SET started_at = excluded.started_at, state = excluded.state, record = excluded.record",
+            (
+                run.run_id.to_string(),
+                started_at,
+                run.state.as_str(),
+                serde_json::to_string(run)?,
+            ),
+        )?;
+        Ok(())
+    }
+
+    fn load_run(&self, run_id: Uuid) -> Result<Option<RunRecord>> {
+        let runs = self.query(
+            "SELECT record FROM runs WHERE run_id = ?1",
+            [run_id.to_string()],
+        )?;
+        Ok(runs.into_iter().next())
+    }
+
+    fn runs(&self) -> Result<Vec<RunRecord>> {
+        self.query("SELECT record FROM runs ORDER BY started_at", [])
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteStateStore {
+    /// Runs a query that selects the JSON records
+    fn query<T: serde::de::DeserializeOwned>(
+        &self,
+        sql: &str,
+        params: impl rusqlite::Params,
+    ) -> Result<Vec<T>> {
+        let connection = self.connection.lock().unwrap_or_else(|e| e.into_inner());
+        let mut statement = connection.prepare(sql)?;
+        let records = statement
+            .query_map(params, |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        records
+            .iter()
+            .map(|record| Ok(serde_json::from_str(record)?))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+fn test_state_store(store: &dyn StateStore) {
+    let run_id = Uuid::new_v4();
+    let mut job = Job::new("Build", crate::job_type::JobType::Noop);
+    let mut other = Job::new("Test", crate::job_type::JobType::Noop);
+    assert!(store.load_jobs(run_id).unwrap().is_empty());
+    assert_eq!(store.load_run(run_id).unwrap(), None);
+
+    store.save_job(run_id, &JobRecord::from_job(&job)).unwrap();
+    store
+        .save_job(run_id, &JobRecord::from_job(&other))
+        .unwrap();
+    smol::block_on(job.execute()).unwrap();
+    store.save_job(run_id, &JobRecord::from_job(&job)).unwrap();
+
+    let jobs = store.load_jobs(run_id).unwrap();
+    assert_eq!(jobs.len(), 2);
+    assert_eq!(jobs[0].state, JobState::Succeeded);
+    assert!(jobs[0].started_at.is_some());
+    assert_eq!(jobs[1].state, JobState::Waiting);
+
+    jobs[0].restore(&mut other);
+    assert_eq!(other.get_status(), job.get_status());
+    assert!(store.load_jobs(Uuid::new_v4()).unwrap().is_empty());
+
+    let started_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let mut run = RunRecord {
+        run_id,
+        started_at,
+        finished_at: None,
+        definition_hash: None,
+        state: JobState::Running,
+    };
+    let earlier = RunRecord {
+        run_id: Uuid::new_v4(),
+        started_at: started_at - Duration::from_secs(60),
+        ..run.clone()
+    };
+    store.save_run(&run).unwrap();
+    store.save_run(&earlier).unwrap();
+    run.finished_at = Some(started_at + Duration::from_secs(5));
+    run.state = JobState::Succeeded;
+    store.save_run(&run).unwrap();
+
+    assert_eq!(store.load_run(run_id).unwrap(), Some(run.clone()));
+    assert_eq!(store.runs().unwrap(), [earlier, run.clone()]);
+    assert_eq!(run.duration(), Some(Duration::from_secs(5)));
+}
+
+#[test]
+pub fn test_json_state_store() {
+    let root = std::env::temp_dir().join(format!("waterflow-state-{}", Uuid::new_v4()));
+    test_state_store(&JsonStateStore::new(&root).unwrap());
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+pub fn test_sqlite_state_store() {
+    test_state_store(&SqliteStateStore::in_memory().unwrap());
+}
@@ -0,0 +1,149 @@
+//! Helpers for testing plugins against the real WASM runtime.
+//!
+//! For quick tests that don't need to compile to wasm, see `waterflow_plugin_interface::testing`.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::error::Error;
+use crate::job::JobContext;
+use crate::module_source::ModuleSource;
+use crate::wasm::{run_wasm_code, run_wasm_values, Capabilities, Value};
+use crate::Result;
+
+/// Plugin that's run like a `JobType::Wasm` job would run it
+#[derive(Debug, Clone)]
+pub struct TestPlugin {
+    source: ModuleSource,
+    capabilities: Capabilities,
+    context: JobContext,
+}
+
+impl TestPlugin {
+    pub fn new(source: impl Into<ModuleSource>) -> Self {
+        TestPlugin {
+            source: source.into(),
+            capabilities: Capabilities::default(),
+            context: JobContext::default(),
+        }
+    }
+
+    /// Builds the plugin crate in `crate_dir` with `wasm-pack`, in release mode
+    pub fn build(crate_dir: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::new(build_plugin(crate_dir.as_ref())?))
+    }
+
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    pub fn with_env(mut self, key: &str, value: &str) -> Self {
+        self.context.env.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn with_secret(mut self, key: &str, value: &str) -> Self {
+        self.context.secrets.insert(key, value);
+        self
+    }
+
+    /// Calls the function with string inputs
+    pub fn run(&self, function_name: &str, inputs: &[&str]) -> Result<String> {
+        let context = JobContext {
+            input: inputs.iter().map(|input| input.to_string()).collect(),
+            ..self.context.clone()
+        };
+        run_wasm_code(function_name, &self.source, self.capabilities, &context)
+    }
+
+    /// Calls the function with typed inputs
+    pub fn run_values(&self, function_name: &str, inputs: Vec<Value>) -> Result<Value> {
+        run_wasm_values(
+            function_name,
+            &self.source,
+            self.capabilities,
+            &self.context,
+            inputs,
+        )
+    }
+}
+
+fn build_plugin(crate_dir: &Path) -> Result<PathBuf> {
+    let manifest_path = crate_dir.join("Cargo.toml").canonicalize()?;
+    let library_name = library_name(&manifest_path)?;
+    let out_dir = std::env::temp_dir()
+        .join("waterflow-plugins")
+        .join(&library_name);
+
+    let output = Command::new("wasm-pack")
+        .args(["build", "--release", "--target", "web", "--out-dir"])
+        .arg(&out_dir)
+        .arg(crate_dir)
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::PluginBuild {
+            message: String::from_utf8_lossy(&output.stderr).to_string(),
+        });
+    }
+
+    Ok(out_dir.join(format!("{library_name}_bg.wasm")))
+}
+
+/// Name of the `cdylib` target of the crate, as reported by `cargo metadata`
+fn library_name(manifest_path: &Path) -> Result<String> {
+    let output = Command::new(std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()))
+        .args(["metadata", "--no-deps", "--format-version", "1"])
+        .arg("--manifest-path")
+        .arg(manifest_path)
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::PluginBuild {
+            message: String::from_utf8_lossy(&output.stderr).to_string(),
+        });
+    }
+
+    let metadata: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    metadata["packages"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|package| package["manifest_path"].as_str() == manifest_path.to_str())
+        .flat_map(|package| package["targets"].as_array().into_iter().flatten())
+        .find(|target| {
+            target["kind"]
+                .as_array()
+                .is_some_and(|kinds| kinds.iter().any(|kind| kind == "cdylib"))
+        })
+        .and_then(|target| target["name"].as_str())
+        .map(|name| name.replace('-', "_"))
+        .ok_or_else(|| Error::PluginBuild {
+            message: format!("{} doesn't have a cdylib target", manifest_path.display()),
+        })
+}
+
+#[test]
+pub fn test_library_name() {
+    let manifest_path = Path::new("tests/wasm_example/Cargo.toml")
+        .canonicalize()
+        .unwrap();
+    assert_eq!(library_name(&manifest_path).unwrap(), "wasm_example");
+}
+
+#[test]
+#[ignore = "This needs wasm-pack and the wasm32-unknown-unknown target installed"]
+pub fn test_build_and_run_plugin() {
+    let plugin = TestPlugin::build("tests/wasm_example")
+        .expect("Failed to build the plugin")
+        .with_env("GREETING", "Hi");
+
+    assert_eq!(
+        plugin.run("reverse_join", &["Hello", "World!"]).unwrap(),
+        "World!, Hello"
+    );
+    assert_eq!(plugin.run("greet", &["Ann"]).unwrap(), "Hi, Ann!");
+    assert!(matches!(
+        plugin.run("checked_join", &[]),
+        Err(Error::WasmPlugin { .. })
+    ));
+}
@@ -1,59 +1,412 @@
 use crate::error::Error;
+use crate::job::{JobContext, JobLogs, Secrets};
+use crate::module_source::ModuleSource;
+use crate::pipeline::CancelHandle;
 use crate::Result;
 use bypar::ToBytes as _;
 use bypar::{
     prelude::{IntoSizedString, IntoSizedVec},
     FromBytes as _,
 };
+use std::collections::BTreeMap;
 use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, trace, warn, Level};
+use uuid::Uuid;
 use wasmtime::*;
-use waterflow_plugin_interface::Communication;
+use waterflow_plugin_interface::value::WireValue;
+pub use waterflow_plugin_interface::value::{FromValue, IntoValue, Value};
+use waterflow_plugin_interface::{Communication, PluginError};
 
 static ENGINE: OnceLock<Engine> = OnceLock::new();
 
+/// How often running plugins check whether their run was cancelled
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+pub(crate) fn engine() -> &'static Engine {
+    let mut created = false;
+    let engine = ENGINE.get_or_init(|| {
+        created = true;
+        let mut config = Config::new();
+        config.epoch_interruption(true);
+        Engine::new(&config).expect("Failed to create the WASM engine")
+    });
+    if created {
+        tick_epochs(engine);
+    }
+    engine
+}
+
+/// Advances the engine's epoch for as long as the process runs
+pub(crate) fn tick_epochs(engine: &'static Engine) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(EPOCH_TICK);
+        engine.increment_epoch();
+    });
+}
+
+/// Traps the plugin on the next epoch tick after the job's run was cancelled
+pub(crate) fn interrupt_on_cancel(store: &mut Store<HostState>) {
+    let cancel = store.data().cancel.clone();
+    store.set_epoch_deadline(1);
+    store.epoch_deadline_callback(move |_| {
+        if cancel.is_cancelled() {
+            Err(wasmtime::Error::msg("The run was cancelled"))
+        } else {
+            Ok(UpdateDeadline::Continue(1))
+        }
+    });
+}
+
+/// Reports a trap as the cancellation that caused it
+pub(crate) fn trapped(e: wasmtime::Error, context: &JobContext) -> Error {
+    if context.cancel.is_cancelled() {
+        Error::Cancelled
+    } else {
+        e.into()
+    }
+}
+
+/// Byte layouts of the plugin protocol, that the host knows how to talk
+pub const SUPPORTED_ABI_VERSIONS: [u32; 2] = [1, 2];
+
+/// Name of the import module, under which the host functions are exposed to plugins
+const HOST_MODULE: &str = "waterflow";
+
+/// Host functions that have to be explicitly allowed for a plugin
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Capabilities {
+    /// Allows the plugin to make HTTP GET requests through `http_fetch`
+    pub http: bool,
+}
+
+/// State that the host functions can access while the plugin is running
+pub(crate) struct HostState {
+    pub(crate) job_id: Uuid,
+    pub(crate) env: BTreeMap<String, String>,
+    pub(crate) secrets: Secrets,
+    pub(crate) capabilities: Capabilities,
+    pub(crate) logs: JobLogs,
+    pub(crate) cancel: CancelHandle,
+}
+
+impl HostState {
+    pub(crate) fn new(context: &JobContext, capabilities: Capabilities) -> Self {
+        HostState {
+            job_id: context.job_id,
+            env: context.env.clone(),
+            secrets: context.secrets.clone(),
+            capabilities,
+            logs: context.logs.clone(),
+            cancel: context.cancel.clone(),
+        }
+    }
+}
+
 pub(crate) fn run_wasm_code(
     function_name: &str,
-    file_name: &str,
-    inputs: &[String],
+    source: &ModuleSource,
+    capabilities: Capabilities,
+    context: &JobContext,
 ) -> Result<String> {
-    let input = get_input_bytes(inputs);
-    let engine = ENGINE.get_or_init(Engine::default);
-    let module = Module::from_file(engine, file_name).expect("Failed to load WASM module");
-    let mut store = Store::new(engine, ());
+    let input = get_input_bytes(&context.input);
+    let output = call_plugin(function_name, source, capabilities, context, &input)?;
+
+    Ok(output.to_string())
+}
+
+/// Calls a plugin function with typed values instead of the job's input strings
+pub fn run_wasm_values(
+    function_name: &str,
+    source: &ModuleSource,
+    capabilities: Capabilities,
+    context: &JobContext,
+    inputs: Vec<Value>,
+) -> Result<Value> {
+    let input = Communication::Values(
+        inputs
+            .into_iter()
+            .map(WireValue::from)
+            .collect::<Vec<_>>()
+            .into_sized(),
+    )
+    .to_vec();
+
+    call_plugin(function_name, source, capabilities, context, &input)
+}
+
+fn call_plugin(
+    function_name: &str,
+    source: &ModuleSource,
+    capabilities: Capabilities,
+    context: &JobContext,
+    input: &[u8],
+) -> Result<Value> {
+    let engine = engine();
+    let started_at = Instant::now();
+    let module = Module::new(engine, &source.load()?)?;
 
     // Instantiate the WASM module
-    let instance = Instance::new(&mut store, &module, &[]).expect("Failed to instantiate WASM");
+    let (mut store, instance) =
+        instantiate(engine, &module, HostState::new(context, capabilities))?;
+    if let Some(metrics) = &context.metrics {
+        metrics.wasm_instantiated("wasm", started_at.elapsed());
+    }
+
+    let abi_version = abi_version(&mut store, &instance)?;
+    trace!("Plugin {source} uses ABI version {abi_version}");
 
-    // Get the `reverse_bytes` function
-    let reverse_bytes = instance
+    let function = instance
         .get_typed_func::<(i32, i32), i32>(&mut store, function_name)
-        .expect("Failed to get function");
+        .map_err(|_| Error::WasmFunctionMissing {
+            function_name: function_name.to_string(),
+            module: source.to_string(),
+        })?;
 
-    // Allocate memory for the input data
     let memory = instance
         .get_memory(&mut store, "memory")
-        .expect("Failed to find memory");
+        .ok_or_else(|| wasmtime::Error::msg("Plugin doesn't export its memory"))?;
+
+    // Allocate memory for the input data
+    let input_ptr = match abi_version {
+        1 => 0,
+        _ => instance
+            .get_typed_func::<i32, i32>(&mut store, "waterflow_alloc")?
+            .call(&mut store, input.len() as i32)?,
+    };
 
     // Copy input data to WASM memory
-    memory.data_mut(&mut store)[..input.len()].copy_from_slice(&input);
+    memory.write(&mut store, input_ptr as u32 as usize, input)?;
+
+    // Call the WASM function, a trap fails the job
+    let output_ptr = function
+        .call(&mut store, (input_ptr, input.len() as i32))
+        .map_err(|e| trapped(e, context))? as u32 as usize;
+
+    // Retrieve the output data from WASM memory, without trusting the pointer or the length
+    let out_of_bounds = |len| Error::WasmOutputOutOfBounds {
+        ptr: output_ptr,
+        len,
+    };
+    let output = match abi_version {
+        // The parser only reads as much as it needs
+        1 => memory
+            .data(&store)
+            .get(output_ptr..)
+            .ok_or_else(|| out_of_bounds(0))?
+            .to_vec(),
+        _ => {
+            let mut output_len = [0; 4];
+            memory
+                .read(&store, output_ptr, &mut output_len)
+                .map_err(|_| out_of_bounds(output_len.len()))?;
+
+            let start = output_ptr + output_len.len();
+            let len = u32::from_le_bytes(output_len) as usize;
+            memory
+                .data(&store)
+                .get(start..start.saturating_add(len))
+                .ok_or_else(|| out_of_bounds(len))?
+                .to_vec()
+        }
+    };
+
+    match Communication::from_bytes(&output)? {
+        Communication::Output(output) => Ok(Value::String(output.into())),
+        Communication::Value(output) => Ok(output.into()),
+        Communication::Error(PluginError { message, code }) => Err(Error::WasmPlugin {
+            message: message.into(),
+            code,
+        }),
+        Communication::Inputs(_) | Communication::Values(_) => Err(Error::WasmWrongTypeReturned),
+    }
+}
+
+/// Asks the plugin which byte layout it uses, plugins from before the negotiation are version `1`
+fn abi_version(store: &mut Store<HostState>, instance: &Instance) -> Result<u32> {
+    let version = match instance.get_typed_func::<(), i32>(&mut *store, "waterflow_abi_version") {
+        Ok(func) => func.call(&mut *store, ())? as u32,
+        Err(_) => 1,
+    };
+
+    if SUPPORTED_ABI_VERSIONS.contains(&version) {
+        Ok(version)
+    } else {
+        Err(Error::WasmAbiUnsupported { version })
+    }
+}
+
+/// Forwards a plugin's message to tracing and to the job's logs
+pub(crate) fn log_plugin_message(state: &HostState, level: Level, message: &str) {
+    let job_id = state.job_id;
+    match level {
+        Level::ERROR => error!(%job_id, "{message}"),
+        Level::WARN => warn!(%job_id, "{message}"),
+        Level::INFO => info!(%job_id, "{message}"),
+        Level::DEBUG => debug!(%job_id, "{message}"),
+        _ => trace!(%job_id, "{message}"),
+    }
+    state
+        .logs
+        .push(format!("[{}] {message}", level.as_str().to_lowercase()));
+}
+
+fn instantiate(
+    engine: &Engine,
+    module: &Module,
+    state: HostState,
+) -> Result<(Store<HostState>, Instance)> {
+    let mut linker = Linker::new(engine);
+    add_host_functions(&mut linker)?;
+
+    let mut store = Store::new(engine, state);
+    interrupt_on_cancel(&mut store);
+    let instance = linker.instantiate(&mut store, module)?;
+
+    Ok((store, instance))
+}
 
-    // Call the WASM function
-    let output_ptr = reverse_bytes
-        .call(&mut store, (0, input.len() as i32))
-        .expect("Failed to call function");
+/// Registers the functions that plugins can import from the `waterflow` module.
+///
+/// Strings are passed in as a `(ptr, len)` pair into the plugin's memory.
+/// Strings that are returned to the plugin are written into a buffer allocated through the plugin's
+/// `waterflow_alloc` export and returned as the unsigned `(ptr << 32) | len`, or `-1` when there's
+/// no value.
+fn add_host_functions(linker: &mut Linker<HostState>) -> Result<()> {
+    linker.func_wrap(
+        HOST_MODULE,
+        "log",
+        |mut caller: Caller<'_, HostState>, level: i32, ptr: i32, len: i32| {
+            let message = read_guest_string(&mut caller, ptr, len)?;
+            let level = match level {
+                0 => Level::ERROR,
+                1 => Level::WARN,
+                2 => Level::INFO,
+                3 => Level::DEBUG,
+                _ => Level::TRACE,
+            };
+            log_plugin_message(caller.data(), level, &message);
+            Ok(())
+        },
+    )?;
 
-    // Retrieve the output data from WASM memory
-    let output_len = input.len(); // Output length is the same as input length
-    let output =
-        memory.data(&store)[output_ptr as usize..(output_ptr as usize + output_len)].to_vec();
+    linker.func_wrap(
+        HOST_MODULE,
+        "get_env",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| {
+            let key = read_guest_string(&mut caller, ptr, len)?;
+            let value = caller.data().env.get(&key).cloned();
+            write_guest_string(&mut caller, value.as_deref())
+        },
+    )?;
 
-    let output = Communication::from_bytes(&output)?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "get_secret",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| {
+            let key = read_guest_string(&mut caller, ptr, len)?;
+            let value = caller.data().secrets.get(&key).map(str::to_string);
+            write_guest_string(&mut caller, value.as_deref())
+        },
+    )?;
 
-    let Communication::Output(output) = output else {
-        return Err(Error::WasmWrongTypeReturned);
+    linker.func_wrap(
+        HOST_MODULE,
+        "emit_progress",
+        |caller: Caller<'_, HostState>, progress: f32| {
+            let job_id = caller.data().job_id;
+            info!(%job_id, progress, "Plugin reported progress");
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "http_fetch",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| {
+            let url = read_guest_string(&mut caller, ptr, len)?;
+            let job_id = caller.data().job_id;
+            if !caller.data().capabilities.http {
+                warn!(%job_id, "Plugin tried to fetch {url} without the http capability");
+                return write_guest_string(&mut caller, None);
+            }
+
+            let body = http_fetch(&url)
+                .inspect_err(|e| warn!(%job_id, "Plugin failed to fetch {url}: {e}"))
+                .ok();
+            write_guest_string(&mut caller, body.as_deref())
+        },
+    )?;
+
+    Ok(())
+}
+
+#[cfg(feature = "web")]
+pub(crate) fn http_fetch(url: &str) -> std::result::Result<String, String> {
+    ureq::get(url)
+        .call()
+        .map_err(|e| e.to_string())?
+        .into_string()
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "web"))]
+pub(crate) fn http_fetch(_url: &str) -> std::result::Result<String, String> {
+    Err("waterflow was built without the `web` feature".to_string())
+}
+
+fn guest_memory(caller: &mut Caller<'_, HostState>) -> wasmtime::Result<Memory> {
+    caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| wasmtime::Error::msg("Plugin doesn't export its memory"))
+}
+
+fn read_guest_string(
+    caller: &mut Caller<'_, HostState>,
+    ptr: i32,
+    len: i32,
+) -> wasmtime::Result<String> {
+    let memory = guest_memory(caller)?;
+    // Checked before allocating, so that a bogus length can't exhaust the host's memory
+    let in_bounds = usize::try_from(ptr)
+        .ok()
+        .zip(usize::try_from(len).ok())
+        .is_some_and(|(ptr, len)| ptr.saturating_add(len) <= memory.data_size(&*caller));
+    if !in_bounds {
+        return Err(wasmtime::Error::msg(format!(
+            "Plugin passed a string of length {len} at {ptr}, which is outside of its memory"
+        )));
+    }
+
+    let mut buffer = vec![0; len as usize];
+    memory.read(&*caller, ptr as usize, &mut buffer)?;
+
+    Ok(String::from_utf8_lossy(&buffer).to_string())
+}
+
+fn write_guest_string(
+    caller: &mut Caller<'_, HostState>,
+    value: Option<&str>,
+) -> wasmtime::Result<i64> {
+    let Some(value) = value else {
+        return Ok(-1);
     };
 
-    Ok(output.into())
+    let len = u32::try_from(value.len())
+        .map_err(|_| wasmtime::Error::msg("String is too long to hand to the plugin"))?;
+    let alloc = caller
+        .get_export("waterflow_alloc")
+        .and_then(Extern::into_func)
+        .ok_or_else(|| wasmtime::Error::msg("Plugin doesn't export `waterflow_alloc`"))?
+        .typed::<i32, i32>(&*caller)?;
+    // The guest's pointers and lengths are unsigned, even though they're passed as an i32
+    let ptr = alloc.call(&mut *caller, len as i32)? as u32;
+
+    let memory = guest_memory(caller)?;
+    memory.write(&mut *caller, ptr as usize, value.as_bytes())?;
+
+    // -1 can't be confused with a string, as it would end past the 4 GiB of a 32-bit memory
+    Ok(((u64::from(ptr) << 32) | u64::from(len)) as i64)
 }
 
 fn get_input_bytes(inputs: &[String]) -> Vec<u8> {
@@ -67,3 +420,298 @@ fn get_input_bytes(inputs: &[String]) -> Vec<u8> {
 
     inputs.to_vec()
 }
+
+#[test]
+pub fn test_host_get_env_and_secret() {
+    let engine = engine();
+    let module = Module::new(
+        engine,
+        r#"
+        (module
+            (import "waterflow" "get_env" (func $get_env (param i32 i32) (result i64)))
+            (import "waterflow" "get_secret" (func $get_secret (param i32 i32) (result i64)))
+            (memory (export "memory") 32769)
+            (global $next (export "next") (mut i32) (i32.const 1024))
+            (data (i32.const 0) "NAMETOKEN")
+            (func (export "waterflow_alloc") (param $len i32) (result i32)
+                (local $ptr i32)
+                (local.set $ptr (global.get $next))
+                (global.set $next (i32.add (global.get $next) (local.get $len)))
+                (local.get $ptr))
+            (func (export "env") (result i64)
+                (call $get_env (i32.const 0) (i32.const 4)))
+            (func (export "secret") (result i64)
+                (call $get_secret (i32.const 4) (i32.const 5)))
+            (func (export "missing") (result i64)
+                (call $get_env (i32.const 4) (i32.const 5))))
+        "#,
+    )
+    .unwrap();
+
+    let mut context = JobContext::default();
+    context
+        .env
+        .insert("NAME".to_string(), "waterflow".to_string());
+    context.secrets.insert("TOKEN", "hunter2");
+
+    let (mut store, instance) = instantiate(
+        engine,
+        &module,
+        HostState::new(&context, Capabilities::default()),
+    )
+    .unwrap();
+    let memory = instance.get_memory(&mut store, "memory").unwrap();
+
+    let call = |store: &mut Store<HostState>, name: &str| {
+        let packed = instance
+            .get_typed_func::<(), i64>(&mut *store, name)
+            .unwrap()
+            .call(&mut *store, ())
+            .unwrap();
+        if packed == -1 {
+            return None;
+        }
+        let packed = packed as u64;
+        let (ptr, len) = ((packed >> 32) as usize, (packed & 0xffff_ffff) as usize);
+        Some(String::from_utf8(memory.data(&*store)[ptr..ptr + len].to_vec()).unwrap())
+    };
+
+    assert_eq!(call(&mut store, "env").as_deref(), Some("waterflow"));
+    assert_eq!(call(&mut store, "secret").as_deref(), Some("hunter2"));
+    assert_eq!(call(&mut store, "missing"), None);
+
+    // Buffers past 2 GiB would look negative as an i32
+    let next = instance.get_global(&mut store, "next").unwrap();
+    next.set(&mut store, Val::I32(0x8000_0000_u32 as i32))
+        .unwrap();
+    assert_eq!(call(&mut store, "env").as_deref(), Some("waterflow"));
+}
+
+#[test]
+pub fn test_plugin_failures() {
+    // The error is much longer than the plugin's input
+    let message = "The plugin couldn't do its job because of a very long list of reasons";
+    let error = Communication::Error(PluginError {
+        message: message.to_string().into_sized(),
+        code: 7,
+    })
+    .to_vec();
+    let data = error
+        .iter()
+        .map(|b| format!("\\{b:02x}"))
+        .collect::<String>();
+    let module = wat::parse_str(format!(
+        r#"
+        (module
+            (memory (export "memory") 1)
+            (data (i32.const 1024) "{data}")
+            (func (export "fail") (param i32 i32) (result i32) (i32.const 1024))
+            (func (export "trap") (param i32 i32) (result i32) unreachable)
+            (func (export "past_memory") (param i32 i32) (result i32) (i32.const 70000)))
+        "#
+    ))
+    .unwrap();
+    let run = |function_name| {
+        run_wasm_code(
+            function_name,
+            &module.clone().into(),
+            Capabilities::default(),
+            &JobContext::default(),
+        )
+    };
+
+    assert!(matches!(
+        run("fail"),
+        Err(Error::WasmPlugin { message: m, code: 7 }) if m == message
+    ));
+    assert!(matches!(run("trap"), Err(Error::Wasm { .. })));
+    assert!(matches!(
+        run("past_memory"),
+        Err(Error::WasmOutputOutOfBounds { ptr: 70000, .. })
+    ));
+    assert!(matches!(
+        run("missing"),
+        Err(Error::WasmFunctionMissing { function_name, .. }) if function_name == "missing"
+    ));
+
+    // A length prefix of almost 4 GiB isn't allocated
+    let module = wat::parse_str(
+        r#"
+        (module
+            (memory (export "memory") 1)
+            (data (i32.const 1024) "\f0\ff\ff\ff")
+            (func (export "waterflow_abi_version") (result i32) (i32.const 2))
+            (func (export "waterflow_alloc") (param i32) (result i32) (i32.const 0))
+            (func (export "huge") (param i32 i32) (result i32) (i32.const 1024)))
+        "#,
+    )
+    .unwrap();
+    assert!(matches!(
+        run_wasm_code(
+            "huge",
+            &module.into(),
+            Capabilities::default(),
+            &JobContext::default()
+        ),
+        Err(Error::WasmOutputOutOfBounds {
+            ptr: 1024,
+            len: 0xffff_fff0
+        })
+    ));
+
+    // A plugin that never returns is interrupted once its run is cancelled
+    let module = wat::parse_str(
+        r#"(module (memory (export "memory") 1) (func (export "spin") (param i32 i32) (result i32) (loop $spin (br $spin)) (i32.const 0)))"#,
+    )
+    .unwrap();
+    let context = JobContext::default();
+    let cancel = context.cancel.clone();
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(100));
+        cancel.cancel();
+    });
+    assert!(matches!(
+        run_wasm_code("spin", &module.into(), Capabilities::default(), &context),
+        Err(Error::Cancelled)
+    ));
+}
+
+#[test]
+pub fn test_host_capabilities() {
+    use std::io::{BufRead, BufReader, Write};
+
+    // Answers a single request
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 2 {
+            line.clear();
+        }
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello"
+        )
+        .unwrap();
+    });
+
+    let engine = engine();
+    let module = Module::new(
+        engine,
+        r#"
+        (module
+            (import "waterflow" "http_fetch" (func $http_fetch (param i32 i32) (result i64)))
+            (import "waterflow" "log" (func $log (param i32 i32 i32)))
+            (memory (export "memory") 1)
+            (global $next (mut i32) (i32.const 1024))
+            (func (export "waterflow_alloc") (param $len i32) (result i32)
+                (local $ptr i32)
+                (local.set $ptr (global.get $next))
+                (global.set $next (i32.add (global.get $next) (local.get $len)))
+                (local.get $ptr))
+            (func (export "fetch") (param $len i32) (result i64)
+                (call $http_fetch (i32.const 0) (local.get $len)))
+            (func (export "log_negative")
+                (call $log (i32.const 2) (i32.const 0) (i32.const -1)))
+            (func (export "log_past_memory")
+                (call $log (i32.const 2) (i32.const 65000) (i32.const 1000))))
+        "#,
+    )
+    .unwrap();
+
+    let fetch = |capabilities: Capabilities| {
+        let (mut store, instance) = instantiate(
+            engine,
+            &module,
+            HostState::new(&JobContext::default(), capabilities),
+        )
+        .unwrap();
+        let memory = instance.get_memory(&mut store, "memory").unwrap();
+        memory.write(&mut store, 0, url.as_bytes()).unwrap();
+
+        let packed = instance
+            .get_typed_func::<i32, i64>(&mut store, "fetch")
+            .unwrap()
+            .call(&mut store, url.len() as i32)
+            .unwrap();
+        if packed < 0 {
+            return None;
+        }
+        let (ptr, len) = ((packed >> 32) as usize, (packed & 0xffff_ffff) as usize);
+        Some(String::from_utf8(memory.data(&store)[ptr..ptr + len].to_vec()).unwrap())
+    };
+
+    assert_eq!(fetch(Capabilities::default()), None);
+    let expected = cfg!(feature = "web").then(|| "hello".to_string());
+    assert_eq!(fetch(Capabilities { http: true }), expected);
+
+    // Strings outside of the plugin's memory trap instead of being allocated
+    let (mut store, instance) = instantiate(
+        engine,
+        &module,
+        HostState::new(&JobContext::default(), Capabilities::default()),
+    )
+    .unwrap();
+    for name in ["log_negative", "log_past_memory"] {
+        let error = instance
+            .get_typed_func::<(), ()>(&mut store, name)
+            .unwrap()
+            .call(&mut store, ())
+            .unwrap_err();
+        assert!(
+            format!("{error:?}").contains("outside of its memory"),
+            "{error:?}"
+        );
+    }
+}
+
+#[test]
+#[ignore = "This needs to have the wasm_example built"]
+pub fn test_run_wasm_values() {
+    let output = run_wasm_values(
+        "summarize",
+        &"tests/wasm_example/pkg/wasm_example_bg.wasm".into(),
+        Capabilities::default(),
+        &JobContext::default(),
+        vec![Value::Int(2), Value::Bytes(vec![1, 2, 3])],
+    )
+    .expect("Failed to run the plugin");
+
+    let Value::Map(summary) = output else {
+        panic!("Expected the plugin to return a map, got {output:?}");
+    };
+
+    assert_eq!(summary["count"], Value::Int(3));
+    assert_eq!(summary["total"], Value::Int(12));
+}
+
+#[test]
+pub fn test_abi_version_negotiation() {
+    let version_of = |module: &str| {
+        let module = Module::new(engine(), module).unwrap();
+        let (mut store, instance) = instantiate(
+            engine(),
+            &module,
+            HostState::new(&JobContext::default(), Capabilities::default()),
+        )
+        .unwrap();
+        abi_version(&mut store, &instance)
+    };
+
+    assert_eq!(version_of("(module)").unwrap(), 1);
+    assert_eq!(
+        version_of(
+            r#"(module (func (export "waterflow_abi_version") (result i32) (i32.const 2)))"#
+        )
+        .unwrap(),
+        2
+    );
+    assert!(matches!(
+        version_of(
+            r#"(module (func (export "waterflow_abi_version") (result i32) (i32.const 7)))"#
+        ),
+        Err(Error::WasmAbiUnsupported { version: 7 })
+    ));
+}
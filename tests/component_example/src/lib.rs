@@ -0,0 +1,32 @@
+// Build with:
+// cargo build --release -p component_example --target wasm32-unknown-unknown
+// wasm-tools component new target/wasm32-unknown-unknown/release/component_example.wasm -o tests/component_example/pkg/component_example.wasm
+wit_bindgen::generate!({
+    path: "../../wit",
+    world: "plugin",
+});
+
+use exports::waterflow::plugin::run::{Guest, PluginError};
+use waterflow::plugin::host::{log, LogLevel};
+
+struct Component;
+
+impl Guest for Component {
+    fn run(function_name: String, inputs: Vec<String>) -> Result<String, PluginError> {
+        log(
+            LogLevel::Info,
+            &format!("Running {function_name} with {} inputs", inputs.len()),
+        );
+
+        match function_name.as_str() {
+            "reverse_join" => Ok(inputs.into_iter().rev().collect::<Vec<_>>().join(", ")),
+            "normal_join" => Ok(inputs.join(", ")),
+            _ => Err(PluginError {
+                message: format!("Unknown function {function_name}"),
+                code: 2,
+            }),
+        }
+    }
+}
+
+export!(Component);
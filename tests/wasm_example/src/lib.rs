@@ -1,15 +1,89 @@
 use wasm_bindgen::prelude::*;
-use waterflow_bindings::waterflow_binding;
+use waterflow_bindings::{waterflow_binding, FromValue, IntoValue};
 use waterflow_plugin_interface::prelude::*;
 
 #[waterflow_binding]
 pub fn reverse_join(input: Vec<String>) -> String {
-    let return_value = input.into_iter().rev().collect::<Vec<String>>().join(", ");
-    return_value
+    input.into_iter().rev().collect::<Vec<String>>().join(", ")
 }
 
 #[waterflow_binding]
 pub fn normal_join(input: Vec<String>) -> String {
-    let return_value = input.into_iter().collect::<Vec<String>>().join(", ");
-    return_value
+    input.into_iter().collect::<Vec<String>>().join(", ")
+}
+
+#[waterflow_binding]
+pub fn greet(input: Vec<String>) -> String {
+    let greeting = host::get_env("GREETING").unwrap_or_else(|| "Hello".to_string());
+    host::log(LogLevel::Info, &format!("Greeting {} people", input.len()));
+    format!("{greeting}, {}!", input.join(" and "))
+}
+
+#[waterflow_binding]
+pub fn checked_join(input: Vec<String>) -> Result<String, String> {
+    if input.is_empty() {
+        return Err("There is nothing to join".to_string());
+    }
+    Ok(input.join(", "))
+}
+
+#[derive(IntoValue, FromValue)]
+pub struct Summary {
+    pub count: u32,
+    pub total: u64,
+}
+
+#[waterflow_binding]
+pub fn summarize(scale: u32, data: Vec<u8>) -> Summary {
+    Summary {
+        count: data.len() as u32,
+        total: data.iter().map(|b| *b as u64 * scale as u64).sum(),
+    }
+}
+
+#[waterflow_binding(name = "repeat", description = "Repeats a text a number of times")]
+pub fn repeat_text(text: &str, times: u32, separator: &str) -> String {
+    vec![text; times as usize].join(separator)
+}
+
+#[waterflow_binding]
+pub fn count_inputs(input: &[String]) -> u32 {
+    input.len() as u32
+}
+
+#[test]
+fn test_native_round_trip() {
+    use waterflow_plugin_interface::testing::{self, MockHost};
+
+    assert_eq!(
+        testing::call(reverse_join, &["Hello", "World!"]),
+        Ok(Value::String("World!, Hello".to_string()))
+    );
+    assert_eq!(
+        testing::call(checked_join, &[]).map_err(|e| e.message),
+        Err("There is nothing to join".to_string())
+    );
+    assert_eq!(
+        testing::call_values(
+            repeat_text,
+            vec![
+                Value::String("ab".to_string()),
+                Value::Int(3),
+                Value::String("-".to_string())
+            ]
+        ),
+        Ok(Value::String("ab-ab-ab".to_string()))
+    );
+    assert_eq!(testing::call(count_inputs, &["a", "b"]), Ok(Value::Int(2)));
+
+    let summary = testing::call_values(summarize, vec![Value::Int(2), Value::Bytes(vec![1, 2, 3])])
+        .map(Summary::from_value)
+        .unwrap()
+        .unwrap();
+    assert_eq!((summary.count, summary.total), (3, 12));
+
+    let host = MockHost::default().with_env("GREETING", "Hi");
+    let (output, host) = testing::with_host(host, || testing::call(greet, &["Ann", "Bob"]));
+    assert_eq!(output, Ok(Value::String("Hi, Ann and Bob!".to_string())));
+    assert_eq!(host.logs.len(), 1);
 }
@@ -1,36 +1,512 @@
 use proc_macro::TokenStream;
 use quote::{format_ident, quote};
-use syn::{self, parse_macro_input, ItemFn};
+use syn::{
+    self, parse_macro_input, parse_quote, AttributeArgs, Data, DeriveInput, Fields, FnArg,
+    GenericArgument, ItemFn, Lit, LitStr, Meta, NestedMeta, Pat, PathArguments, ReturnType, Type,
+};
 
+/// Exports a function to the host.
+///
+/// The function can take any number of positional parameters that implement `FromValue`,
+/// or borrows of them like `&str` and `&[String]`. A single `Vec<String>` or `&[String]`
+/// parameter receives all of the inputs.
+///
+/// Options: `#[waterflow_binding(name = "...", description = "...")]`
 #[proc_macro_attribute]
-pub fn waterflow_binding(_attr: TokenStream, item: TokenStream) -> TokenStream {
+pub fn waterflow_binding(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr as AttributeArgs);
     let input = parse_macro_input!(item as ItemFn);
 
+    match binding(args, &input) {
+        Ok(gen) => gen.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// Options given to `#[waterflow_binding(...)]`
+#[derive(Default)]
+struct BindingOptions {
+    /// Name that the function is exported with, instead of its own name
+    name: Option<LitStr>,
+    /// Description for the manifest, instead of the doc comments
+    description: Option<String>,
+}
+
+impl BindingOptions {
+    fn parse(args: AttributeArgs) -> syn::Result<Self> {
+        let mut options = BindingOptions::default();
+
+        for arg in args {
+            let (key, value) = match &arg {
+                NestedMeta::Meta(Meta::NameValue(meta)) => match (meta.path.get_ident(), &meta.lit)
+                {
+                    (Some(key), Lit::Str(value)) => (key.to_string(), value.clone()),
+                    (Some(_), lit) => {
+                        return Err(syn::Error::new_spanned(lit, "Expected a string literal"))
+                    }
+                    (None, _) => return Err(syn::Error::new_spanned(&meta.path, "Unknown option")),
+                },
+                _ => {
+                    return Err(syn::Error::new_spanned(
+                        &arg,
+                        "Expected `name = \"...\"` or `description = \"...\"`",
+                    ))
+                }
+            };
+
+            match key.as_str() {
+                "name" if options.name.is_some() => {
+                    return Err(syn::Error::new_spanned(
+                        &arg,
+                        "`name` is set more than once",
+                    ))
+                }
+                "name" if value.value().is_empty() => {
+                    return Err(syn::Error::new_spanned(&value, "The name can't be empty"))
+                }
+                "name" => options.name = Some(value),
+                "description" if options.description.is_some() => {
+                    return Err(syn::Error::new_spanned(
+                        &arg,
+                        "`description` is set more than once",
+                    ))
+                }
+                "description" => options.description = Some(value.value()),
+                _ => {
+                    return Err(syn::Error::new_spanned(
+                        &arg,
+                        format!("Unknown option `{key}`, expected `name` or `description`"),
+                    ))
+                }
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+fn binding(args: AttributeArgs, input: &ItemFn) -> syn::Result<proc_macro2::TokenStream> {
+    let options = BindingOptions::parse(args)?;
+    check_signature(input)?;
+
     // Get the original function signature
     let fn_name = &input.sig.ident;
     let wrapped_fn_name = format_ident!("{}_impl", fn_name); // Create new name for wrapped function
     let inputs = &input.sig.inputs;
     let output = &input.sig.output;
     let body = &input.block;
+    let manifest = manifest(input, &options);
+
+    let wasm_bindgen = match &options.name {
+        Some(name) => quote!(#[wasm_bindgen(js_name = #name)]),
+        None => quote!(#[wasm_bindgen]),
+    };
+
+    let return_value = match output {
+        ReturnType::Default => quote!(Typed(return_value)),
+        ReturnType::Type(_, ty) => match result_ok_type(ty) {
+            Some(ok) if is_string(ok) => quote!(return_value),
+            Some(_) => quote!(return_value.map(Typed)),
+            None if is_string(ty) => quote!(return_value),
+            None => quote!(Typed(return_value)),
+        },
+    };
+
+    if let Some(borrowed) = takes_all_inputs(input) {
+        let input = if borrowed {
+            quote!(&input)
+        } else {
+            quote!(input)
+        };
 
-    // Generate the new function body
-    let gen = quote! {
         // Define the original function with the new name
+        // and the wrapper function with the original name
+        return Ok(quote! {
+            fn #wrapped_fn_name(#inputs) #output {
+                #body
+            }
+
+            #wasm_bindgen
+            pub fn #fn_name(ptr: *const u8, len: u32) -> *const u8 {
+                match get_input_strings(ptr, len) {
+                    Some(input) => {
+                        let return_value = #wrapped_fn_name(#input);
+                        pack_into_output(#return_value)
+                    }
+                    None => pack_into_output(Err::<String, _>(ValueError::new(
+                        "Couldn't decode the inputs",
+                    ))),
+                }
+            }
+
+            #manifest
+        });
+    }
+
+    // Every argument is converted from the positional input value with the same index
+    let mut arg_names = vec![];
+    let mut arg_types = vec![];
+    let mut arg_idents = vec![];
+    let mut call_args = vec![];
+    for (i, arg) in inputs.iter().enumerate() {
+        let FnArg::Typed(arg) = arg else {
+            continue;
+        };
+        let ident = format_ident!("arg{}", i);
+        let (ty, borrowed) = owned_type(&arg.ty);
+
+        arg_names.push(match &*arg.pat {
+            Pat::Ident(pat) => pat.ident.to_string(),
+            _ => i.to_string(),
+        });
+        arg_types.push(ty);
+        call_args.push(if borrowed {
+            quote!(&#ident)
+        } else {
+            quote!(#ident)
+        });
+        arg_idents.push(ident);
+    }
+
+    Ok(quote! {
         fn #wrapped_fn_name(#inputs) #output {
             #body
         }
 
-        // Define the wrapper function with the original name
-        #[wasm_bindgen]
+        #wasm_bindgen
         pub fn #fn_name(ptr: *const u8, len: u32) -> *const u8 {
-            let input_strings = get_input_strings(ptr, len).unwrap_or_default();
-            let return_value = (|input: Vec<String>| {
-                let return_value = #wrapped_fn_name(input);
-                return_value
-            })(input_strings);
-            pack_into_output(return_value)
+            let arguments = (|| -> Result<_, ValueError> {
+                #[allow(unused_mut, unused_variables)]
+                let mut values = get_input_values(ptr, len)
+                    .ok_or_else(|| ValueError::new("Couldn't decode the inputs"))?
+                    .into_iter();
+                #(let #arg_idents: #arg_types = take_argument(&mut values, #arg_names)?;)*
+                Ok((#(#arg_idents,)*))
+            })();
+
+            match arguments {
+                Ok((#(#arg_idents,)*)) => {
+                    let return_value = #wrapped_fn_name(#(#call_args),*);
+                    pack_into_output(#return_value)
+                }
+                Err(e) => pack_into_output(Err::<String, _>(e)),
+            }
+        }
+
+        #manifest
+    })
+}
+
+/// Rejects signatures that can't be called with values from the host
+fn check_signature(input: &ItemFn) -> syn::Result<()> {
+    let sig = &input.sig;
+    if let Some(asyncness) = &sig.asyncness {
+        return Err(syn::Error::new_spanned(
+            asyncness,
+            "`#[waterflow_binding]` functions can't be async",
+        ));
+    }
+    if !sig.generics.params.is_empty() || sig.generics.where_clause.is_some() {
+        return Err(syn::Error::new_spanned(
+            &sig.generics,
+            "`#[waterflow_binding]` functions can't be generic",
+        ));
+    }
+    if let Some(variadic) = &sig.variadic {
+        return Err(syn::Error::new_spanned(
+            variadic,
+            "`#[waterflow_binding]` functions can't be variadic",
+        ));
+    }
+
+    for arg in &sig.inputs {
+        match arg {
+            FnArg::Receiver(receiver) => {
+                return Err(syn::Error::new_spanned(
+                    receiver,
+                    "`#[waterflow_binding]` functions can't take `self`",
+                ))
+            }
+            FnArg::Typed(arg) => check_argument_type(&arg.ty)?,
+        }
+    }
+
+    match &sig.output {
+        ReturnType::Type(_, ty) if matches!(**ty, Type::ImplTrait(_)) => Err(
+            syn::Error::new_spanned(ty, "Return a concrete type instead of `impl Trait`"),
+        ),
+        _ => Ok(()),
+    }
+}
+
+fn check_argument_type(ty: &Type) -> syn::Result<()> {
+    match ty {
+        Type::Reference(reference) if reference.mutability.is_some() => {
+            Err(syn::Error::new_spanned(
+                ty,
+                "Mutable references aren't supported, take the argument by value",
+            ))
+        }
+        Type::Reference(reference) => match &*reference.elem {
+            Type::Reference(_) => Err(syn::Error::new_spanned(
+                ty,
+                "Nested references aren't supported",
+            )),
+            elem => check_argument_type(elem),
+        },
+        Type::ImplTrait(_) => Err(syn::Error::new_spanned(
+            ty,
+            "Use a concrete type instead of `impl Trait`",
+        )),
+        Type::BareFn(_) | Type::TraitObject(_) | Type::Never(_) | Type::Infer(_) => Err(
+            syn::Error::new_spanned(ty, "This type can't be read from an input value"),
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Type that the argument is decoded into, and whether the function borrows it
+fn owned_type(ty: &Type) -> (Type, bool) {
+    let Type::Reference(reference) = ty else {
+        return (ty.clone(), false);
+    };
+
+    let owned = match &*reference.elem {
+        Type::Path(path) if path.path.is_ident("str") => parse_quote!(String),
+        Type::Slice(slice) => {
+            let elem = &slice.elem;
+            parse_quote!(Vec<#elem>)
+        }
+        elem => elem.clone(),
+    };
+    (owned, true)
+}
+
+/// Embeds a JSON line describing the function into the `waterflow_manifest` custom section
+fn manifest(input: &ItemFn, options: &BindingOptions) -> proc_macro2::TokenStream {
+    let fn_name = &input.sig.ident;
+    let export_name = options
+        .name
+        .as_ref()
+        .map(LitStr::value)
+        .unwrap_or_else(|| fn_name.to_string());
+    let entry_name = format_ident!(
+        "__WATERFLOW_MANIFEST_{}",
+        fn_name.to_string().to_uppercase()
+    );
+    let section_name = format_ident!("{}_SECTION", entry_name);
+
+    let description = options.description.clone().unwrap_or_else(|| {
+        input
+            .attrs
+            .iter()
+            .filter_map(|attr| match attr.parse_meta() {
+                Ok(Meta::NameValue(meta)) if meta.path.is_ident("doc") => match meta.lit {
+                    Lit::Str(doc) => Some(doc.value().trim().to_string()),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    });
+
+    let params = input
+        .sig
+        .inputs
+        .iter()
+        .filter_map(|arg| match arg {
+            FnArg::Typed(arg) => {
+                let pat = &arg.pat;
+                Some(format!(
+                    "{{\"name\":{},\"type\":{}}}",
+                    json_string(&quote!(#pat).to_string()),
+                    json_string(&type_name(&arg.ty))
+                ))
+            }
+            FnArg::Receiver(_) => None,
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let returns = match &input.sig.output {
+        ReturnType::Default => "()".to_string(),
+        ReturnType::Type(_, ty) => type_name(ty),
+    };
+
+    let prefix = format!(
+        "{{\"name\":{},\"description\":{},\"params\":[{}],\"returns\":{},\"version\":\"",
+        json_string(&export_name),
+        json_string(&description),
+        params,
+        json_string(&returns),
+    );
+
+    quote! {
+        #[doc(hidden)]
+        const #entry_name: &[&str] = &[
+            concat!(#prefix, env!("CARGO_PKG_VERSION"), "\",\"abi\":"),
+            ABI_VERSION_STR,
+            "}\n",
+        ];
+
+        #[doc(hidden)]
+        #[used]
+        #[cfg_attr(target_arch = "wasm32", link_section = "waterflow_manifest")]
+        static #section_name: [u8; manifest_len(#entry_name)] = manifest_section(#entry_name);
+    }
+}
+
+fn type_name(ty: &Type) -> String {
+    quote!(#ty).to_string().replace(' ', "")
+}
+
+fn json_string(value: &str) -> String {
+    let mut json = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// Functions that take a single `Vec<String>` or `&[String]` receive all of the inputs as they are.
+///
+/// Returns whether the inputs are borrowed.
+fn takes_all_inputs(input: &ItemFn) -> Option<bool> {
+    let mut args = input.sig.inputs.iter();
+    let (Some(FnArg::Typed(arg)), None) = (args.next(), args.next()) else {
+        return None;
+    };
+
+    match &*arg.ty {
+        Type::Reference(reference) if reference.mutability.is_none() => match &*reference.elem {
+            Type::Slice(slice) if is_string(&slice.elem) => Some(true),
+            _ => None,
+        },
+        ty => match single_generic_argument(ty, "Vec") {
+            Some(inner) if is_string(inner) => Some(false),
+            _ => None,
+        },
+    }
+}
+
+fn is_string(ty: &Type) -> bool {
+    matches!(ty, Type::Path(path) if path.path.is_ident("String"))
+}
+
+fn result_ok_type(ty: &Type) -> Option<&Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    if segment.ident != "Result" {
+        return None;
+    }
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return None;
+    };
+    match args.args.first()? {
+        GenericArgument::Type(ty) => Some(ty),
+        _ => None,
+    }
+}
+
+fn single_generic_argument<'a>(ty: &'a Type, name: &str) -> Option<&'a Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    if segment.ident != name {
+        return None;
+    }
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return None;
+    };
+    match args.args.first()? {
+        GenericArgument::Type(ty) if args.args.len() == 1 => Some(ty),
+        _ => None,
+    }
+}
+
+/// Converts a struct with named fields into a `Value::Map`
+#[proc_macro_derive(IntoValue)]
+pub fn derive_into_value(item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as DeriveInput);
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let fields = match named_fields(&input) {
+        Ok(fields) => fields,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let keys = fields.iter().map(|f| f.to_string());
+
+    let gen = quote! {
+        impl #impl_generics IntoValue for #name #ty_generics #where_clause {
+            fn into_value(self) -> Value {
+                let mut entries = ::std::collections::BTreeMap::new();
+                #(entries.insert(#keys.to_string(), IntoValue::into_value(self.#fields));)*
+                Value::Map(entries)
+            }
         }
     };
 
     gen.into()
 }
+
+/// Reads a struct with named fields from a `Value::Map`
+#[proc_macro_derive(FromValue)]
+pub fn derive_from_value(item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as DeriveInput);
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let fields = match named_fields(&input) {
+        Ok(fields) => fields,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let keys = fields.iter().map(|f| f.to_string());
+
+    let gen = quote! {
+        impl #impl_generics FromValue for #name #ty_generics #where_clause {
+            fn from_value(value: Value) -> Result<Self, ValueError> {
+                let mut entries = match value {
+                    Value::Map(entries) => entries,
+                    value => return Err(ValueError::expected("a map", &value)),
+                };
+                Ok(#name {
+                    #(#fields: take_field(&mut entries, #keys)?,)*
+                })
+            }
+        }
+    };
+
+    gen.into()
+}
+
+fn named_fields(input: &DeriveInput) -> syn::Result<Vec<&syn::Ident>> {
+    match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => Ok(fields
+                .named
+                .iter()
+                .filter_map(|f| f.ident.as_ref())
+                .collect()),
+            fields => Err(syn::Error::new_spanned(
+                fields,
+                "Only structs with named fields can be converted to and from a Value",
+            )),
+        },
+        _ => Err(syn::Error::new_spanned(
+            &input.ident,
+            "Only structs with named fields can be converted to and from a Value",
+        )),
+    }
+}
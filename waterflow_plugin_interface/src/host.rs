@@ -0,0 +1,106 @@
+//! Functions that the waterflow host exposes to plugins.
+//!
+//! When compiled for anything other than `wasm32`, these are answered by the
+//! [`MockHost`](crate::testing::MockHost) if one is set up, and fall back to native equivalents
+//! otherwise, so that plugins can still be built and tested on the host machine.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogLevel {
+    Error = 0,
+    Warn = 1,
+    Info = 2,
+    Debug = 3,
+    Trace = 4,
+}
+
+#[cfg(target_arch = "wasm32")]
+mod ffi {
+    #[link(wasm_import_module = "waterflow")]
+    extern "C" {
+        pub fn log(level: u32, ptr: *const u8, len: u32);
+        pub fn get_env(ptr: *const u8, len: u32) -> i64;
+        pub fn get_secret(ptr: *const u8, len: u32) -> i64;
+        pub fn emit_progress(progress: f32);
+        pub fn http_fetch(ptr: *const u8, len: u32) -> i64;
+    }
+}
+
+/// Allocates a buffer that the host writes returned strings into.
+/// Ownership of the buffer is handed back to the plugin by [`take_host_string`].
+#[no_mangle]
+pub extern "C" fn waterflow_alloc(len: u32) -> *mut u8 {
+    let buffer = vec![0u8; len as usize].into_boxed_slice();
+    Box::into_raw(buffer) as *mut u8
+}
+
+/// Reclaims a string that the host returned as `(ptr << 32) | len`
+#[cfg(target_arch = "wasm32")]
+fn take_host_string(packed: i64) -> Option<String> {
+    if packed == -1 {
+        return None;
+    }
+
+    let packed = packed as u64;
+    let ptr = (packed >> 32) as u32 as *mut u8;
+    let len = (packed & 0xffff_ffff) as usize;
+    let buffer = unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)) };
+
+    Some(String::from_utf8_lossy(&buffer).to_string())
+}
+
+/// Logs a message through the host's `tracing` output, tagged with the id of the running job
+pub fn log(level: LogLevel, message: &str) {
+    #[cfg(target_arch = "wasm32")]
+    unsafe {
+        ffi::log(level as u32, message.as_ptr(), message.len() as u32)
+    };
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if crate::testing::mocked(|host| host.logs.push((level, message.to_string()))).is_none() {
+        eprintln!("[{level:?}] {message}");
+    }
+}
+
+/// Reads a configuration value from the job's environment
+pub fn get_env(key: &str) -> Option<String> {
+    #[cfg(target_arch = "wasm32")]
+    return take_host_string(unsafe { ffi::get_env(key.as_ptr(), key.len() as u32) });
+
+    #[cfg(not(target_arch = "wasm32"))]
+    return crate::testing::mocked(|host| host.env.get(key).cloned())
+        .unwrap_or_else(|| std::env::var(key).ok());
+}
+
+/// Reads a secret that was handed to the job
+pub fn get_secret(key: &str) -> Option<String> {
+    #[cfg(target_arch = "wasm32")]
+    return take_host_string(unsafe { ffi::get_secret(key.as_ptr(), key.len() as u32) });
+
+    #[cfg(not(target_arch = "wasm32"))]
+    return crate::testing::mocked(|host| host.secrets.get(key).cloned())
+        .unwrap_or_else(|| std::env::var(key).ok());
+}
+
+/// Reports how far along the plugin is, from `0.0` to `1.0`
+pub fn emit_progress(progress: f32) {
+    #[cfg(target_arch = "wasm32")]
+    unsafe {
+        ffi::emit_progress(progress)
+    };
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if crate::testing::mocked(|host| host.progress.push(progress)).is_none() {
+        eprintln!("[Progress] {:.0}%", progress * 100.0);
+    }
+}
+
+/// Fetches the body of `url` with a GET request.
+///
+/// Returns `None` if the request failed or the job wasn't given the `http` capability.
+pub fn http_fetch(url: &str) -> Option<String> {
+    #[cfg(target_arch = "wasm32")]
+    return take_host_string(unsafe { ffi::http_fetch(url.as_ptr(), url.len() as u32) });
+
+    #[cfg(not(target_arch = "wasm32"))]
+    return crate::testing::mocked(|host| host.responses.get(url).cloned()).flatten();
+}
@@ -1,5 +1,13 @@
+pub mod host;
+pub mod manifest;
 pub mod prelude;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod testing;
+pub mod value;
+use std::fmt::Display;
+
 use prelude::*;
+use value::{IntoValue, Value, WireValue};
 
 #[derive(ToBytes, FromBytes)]
 pub enum Communication {
@@ -7,6 +15,54 @@ pub enum Communication {
     Inputs(SizedVec<u32, SizedString<u32>>),
     #[enum_index(1)]
     Output(SizedString<u32>),
+    #[enum_index(2)]
+    Error(PluginError),
+    #[enum_index(3)]
+    Values(SizedVec<u32, WireValue>),
+    #[enum_index(4)]
+    Value(WireValue),
+}
+
+/// Failure reported by a plugin, which the host turns into a failed job
+#[derive(ToBytes, FromBytes)]
+pub struct PluginError {
+    pub message: SizedString<u32>,
+    pub code: u32,
+}
+
+/// Error code used when a plugin fails with an error that doesn't carry its own code
+pub const DEFAULT_ERROR_CODE: u32 = 1;
+
+/// Values that a `#[waterflow_binding]` function is allowed to return
+pub trait IntoCommunication {
+    fn into_communication(self) -> Communication;
+}
+
+impl IntoCommunication for String {
+    fn into_communication(self) -> Communication {
+        Communication::Output(self.into_sized())
+    }
+}
+
+/// Wraps a return value that's sent back as a [`Value`] instead of a plain string
+pub struct Typed<T>(pub T);
+
+impl<T: IntoValue> IntoCommunication for Typed<T> {
+    fn into_communication(self) -> Communication {
+        Communication::Value(self.0.into_value().into())
+    }
+}
+
+impl<T: IntoCommunication, E: Display> IntoCommunication for Result<T, E> {
+    fn into_communication(self) -> Communication {
+        match self {
+            Ok(output) => output.into_communication(),
+            Err(e) => Communication::Error(PluginError {
+                message: e.to_string().into_sized(),
+                code: DEFAULT_ERROR_CODE,
+            }),
+        }
+    }
 }
 
 #[allow(clippy::not_unsafe_ptr_arg_deref)]
@@ -28,8 +84,41 @@ pub fn get_input_strings(ptr: *const u8, len: u32) -> Option<Vec<String>> {
     )
 }
 
-pub fn pack_into_output(output: String) -> *const u8 {
-    let slice = Communication::Output(output.into_sized()).to_vec();
+/// Reads the inputs as values, no matter if the host sent them as strings or values
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub fn get_input_values(ptr: *const u8, len: u32) -> Option<Vec<Value>> {
+    let input = unsafe { std::slice::from_raw_parts(ptr, len as usize) };
+
+    match Communication::from_bytes(input).ok()? {
+        Communication::Inputs(input_strings) => Some(
+            Vec::from(input_strings)
+                .into_iter()
+                .map(|s| Value::String(s.into()))
+                .collect(),
+        ),
+        Communication::Values(values) => {
+            Some(Vec::from(values).into_iter().map(Value::from).collect())
+        }
+        _ => None,
+    }
+}
+
+/// Packs the output for the host.
+///
+/// Since ABI version 2, the output is prefixed with its length as a little endian `u32`.
+pub fn pack_into_output(output: impl IntoCommunication) -> *const u8 {
+    let output = output.into_communication().to_vec();
+
+    #[cfg(feature = "abi_v1")]
+    let slice = output;
+
+    #[cfg(not(feature = "abi_v1"))]
+    let slice = {
+        let mut slice = (output.len() as u32).to_le_bytes().to_vec();
+        slice.extend(output);
+        slice
+    };
+
     // Allocate memory in WASM and return a pointer to the reversed data
     let boxed_slice = slice.into_boxed_slice();
     let ptr = boxed_slice.as_ptr();
@@ -0,0 +1,59 @@
+//! Manifest that `#[waterflow_binding]` embeds into the plugin.
+//!
+//! Every bound function adds one JSON line to the `waterflow_manifest` custom section.
+//! The linker concatenates the sections of all functions, so the host can read them back line by line.
+
+/// Version of the byte layout that the plugin uses to talk to the host
+///
+/// * `1`: The host writes the input at the start of the memory and the output isn't length prefixed.
+/// * `2`: The host allocates the input through `waterflow_alloc` and the output is length prefixed.
+#[cfg(not(feature = "abi_v1"))]
+pub const ABI_VERSION: u32 = 2;
+#[cfg(feature = "abi_v1")]
+pub const ABI_VERSION: u32 = 1;
+
+/// [`ABI_VERSION`] as it's written into the manifest
+#[cfg(not(feature = "abi_v1"))]
+pub const ABI_VERSION_STR: &str = "2";
+#[cfg(feature = "abi_v1")]
+pub const ABI_VERSION_STR: &str = "1";
+
+/// Lets the host know which byte layout the plugin uses.
+/// Plugins that don't export this are treated as version `1`.
+#[cfg(not(feature = "abi_v1"))]
+#[no_mangle]
+pub extern "C" fn waterflow_abi_version() -> u32 {
+    ABI_VERSION
+}
+
+/// Name of the custom section that holds the manifest
+pub const MANIFEST_SECTION: &str = "waterflow_manifest";
+
+/// Combined length of the parts of a manifest entry
+pub const fn manifest_len(parts: &[&str]) -> usize {
+    let mut len = 0;
+    let mut i = 0;
+    while i < parts.len() {
+        len += parts[i].len();
+        i += 1;
+    }
+    len
+}
+
+/// Copies the parts of a manifest entry into a fixed size array, so that it can be placed into a link section
+pub const fn manifest_section<const N: usize>(parts: &[&str]) -> [u8; N] {
+    let mut section = [0; N];
+    let mut offset = 0;
+    let mut i = 0;
+    while i < parts.len() {
+        let bytes = parts[i].as_bytes();
+        let mut j = 0;
+        while j < bytes.len() {
+            section[offset] = bytes[j];
+            offset += 1;
+            j += 1;
+        }
+        i += 1;
+    }
+    section
+}
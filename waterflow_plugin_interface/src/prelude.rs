@@ -1,4 +1,9 @@
-pub use crate::{get_input_strings, pack_into_output, Communication};
+pub use crate::host::{self, LogLevel};
+pub use crate::manifest::{manifest_len, manifest_section, ABI_VERSION, ABI_VERSION_STR};
+pub use crate::value::{take_argument, take_field, FromValue, IntoValue, Value, ValueError};
+pub use crate::{
+    get_input_strings, get_input_values, pack_into_output, Communication, IntoCommunication, Typed,
+};
 pub use bypar::{
     prelude::{IntoSizedString as _, SizedString, SizedVec},
     FromBytes as _, ToBytes as _,
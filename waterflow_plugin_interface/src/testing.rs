@@ -0,0 +1,209 @@
+//! Calls `#[waterflow_binding]` functions natively, through the same encoding that the host uses.
+//!
+//! ```ignore
+//! let output = testing::call(reverse_join, &["Hello", "World!"]);
+//! assert_eq!(output, Ok(Value::String("World!, Hello".to_string())));
+//! ```
+//!
+//! Host functions are answered by a [`MockHost`], which can be set up with [`with_host`].
+//! Calling functions needs the length prefixed output, so it isn't available with `abi_v1`.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use crate::host::LogLevel;
+#[cfg(not(feature = "abi_v1"))]
+use {
+    crate::prelude::*, crate::value::WireValue, crate::DEFAULT_ERROR_CODE,
+    bypar::prelude::IntoSizedVec as _,
+};
+
+/// Function generated by `#[waterflow_binding]`
+pub type PluginFunction = fn(*const u8, u32) -> *const u8;
+
+/// Error that a plugin function reported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginFailure {
+    pub message: String,
+    pub code: u32,
+}
+
+impl Display for PluginFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (code {})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for PluginFailure {}
+
+/// Answers the host functions while a plugin is tested natively
+#[derive(Debug, Clone, Default)]
+pub struct MockHost {
+    pub env: BTreeMap<String, String>,
+    pub secrets: BTreeMap<String, String>,
+    /// Bodies returned by `http_fetch`, keyed by URL
+    pub responses: BTreeMap<String, String>,
+    /// Messages that the plugin logged
+    pub logs: Vec<(LogLevel, String)>,
+    /// Progress that the plugin reported
+    pub progress: Vec<f32>,
+}
+
+impl MockHost {
+    pub fn with_env(mut self, key: &str, value: &str) -> Self {
+        self.env.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn with_secret(mut self, key: &str, value: &str) -> Self {
+        self.secrets.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn with_response(mut self, url: &str, body: &str) -> Self {
+        self.responses.insert(url.to_string(), body.to_string());
+        self
+    }
+}
+
+thread_local! {
+    static HOST: RefCell<Option<MockHost>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` with `host` answering the host functions.
+///
+/// Returns the host afterwards, so that logs and progress can be checked.
+pub fn with_host<T>(host: MockHost, f: impl FnOnce() -> T) -> (T, MockHost) {
+    let previous = HOST.with(|cell| cell.replace(Some(host)));
+    let output = f();
+    let host = HOST.with(|cell| cell.replace(previous));
+    (output, host.unwrap_or_default())
+}
+
+/// Calls `f` with the mocked host, if there is one
+pub(crate) fn mocked<T>(f: impl FnOnce(&mut MockHost) -> T) -> Option<T> {
+    HOST.with(|cell| cell.borrow_mut().as_mut().map(f))
+}
+
+/// Calls `function` with string inputs, like a `JobType::Wasm` job does
+#[cfg(not(feature = "abi_v1"))]
+pub fn call(function: PluginFunction, inputs: &[&str]) -> Result<Value, PluginFailure> {
+    let inputs = inputs
+        .iter()
+        .map(|input| input.to_string().into_sized())
+        .collect::<Vec<SizedString<u32>>>();
+
+    call_encoded(
+        function,
+        Communication::Inputs(inputs.into_sized()).to_vec(),
+    )
+}
+
+/// Calls `function` with typed inputs, like `wasm::run_wasm_values` does
+#[cfg(not(feature = "abi_v1"))]
+pub fn call_values(function: PluginFunction, inputs: Vec<Value>) -> Result<Value, PluginFailure> {
+    let inputs = inputs.into_iter().map(WireValue::from).collect::<Vec<_>>();
+
+    call_encoded(
+        function,
+        Communication::Values(inputs.into_sized()).to_vec(),
+    )
+}
+
+#[cfg(not(feature = "abi_v1"))]
+fn call_encoded(function: PluginFunction, input: Vec<u8>) -> Result<Value, PluginFailure> {
+    let output = take_output(function(input.as_ptr(), input.len() as u32));
+
+    match Communication::from_bytes(&output) {
+        Ok(Communication::Output(output)) => Ok(Value::String(output.into())),
+        Ok(Communication::Value(value)) => Ok(value.into()),
+        Ok(Communication::Error(e)) => Err(PluginFailure {
+            message: e.message.into(),
+            code: e.code,
+        }),
+        Ok(_) => Err(PluginFailure {
+            message: "The plugin returned inputs instead of an output".to_string(),
+            code: DEFAULT_ERROR_CODE,
+        }),
+        Err(e) => Err(PluginFailure {
+            message: format!("Couldn't decode the output: {e:?}"),
+            code: DEFAULT_ERROR_CODE,
+        }),
+    }
+}
+
+/// Reclaims the length prefixed buffer that `pack_into_output` handed out
+#[cfg(not(feature = "abi_v1"))]
+fn take_output(ptr: *const u8) -> Vec<u8> {
+    let len = u32::from_le_bytes(unsafe { *(ptr as *const [u8; 4]) }) as usize;
+    let buffer =
+        unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr as *mut u8, len + 4)) };
+    buffer[4..].to_vec()
+}
+
+#[test]
+fn test_mock_host() {
+    let host = MockHost::default()
+        .with_env("GREETING", "Hi")
+        .with_secret("TOKEN", "hunter2")
+        .with_response("http://example.com", "body");
+
+    let (output, host) = with_host(host, || {
+        crate::host::log(LogLevel::Info, "Hello");
+        crate::host::emit_progress(0.5);
+        (
+            crate::host::get_env("GREETING"),
+            crate::host::get_secret("TOKEN"),
+            crate::host::http_fetch("http://example.com"),
+            crate::host::http_fetch("http://example.org"),
+        )
+    });
+
+    assert_eq!(
+        output,
+        (
+            Some("Hi".to_string()),
+            Some("hunter2".to_string()),
+            Some("body".to_string()),
+            None
+        )
+    );
+    assert_eq!(host.logs, [(LogLevel::Info, "Hello".to_string())]);
+    assert_eq!(host.progress, [0.5]);
+}
+
+#[test]
+#[cfg(not(feature = "abi_v1"))]
+fn test_call_round_trip() {
+    fn join(ptr: *const u8, len: u32) -> *const u8 {
+        let inputs = get_input_strings(ptr, len).unwrap();
+        pack_into_output(inputs.join(", "))
+    }
+
+    fn fail(_ptr: *const u8, _len: u32) -> *const u8 {
+        pack_into_output(Err::<String, _>("Nope"))
+    }
+
+    fn double(ptr: *const u8, len: u32) -> *const u8 {
+        let mut values = get_input_values(ptr, len).unwrap().into_iter();
+        let n: i64 = take_argument(&mut values, "n").unwrap();
+        pack_into_output(Typed(n * 2))
+    }
+
+    assert_eq!(
+        call(join, &["Hello", "World!"]),
+        Ok(Value::String("Hello, World!".to_string()))
+    );
+    assert_eq!(
+        call(fail, &[]),
+        Err(PluginFailure {
+            message: "Nope".to_string(),
+            code: DEFAULT_ERROR_CODE
+        })
+    );
+    assert_eq!(
+        call_values(double, vec![Value::Int(21)]),
+        Ok(Value::Int(42))
+    );
+}
@@ -0,0 +1,541 @@
+//! Self-describing values that plugins and the host exchange, instead of plain strings.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Write as _};
+
+use crate::prelude::*;
+use bypar::prelude::IntoSizedVec as _;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Value {
+    #[default]
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    List(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+}
+
+impl Value {
+    fn kind(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Bool(_) => "a boolean",
+            Value::Int(_) => "an integer",
+            Value::Float(_) => "a float",
+            Value::String(_) => "a string",
+            Value::Bytes(_) => "bytes",
+            Value::List(_) => "a list",
+            Value::Map(_) => "a map",
+        }
+    }
+}
+
+/// Strings are displayed as they are, everything else is displayed as JSON
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::String(s) => f.write_str(s),
+            _ => {
+                let mut json = String::new();
+                write_json(self, &mut json)?;
+                f.write_str(&json)
+            }
+        }
+    }
+}
+
+fn write_json(value: &Value, out: &mut String) -> std::fmt::Result {
+    match value {
+        Value::Null => out.write_str("null"),
+        Value::Bool(b) => write!(out, "{b}"),
+        Value::Int(i) => write!(out, "{i}"),
+        Value::Float(x) if x.is_finite() => write!(out, "{x}"),
+        Value::Float(_) => out.write_str("null"),
+        Value::String(s) => write_json_string(s, out),
+        Value::Bytes(bytes) => write!(out, "{bytes:?}"),
+        Value::List(values) => {
+            out.write_char('[')?;
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    out.write_char(',')?;
+                }
+                write_json(value, out)?;
+            }
+            out.write_char(']')
+        }
+        Value::Map(entries) => {
+            out.write_char('{')?;
+            for (i, (key, value)) in entries.iter().enumerate() {
+                if i > 0 {
+                    out.write_char(',')?;
+                }
+                write_json_string(key, out)?;
+                out.write_char(':')?;
+                write_json(value, out)?;
+            }
+            out.write_char('}')
+        }
+    }
+}
+
+fn write_json_string(s: &str, out: &mut String) -> std::fmt::Result {
+    out.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => out.write_str("\\\"")?,
+            '\\' => out.write_str("\\\\")?,
+            '\n' => out.write_str("\\n")?,
+            '\r' => out.write_str("\\r")?,
+            '\t' => out.write_str("\\t")?,
+            c if c < ' ' => write!(out, "\\u{:04x}", c as u32)?,
+            c => out.write_char(c)?,
+        }
+    }
+    out.write_char('"')
+}
+
+/// Encoding of a [`Value`] inside of [`Communication`]
+#[derive(ToBytes, FromBytes)]
+pub enum WireValue {
+    #[enum_index(0)]
+    Null(u8),
+    #[enum_index(1)]
+    Bool(u8),
+    /// Two's complement of the integer
+    #[enum_index(2)]
+    Int(u64),
+    /// Bits of the IEEE 754 double
+    #[enum_index(3)]
+    Float(u64),
+    #[enum_index(4)]
+    String(SizedString<u32>),
+    #[enum_index(5)]
+    Bytes(SizedVec<u32, u8>),
+    #[enum_index(6)]
+    List(SizedVec<u32, WireValue>),
+    #[enum_index(7)]
+    Map(SizedVec<u32, WireEntry>),
+}
+
+#[derive(ToBytes, FromBytes)]
+pub struct WireEntry {
+    pub key: SizedString<u32>,
+    pub value: WireValue,
+}
+
+impl From<Value> for WireValue {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Null => WireValue::Null(0),
+            Value::Bool(b) => WireValue::Bool(b as u8),
+            Value::Int(i) => WireValue::Int(i as u64),
+            Value::Float(x) => WireValue::Float(x.to_bits()),
+            Value::String(s) => WireValue::String(s.into_sized()),
+            Value::Bytes(bytes) => WireValue::Bytes(bytes.into_sized()),
+            Value::List(values) => WireValue::List(
+                values
+                    .into_iter()
+                    .map(WireValue::from)
+                    .collect::<Vec<_>>()
+                    .into_sized(),
+            ),
+            Value::Map(entries) => WireValue::Map(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| WireEntry {
+                        key: key.into_sized(),
+                        value: value.into(),
+                    })
+                    .collect::<Vec<_>>()
+                    .into_sized(),
+            ),
+        }
+    }
+}
+
+impl From<WireValue> for Value {
+    fn from(value: WireValue) -> Self {
+        match value {
+            WireValue::Null(_) => Value::Null,
+            WireValue::Bool(b) => Value::Bool(b != 0),
+            WireValue::Int(i) => Value::Int(i as i64),
+            WireValue::Float(bits) => Value::Float(f64::from_bits(bits)),
+            WireValue::String(s) => Value::String(s.into()),
+            WireValue::Bytes(bytes) => Value::Bytes(bytes.into()),
+            WireValue::List(values) => Value::List(
+                Vec::from(values)
+                    .into_iter()
+                    .map(Value::from)
+                    .collect::<Vec<_>>(),
+            ),
+            WireValue::Map(entries) => Value::Map(
+                Vec::from(entries)
+                    .into_iter()
+                    .map(|entry| (String::from(entry.key), Value::from(entry.value)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Failure to convert a [`Value`] into the type that a plugin expects
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValueError {
+    pub message: String,
+}
+
+impl ValueError {
+    pub fn new(message: &str) -> Self {
+        ValueError {
+            message: message.to_string(),
+        }
+    }
+
+    pub fn expected(expected: &str, value: &Value) -> Self {
+        ValueError {
+            message: format!("expected {expected}, got {}", value.kind()),
+        }
+    }
+
+    /// Prefixes the error with the name of the argument or field that couldn't be converted
+    pub fn in_field(self, field: &str) -> Self {
+        ValueError {
+            message: format!("`{field}`: {}", self.message),
+        }
+    }
+}
+
+impl Display for ValueError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ValueError {}
+
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+
+    /// Converts a `Vec<Self>`, which lets `Vec<u8>` turn into [`Value::Bytes`] instead of a list
+    #[doc(hidden)]
+    fn vec_into_value(values: Vec<Self>) -> Value
+    where
+        Self: Sized,
+    {
+        Value::List(values.into_iter().map(IntoValue::into_value).collect())
+    }
+}
+
+pub trait FromValue: Sized {
+    fn from_value(value: Value) -> Result<Self, ValueError>;
+
+    /// Converts into a `Vec<Self>`, which lets `Vec<u8>` be read from [`Value::Bytes`]
+    #[doc(hidden)]
+    fn vec_from_value(value: Value) -> Result<Vec<Self>, ValueError> {
+        match value {
+            Value::List(values) => values
+                .into_iter()
+                .enumerate()
+                .map(|(i, value)| Self::from_value(value).map_err(|e| e.in_field(&i.to_string())))
+                .collect(),
+            value => Err(ValueError::expected("a list", &value)),
+        }
+    }
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(value: Value) -> Result<Self, ValueError> {
+        Ok(value)
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> Value {
+        Value::Null
+    }
+}
+
+impl FromValue for () {
+    fn from_value(_: Value) -> Result<Self, ValueError> {
+        Ok(())
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> Value {
+        Value::Bool(self)
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: Value) -> Result<Self, ValueError> {
+        match value {
+            Value::Bool(b) => Ok(b),
+            Value::String(ref s) => s
+                .trim()
+                .parse()
+                .map_err(|_| ValueError::expected("a boolean", &value)),
+            value => Err(ValueError::expected("a boolean", &value)),
+        }
+    }
+}
+
+macro_rules! impl_int_value {
+    ($($ty:ty),*) => {$(
+        impl IntoValue for $ty {
+            fn into_value(self) -> Value {
+                Value::Int(self as i64)
+            }
+        }
+
+        impl FromValue for $ty {
+            fn from_value(value: Value) -> Result<Self, ValueError> {
+                let out_of_range = || ValueError::new(concat!("integer doesn't fit into ", stringify!($ty)));
+                match value {
+                    Value::Int(i) => <$ty>::try_from(i).map_err(|_| out_of_range()),
+                    Value::String(ref s) => s
+                        .trim()
+                        .parse()
+                        .map_err(|_| ValueError::expected("an integer", &value)),
+                    value => Err(ValueError::expected("an integer", &value)),
+                }
+            }
+        }
+    )*};
+}
+
+impl_int_value!(i8, i16, i32, i64, u16, u32, usize, isize);
+
+impl IntoValue for u8 {
+    fn into_value(self) -> Value {
+        Value::Int(self as i64)
+    }
+
+    fn vec_into_value(values: Vec<Self>) -> Value {
+        Value::Bytes(values)
+    }
+}
+
+impl FromValue for u8 {
+    fn from_value(value: Value) -> Result<Self, ValueError> {
+        match value {
+            Value::Int(i) => {
+                u8::try_from(i).map_err(|_| ValueError::new("integer doesn't fit into u8"))
+            }
+            Value::String(ref s) => s
+                .trim()
+                .parse()
+                .map_err(|_| ValueError::expected("an integer", &value)),
+            value => Err(ValueError::expected("an integer", &value)),
+        }
+    }
+
+    fn vec_from_value(value: Value) -> Result<Vec<Self>, ValueError> {
+        match value {
+            Value::Bytes(bytes) => Ok(bytes),
+            Value::String(s) => Ok(s.into_bytes()),
+            Value::List(values) => values.into_iter().map(u8::from_value).collect(),
+            value => Err(ValueError::expected("bytes", &value)),
+        }
+    }
+}
+
+/// Integers above `i64::MAX` keep their bits, the same way as they do on the wire
+impl IntoValue for u64 {
+    fn into_value(self) -> Value {
+        Value::Int(self as i64)
+    }
+}
+
+impl FromValue for u64 {
+    fn from_value(value: Value) -> Result<Self, ValueError> {
+        match value {
+            Value::Int(i) => Ok(i as u64),
+            Value::String(ref s) => s
+                .trim()
+                .parse()
+                .map_err(|_| ValueError::expected("an integer", &value)),
+            value => Err(ValueError::expected("an integer", &value)),
+        }
+    }
+}
+
+macro_rules! impl_float_value {
+    ($($ty:ty),*) => {$(
+        impl IntoValue for $ty {
+            fn into_value(self) -> Value {
+                Value::Float(self as f64)
+            }
+        }
+
+        impl FromValue for $ty {
+            fn from_value(value: Value) -> Result<Self, ValueError> {
+                match value {
+                    Value::Float(x) => Ok(x as $ty),
+                    Value::Int(i) => Ok(i as $ty),
+                    Value::String(ref s) => s
+                        .trim()
+                        .parse()
+                        .map_err(|_| ValueError::expected("a float", &value)),
+                    value => Err(ValueError::expected("a float", &value)),
+                }
+            }
+        }
+    )*};
+}
+
+impl_float_value!(f32, f64);
+
+impl IntoValue for String {
+    fn into_value(self) -> Value {
+        Value::String(self)
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value {
+        Value::String(self.to_string())
+    }
+}
+
+/// Values that aren't strings are converted into their JSON representation
+impl FromValue for String {
+    fn from_value(value: Value) -> Result<Self, ValueError> {
+        match value {
+            Value::String(s) => Ok(s),
+            Value::Bytes(bytes) => {
+                String::from_utf8(bytes).map_err(|_| ValueError::new("bytes aren't valid UTF-8"))
+            }
+            value => Ok(value.to_string()),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value {
+        self.map(IntoValue::into_value).unwrap_or_default()
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: Value) -> Result<Self, ValueError> {
+        match value {
+            Value::Null => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value {
+        T::vec_into_value(self)
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: Value) -> Result<Self, ValueError> {
+        T::vec_from_value(value)
+    }
+}
+
+impl<T: IntoValue> IntoValue for BTreeMap<String, T> {
+    fn into_value(self) -> Value {
+        Value::Map(
+            self.into_iter()
+                .map(|(key, value)| (key, value.into_value()))
+                .collect(),
+        )
+    }
+}
+
+impl<T: FromValue> FromValue for BTreeMap<String, T> {
+    fn from_value(value: Value) -> Result<Self, ValueError> {
+        match value {
+            Value::Map(entries) => entries
+                .into_iter()
+                .map(|(key, value)| {
+                    let value = T::from_value(value).map_err(|e| e.in_field(&key))?;
+                    Ok((key, value))
+                })
+                .collect(),
+            value => Err(ValueError::expected("a map", &value)),
+        }
+    }
+}
+
+/// Takes the next positional argument and converts it, used by `#[waterflow_binding]`
+#[doc(hidden)]
+pub fn take_argument<T: FromValue>(
+    values: &mut impl Iterator<Item = Value>,
+    name: &str,
+) -> Result<T, ValueError> {
+    T::from_value(values.next().unwrap_or_default()).map_err(|e| e.in_field(name))
+}
+
+/// Takes a field out of a map and converts it, used by `#[derive(FromValue)]`
+#[doc(hidden)]
+pub fn take_field<T: FromValue>(
+    entries: &mut BTreeMap<String, Value>,
+    name: &str,
+) -> Result<T, ValueError> {
+    T::from_value(entries.remove(name).unwrap_or_default()).map_err(|e| e.in_field(name))
+}
+
+#[test]
+fn test_value_conversions() {
+    assert_eq!(u32::from_value(Value::Int(42)), Ok(42));
+    assert_eq!(u32::from_value(Value::String("42".to_string())), Ok(42));
+    assert!(u8::from_value(Value::Int(300)).is_err());
+
+    assert_eq!(vec![1u8, 2, 3].into_value(), Value::Bytes(vec![1, 2, 3]));
+    assert_eq!(
+        Vec::<u8>::from_value(Value::Bytes(vec![1, 2, 3])),
+        Ok(vec![1, 2, 3])
+    );
+    assert_eq!(
+        vec![1u32, 2].into_value(),
+        Value::List(vec![Value::Int(1), Value::Int(2)])
+    );
+
+    assert_eq!(Option::<u32>::from_value(Value::Null), Ok(None));
+    assert_eq!(
+        u32::from_value(Value::Bool(true)).unwrap_err().to_string(),
+        "expected an integer, got a boolean"
+    );
+}
+
+#[test]
+fn test_value_display() {
+    let mut map = BTreeMap::new();
+    map.insert("name".to_string(), Value::String("waterflow".to_string()));
+    map.insert(
+        "tags".to_string(),
+        Value::List(vec![Value::Int(1), Value::Bool(false), Value::Null]),
+    );
+
+    assert_eq!(Value::String("plain".to_string()).to_string(), "plain");
+    assert_eq!(
+        Value::Map(map).to_string(),
+        r#"{"name":"waterflow","tags":[1,false,null]}"#
+    );
+
+    let mut map = BTreeMap::new();
+    map.insert(
+        "say \"hi\"".to_string(),
+        Value::String("tab\t\u{1b}[0m é\\".to_string()),
+    );
+    assert_eq!(
+        Value::Map(map).to_string(),
+        r#"{"say \"hi\"":"tab\t\u001b[0m é\\"}"#
+    );
+}